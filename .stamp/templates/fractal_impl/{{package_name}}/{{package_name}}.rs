@@ -1,4 +1,4 @@
-use impl_util::{compute_cells_rmp, config_helper::OptionSetter};
+use impl_util::{compute_cells_rmp, config_helper::OptionSetter, host_log};
 use num::complex::Complex64;
 use num::Zero;
 use serde::{Deserialize, Serialize};
@@ -39,7 +39,7 @@ impl RFractalFunc for {{to_class_case package_name}}CellFunc {
         Tuple2(self.width, self.height)
     }
 
-    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RChunk {
+    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RResult<RChunk, RString> {
         compute_cells_rmp(positions, |pos| self.compute_cell_impl(pos))
     }
 
@@ -69,6 +69,7 @@ impl RFractalFunc for {{to_class_case package_name}}CellFunc {
 pub fn get_fractal_lib_ref() -> FractalLib_Ref {
     FractalLib {
         default_fractal_func_for_size,
+        set_host_services: host_log::set_host_services,
     }
     .leak_into_prefix()
 }
@@ -57,9 +57,10 @@ fn fs_main([[location(0)]] pos: vec2<f32>) -> FragmentOutput {
     if (tex_coord.x < 0.0 || tex_coord.x > 1.0 || tex_coord.y < 0.0 || tex_coord.y > 1.0) {
         out.with_alpha = vec4<f32>(0.0, 0.0, 0.0, 0.0);
         out.with_checkerboard = check;
-    } else if (sample.a > 0.5) {
+    } else if (sample.a > 0.0) {
+        // translucent colors are composited over the checkerboard
         out.with_alpha = sample;
-        out.with_checkerboard = sample;
+        out.with_checkerboard = vec4<f32>(mix(check.rgb, sample.rgb, sample.a), 1.0);
     } else if (bg_color.a > 0.5) {
         out.with_alpha = bg_color;
         out.with_checkerboard = mix(bg_color, check, 0.1);
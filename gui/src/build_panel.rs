@@ -0,0 +1,125 @@
+use egui::{Color32, Ui};
+use worker::{
+    config_manager::{ConfigManager, FuncConfig},
+    fractal_worker2::FractalWorker,
+    rebuild::{BuildConfig, BuildStatus},
+};
+
+/// the edited build settings of one library, applied to the config and the worker together
+#[derive(Debug, Default)]
+struct BuildSettings {
+    command: String,
+    /// one path per line
+    watch_paths: String,
+}
+
+impl BuildSettings {
+    fn from_config(config: &FuncConfig) -> Self {
+        Self {
+            command: config.build_command.clone().unwrap_or_default(),
+            watch_paths: config.watch_paths.join("\n"),
+        }
+    }
+
+    fn apply_to(&self, config: &mut FuncConfig) {
+        let command = self.command.trim();
+        config.build_command = if command.is_empty() {
+            None
+        } else {
+            Some(command.to_owned())
+        };
+        config.watch_paths = self
+            .watch_paths
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+    }
+
+    fn ui(&mut self, ui: &mut Ui, name: &str) {
+        ui.label(format!("{} build command", name));
+        ui.text_edit_singleline(&mut self.command);
+        ui.end_row();
+        ui.label(format!("{} watched paths", name));
+        ui.add(egui::TextEdit::multiline(&mut self.watch_paths).desired_rows(2));
+        ui.end_row();
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildPanel {
+    fractal: BuildSettings,
+    color: BuildSettings,
+}
+
+impl BuildPanel {
+    pub fn new(config_manager: &ConfigManager) -> Self {
+        Self {
+            fractal: BuildSettings::from_config(&config_manager.config().fractal_config),
+            color: BuildSettings::from_config(&config_manager.config().color_config),
+        }
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        config_manager: &mut ConfigManager,
+        worker: &mut FractalWorker,
+    ) {
+        egui::Grid::new("build settings grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                self.fractal.ui(ui, "fractal");
+                self.color.ui(ui, "color");
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("apply").clicked() {
+                config_manager.update(|config| {
+                    self.fractal.apply_to(&mut config.fractal_config);
+                    self.color.apply_to(&mut config.color_config);
+                });
+                let config = config_manager.config();
+                worker.set_build_configs(
+                    BuildConfig::from(&config.fractal_config),
+                    BuildConfig::from(&config.color_config),
+                );
+            }
+            let mut watch = worker.is_watching_sources();
+            if ui.checkbox(&mut watch, "rebuild on change").changed() {
+                worker.set_watch_sources(watch);
+            }
+            if ui.button("rebuild + reload").clicked() {
+                worker.rebuild_and_reload();
+            }
+        });
+
+        status_label(ui, &worker.get_build_status());
+        let output = worker.get_build_output();
+        if !output.is_empty() {
+            egui::ScrollArea::vertical()
+                .id_source("build output")
+                .max_height(200.0)
+                .stick_to_bottom()
+                .show(ui, |ui| {
+                    ui.monospace(output);
+                });
+        }
+    }
+}
+
+fn status_label(ui: &mut Ui, status: &BuildStatus) {
+    match status {
+        BuildStatus::Idle => {}
+        BuildStatus::Building => {
+            ui.label("building...");
+        }
+        BuildStatus::Succeeded => {
+            ui.colored_label(Color32::GREEN, "build succeeded");
+        }
+        BuildStatus::Failed(msg) => {
+            ui.colored_label(Color32::RED, format!("build failed: {}", msg));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
     time::Instant,
 };
 
@@ -9,9 +10,23 @@ use egui::{CtxRef, Ui};
 use itertools::Itertools;
 use native_dialog::FileDialog;
 use ordered_float::OrderedFloat;
-use worker::{config_manager::ConfigManager, fractal_worker2::FractalWorker};
+use worker::{
+    chunk_order::ChunkOrder,
+    chunk_sizing::MAX_CHUNK_SIZE,
+    color_buffer::ToneMapOperator,
+    config_manager::ConfigManager,
+    fractal_worker2::{FractalWorker, WorkerState},
+    render_pool::RenderPriority,
+};
 
-use crate::pan_zoom_debounce::PanZoomDebounce;
+use crate::{
+    build_panel::BuildPanel,
+    log_panel::{self, LogLines, LogPanel},
+    pan_zoom_debounce::PanZoomDebounce,
+    plugin_picker::PluginPicker,
+    remote_panel::RemotePanel,
+    watchdog_panel,
+};
 
 const FRAME_TIMES_COUNT: usize = 60;
 
@@ -26,10 +41,14 @@ pub struct GuiState {
     //
     fractal_options: OptionsGrid,
     color_options: OptionsGrid,
+    log_panel: LogPanel,
+    build_panel: BuildPanel,
+    plugin_picker: PluginPicker,
+    remote_panel: RemotePanel,
 }
 
-impl Default for GuiState {
-    fn default() -> Self {
+impl GuiState {
+    pub fn new(log_lines: LogLines, config_manager: &ConfigManager, plugin_dir: &Path) -> Self {
         Self {
             // edited_fractal_options: Default::default(),
             match_window_size: true,
@@ -38,11 +57,13 @@ impl Default for GuiState {
             last_frame_time: Instant::now(),
             fractal_options: Default::default(),
             color_options: Default::default(),
+            log_panel: LogPanel::new(log_lines),
+            build_panel: BuildPanel::new(config_manager),
+            plugin_picker: PluginPicker::new(plugin_dir),
+            remote_panel: RemotePanel::new(config_manager),
         }
     }
-}
 
-impl GuiState {
     fn update_frame_time(&mut self) {
         if self.frame_times.len() >= FRAME_TIMES_COUNT {
             self.frame_times.pop_front();
@@ -71,8 +92,6 @@ impl GuiState {
         frame: &[u8],
         worker: &mut FractalWorker,
         pan_zoom: &PanZoomDebounce,
-        color_lib_path: &str,
-        fractal_lib_path: &str,
     ) {
         self.update_frame_time();
 
@@ -84,35 +103,67 @@ impl GuiState {
 
                 ui.separator();
 
-                ui.label("render progress");
-                ui.add(
-                    egui::ProgressBar::new(worker.get_progress())
-                        .show_percentage()
-                        .animate(true),
-                );
+                match worker.get_state() {
+                    WorkerState::Refining { .. } => ui.label("refining edges"),
+                    _ => ui.label("render progress"),
+                };
+                let progress_bar = egui::ProgressBar::new(worker.get_progress()).animate(true);
+                let eta = worker.get_render_progress().eta();
+                ui.add(match (worker.get_state(), eta) {
+                    (WorkerState::Working { .. }, Some(eta)) => progress_bar.text(format!(
+                        "{:.0}%, {:.1} s left",
+                        worker.get_progress() * 100.0,
+                        eta.as_secs_f32()
+                    )),
+                    _ => progress_bar.show_percentage(),
+                });
+                let mut paused = worker.is_paused();
+                if ui.checkbox(&mut paused, "paused").changed() {
+                    worker.set_paused(paused);
+                }
+                if let WorkerState::Error(msg) = worker.get_state() {
+                    ui.colored_label(egui::Color32::RED, format!("render failed: {}", msg));
+                }
+                watchdog_panel::stuck_label(ui, worker);
 
                 ui.checkbox(&mut self.match_window_size, "match window size");
 
                 egui::CollapsingHeader::new("general info")
                     .default_open(true)
                     .show(ui, |ui| {
-                        self.general_info_grid(
-                            ui,
-                            window_width,
-                            window_height,
-                            worker,
-                            pan_zoom,
-                            color_lib_path,
-                            fractal_lib_path,
-                        );
+                        self.general_info_grid(ui, window_width, window_height, worker, pan_zoom);
                     });
 
+                egui::CollapsingHeader::new("plugins")
+                    .default_open(false)
+                    .show(ui, |ui| self.plugin_picker.ui(ui, config_manager, worker));
                 egui::CollapsingHeader::new("fractal options")
                     .default_open(false)
                     .show(ui, |ui| self.fractal_options_grid(ui, worker));
                 egui::CollapsingHeader::new("color options")
                     .default_open(false)
                     .show(ui, |ui| self.color_options_grid(ui, worker));
+                egui::CollapsingHeader::new("tone mapping")
+                    .default_open(false)
+                    .show(ui, |ui| self.tone_mapping_grid(ui, worker));
+                egui::CollapsingHeader::new("scheduling")
+                    .default_open(false)
+                    .show(ui, |ui| self.scheduling_grid(ui, config_manager, worker));
+                egui::CollapsingHeader::new("rebuild")
+                    .default_open(false)
+                    .show(ui, |ui| self.build_panel.ui(ui, config_manager, worker));
+                egui::CollapsingHeader::new("render servers")
+                    .default_open(false)
+                    .show(ui, |ui| self.remote_panel.ui(ui, config_manager, worker));
+                egui::CollapsingHeader::new("slow chunks")
+                    .default_open(false)
+                    .show(ui, |ui| watchdog_panel::ui(ui, config_manager, worker));
+                egui::CollapsingHeader::new("log")
+                    .default_open(false)
+                    .show(ui, |ui| self.log_panel.ui(ui));
+                egui::CollapsingHeader::new("plugin metrics")
+                    .default_open(false)
+                    .show(ui, log_panel::metrics_grid);
 
                 ui.horizontal(|ui| {
                     if ui.button("reload lib").clicked() {
@@ -125,6 +176,24 @@ impl GuiState {
                             log::error!("error saving image: {}", e);
                         }
                     }
+                    if ui
+                        .button("save frame (16-bit)")
+                        .on_hover_text("tone mapped like the screen, but not dithered")
+                        .clicked()
+                    {
+                        if let Err(e) = self.save_rgba16_frame(worker) {
+                            log::error!("error saving image: {}", e);
+                        }
+                    }
+                    if ui
+                        .button("save frame (linear EXR)")
+                        .on_hover_text("the linear colors with full range, not tone mapped")
+                        .clicked()
+                    {
+                        if let Err(e) = self.save_linear_frame(worker) {
+                            log::error!("error saving image: {}", e);
+                        }
+                    }
 
                     if ui.button("save config as").clicked() {
                         if let Err(e) = self.save_config_as(config_manager, worker) {
@@ -132,6 +201,13 @@ impl GuiState {
                         }
                     }
                 });
+                let dropped_options = worker.get_dropped_options();
+                if !dropped_options.is_empty() {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!("dropped on reload: {}", dropped_options.join(", ")),
+                    );
+                }
             });
         self.window_visible = open;
     }
@@ -143,8 +219,6 @@ impl GuiState {
         window_height: u32,
         worker: &mut FractalWorker,
         pan_zoom: &PanZoomDebounce,
-        color_lib_path: &str,
-        fractal_lib_path: &str,
     ) {
         let (canvas_width, canvas_height) = worker.get_size();
         egui::Grid::new("info")
@@ -170,10 +244,10 @@ impl GuiState {
                 ui.end_row();
 
                 ui.label("color lib path:");
-                ui.label(color_lib_path);
+                ui.label(worker.get_color_lib_path().display().to_string());
                 ui.end_row();
                 ui.label("fractal lib path:");
-                ui.label(fractal_lib_path);
+                ui.label(worker.get_fractal_lib_path().display().to_string());
                 ui.end_row();
 
                 // ui.label("pan zoom state");
@@ -192,6 +266,37 @@ impl GuiState {
                 ui.label("worker state:");
                 ui.label(format!("{:?}", worker.get_state()));
                 ui.end_row();
+                ui.label("render threads:");
+                ui.label(format!("{}", worker.current_render_threads()));
+                ui.end_row();
+
+                let progress = worker.get_render_progress();
+                ui.label("chunks:");
+                ui.label(format!(
+                    "{} / {}",
+                    progress.completed_chunks, progress.total_chunks
+                ));
+                ui.end_row();
+                ui.label("cells:");
+                ui.label(format!(
+                    "{} / {}",
+                    progress.completed_cells, progress.total_cells
+                ));
+                ui.end_row();
+                ui.label("throughput:");
+                ui.label(format!("{:.2} Mcells/s", progress.cells_per_second() / 1e6));
+                ui.end_row();
+                ui.label("elapsed:");
+                ui.label(format!("{:.2} s", progress.elapsed.as_secs_f32()));
+                ui.end_row();
+                ui.label("eta:");
+                match progress.eta() {
+                    Some(eta) if progress.completed_cells < progress.total_cells => {
+                        ui.label(format!("{:.2} s", eta.as_secs_f32()))
+                    }
+                    _ => ui.label("-"),
+                };
+                ui.end_row();
             });
     }
 
@@ -211,20 +316,209 @@ impl GuiState {
         }
     }
 
+    fn tone_mapping_grid(&mut self, ui: &mut Ui, worker: &mut FractalWorker) {
+        let mut tone_mapping = worker.get_tone_mapping();
+        egui::Grid::new("tone mapping")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("operator");
+                egui::ComboBox::from_id_source("tone map operator")
+                    .selected_text(format!("{:?}", tone_mapping.operator))
+                    .show_ui(ui, |ui| {
+                        for operator in ToneMapOperator::ALL {
+                            ui.selectable_value(
+                                &mut tone_mapping.operator,
+                                operator,
+                                format!("{:?}", operator),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("exposure");
+                ui.add(
+                    egui::Slider::new(&mut tone_mapping.exposure, 0.01..=16.0).logarithmic(true),
+                );
+                ui.end_row();
+
+                ui.label("dither");
+                ui.checkbox(&mut tone_mapping.dither, "");
+                ui.end_row();
+            });
+        worker.set_tone_mapping(tone_mapping);
+    }
+
+    fn scheduling_grid(
+        &mut self,
+        ui: &mut Ui,
+        config_manager: &mut ConfigManager,
+        worker: &mut FractalWorker,
+    ) {
+        egui::Grid::new("scheduling")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("progressive");
+                let mut progressive = worker.is_progressive();
+                if ui.checkbox(&mut progressive, "").changed() {
+                    worker.set_progressive(progressive);
+                    config_manager.update(|config| config.progressive = progressive);
+                }
+                ui.end_row();
+
+                let (mut order, mut seed) = worker.get_chunk_order();
+                ui.label("chunk order");
+                egui::ComboBox::from_id_source("chunk order")
+                    .selected_text(format!("{:?}", order))
+                    .show_ui(ui, |ui| {
+                        for value in ChunkOrder::ALL {
+                            ui.selectable_value(&mut order, value, format!("{:?}", value));
+                        }
+                    });
+                ui.end_row();
+                if order == ChunkOrder::Random {
+                    ui.label("seed");
+                    ui.add(egui::DragValue::new(&mut seed));
+                    ui.end_row();
+                }
+                if (order, seed) != worker.get_chunk_order() {
+                    worker.set_chunk_order(order, seed);
+                    config_manager.update(|config| {
+                        config.chunk_order = order;
+                        config.chunk_order_seed = seed;
+                    });
+                }
+
+                let (mut chunk_size, mut adaptive) = worker.get_chunk_size();
+                ui.label("chunk size");
+                ui.add(
+                    egui::DragValue::new(&mut chunk_size).clamp_range(1..=MAX_CHUNK_SIZE as usize),
+                );
+                ui.end_row();
+                ui.label("adaptive chunk size");
+                ui.checkbox(&mut adaptive, "");
+                ui.end_row();
+                if (chunk_size, adaptive) != worker.get_chunk_size() {
+                    worker.set_chunk_size(chunk_size, adaptive);
+                    config_manager.update(|config| {
+                        config.chunk_size = chunk_size;
+                        config.adaptive_chunk_size = adaptive;
+                    });
+                }
+
+                ui.label("boundary fill");
+                let mut boundary_fill = worker.is_boundary_fill();
+                let checkbox = egui::Checkbox::new(&mut boundary_fill, "");
+                if ui
+                    .add_enabled(worker.supports_boundary_fill(), checkbox)
+                    .on_hover_text("fill rectangles with uniform borders instead of computing them")
+                    .on_disabled_hover_text("the fractal func doesn't declare fill-safe fields")
+                    .changed()
+                {
+                    worker.set_boundary_fill(boundary_fill);
+                    config_manager.update(|config| config.boundary_fill = boundary_fill);
+                }
+                ui.end_row();
+
+                ui.label("refine edges");
+                let mut refine_edges = worker.is_refining_edges();
+                if ui
+                    .checkbox(&mut refine_edges, "")
+                    .on_hover_text("give the high contrast pixels more samples after a render")
+                    .changed()
+                {
+                    worker.set_refine_edges(refine_edges);
+                    config_manager.update(|config| config.refine_edges = refine_edges);
+                }
+                ui.end_row();
+
+                let (mut threads, mut priority) = worker.get_render_threads();
+                ui.label("render threads");
+                ui.add(egui::DragValue::new(&mut threads).clamp_range(0..=256))
+                    .on_hover_text("0 is one per CPU");
+                ui.end_row();
+                ui.label("render priority");
+                egui::ComboBox::from_id_source("render priority")
+                    .selected_text(format!("{:?}", priority))
+                    .show_ui(ui, |ui| {
+                        for value in RenderPriority::ALL {
+                            ui.selectable_value(&mut priority, value, format!("{:?}", value));
+                        }
+                    });
+                ui.end_row();
+                if (threads, priority) != worker.get_render_threads() {
+                    worker.set_render_threads(threads, priority);
+                    config_manager.update(|config| {
+                        config.render_threads = threads;
+                        config.render_priority = priority;
+                    });
+                }
+
+                ui.label("background mode");
+                let mut background_mode = worker.is_background_mode();
+                if ui
+                    .checkbox(&mut background_mode, "")
+                    .on_hover_text("render at low priority while the window isn't focused")
+                    .changed()
+                {
+                    worker.set_background_mode(background_mode);
+                    config_manager.update(|config| config.background_mode = background_mode);
+                }
+                ui.end_row();
+            });
+    }
+
+    fn png_save_path() -> Result<Option<PathBuf>> {
+        Ok(FileDialog::new()
+            .add_filter("PNG Image", &["png"])
+            .set_filename(&format!(
+                "fractal_{}.png",
+                chrono::Local::now().format("%F_%H-%M-%S")
+            ))
+            .show_save_single_file()?)
+    }
+
     fn save_frame(&self, frame_width: u32, frame_height: u32, frame: &[u8]) -> Result<()> {
         let frame_buf =
             image::ImageBuffer::<image::Rgba<u8>, _>::from_raw(frame_width, frame_height, frame)
                 .expect("pixel buffer layout bad");
 
-        let path = FileDialog::new()
-            .add_filter("PNG Image", &["png"])
+        if let Some(path) = Self::png_save_path()? {
+            frame_buf.save(path).context("failed to write output image")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn exr_save_path() -> Result<Option<PathBuf>> {
+        Ok(FileDialog::new()
+            .add_filter("OpenEXR Image", &["exr"])
             .set_filename(&format!(
-                "fractal_{}.png",
+                "fractal_{}.exr",
                 chrono::Local::now().format("%F_%H-%M-%S")
             ))
-            .show_save_single_file()?;
+            .show_save_single_file()?)
+    }
 
-        if let Some(path) = path {
+    fn save_linear_frame(&self, worker: &FractalWorker) -> Result<()> {
+        let (width, height, pixels) = worker.get_linear_image();
+        let frame_buf = image::ImageBuffer::<image::Rgba<f32>, _>::from_raw(width, height, pixels)
+            .expect("pixel buffer layout bad");
+
+        if let Some(path) = Self::exr_save_path()? {
+            frame_buf.save(path).context("failed to write output image")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn save_rgba16_frame(&self, worker: &FractalWorker) -> Result<()> {
+        let (width, height, pixels) = worker.get_rgba16_image();
+        let frame_buf = image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(width, height, pixels)
+            .expect("pixel buffer layout bad");
+
+        if let Some(path) = Self::png_save_path()? {
             frame_buf.save(path).context("failed to write output image")
         } else {
             Ok(())
@@ -246,8 +540,9 @@ impl GuiState {
 
         if let Some(path) = path {
             config_manager.update(|config| {
-                // TODO
-                // config.chunk_size = worker.chunk_size;
+                config.fractal_config.path = worker.get_fractal_lib_path().display().to_string();
+                config.color_config.path = worker.get_color_lib_path().display().to_string();
+                (config.chunk_size, config.adaptive_chunk_size) = worker.get_chunk_size();
                 config.fractal_config.options = worker
                     .get_fractal_options()
                     .into_iter()
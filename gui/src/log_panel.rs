@@ -0,0 +1,159 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use egui::{Color32, Ui};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+const MAX_LINES: usize = 500;
+/// the log panel shows at least this much, independent of what `RUST_LOG` sends to stderr
+const PANEL_LEVEL: LevelFilter = LevelFilter::Info;
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+pub type LogLines = Arc<Mutex<VecDeque<LogLine>>>;
+
+/// forwards to env_logger and keeps the most recent lines around for the gui
+struct PanelLogger {
+    inner: env_logger::Logger,
+    lines: LogLines,
+}
+
+impl Log for PanelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= PANEL_LEVEL || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        if record.level() <= PANEL_LEVEL {
+            if let Ok(mut lines) = self.lines.lock() {
+                if lines.len() >= MAX_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(LogLine {
+                    level: record.level(),
+                    target: record.target().to_owned(),
+                    message: record.args().to_string(),
+                });
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// installs `inner` as the global logger, returns the lines shown in the log panel
+pub fn init(inner: env_logger::Logger) -> LogLines {
+    let lines = LogLines::default();
+    let max_level = inner.filter().max(PANEL_LEVEL);
+    let logger = PanelLogger {
+        inner,
+        lines: lines.clone(),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+    lines
+}
+
+#[derive(Debug)]
+pub struct LogPanel {
+    lines: LogLines,
+    min_level: Level,
+    plugins_only: bool,
+}
+
+impl LogPanel {
+    pub fn new(lines: LogLines) -> Self {
+        Self {
+            lines,
+            min_level: Level::Info,
+            plugins_only: false,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("log level")
+                .selected_text(self.min_level.as_str())
+                .show_ui(ui, |ui| {
+                    for level in [Level::Error, Level::Warn, Level::Info] {
+                        ui.selectable_value(&mut self.min_level, level, level.as_str());
+                    }
+                });
+            ui.checkbox(&mut self.plugins_only, "plugins only");
+            if ui.button("clear").clicked() {
+                if let Ok(mut lines) = self.lines.lock() {
+                    lines.clear();
+                }
+            }
+        });
+
+        let lines = match self.lines.lock() {
+            Ok(lines) => lines,
+            Err(_) => return,
+        };
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .stick_to_bottom()
+            .show(ui, |ui| {
+                for line in lines
+                    .iter()
+                    .filter(|line| line.level <= self.min_level)
+                    .filter(|line| !self.plugins_only || !is_host_target(&line.target))
+                {
+                    let color = match line.level {
+                        Level::Error => Color32::RED,
+                        Level::Warn => Color32::YELLOW,
+                        _ => ui.visuals().text_color(),
+                    };
+                    ui.colored_label(
+                        color,
+                        format!("{} {}: {}", line.level, line.target, line.message),
+                    );
+                }
+            });
+    }
+}
+
+fn is_host_target(target: &str) -> bool {
+    [
+        "rust_mandelbrot_gui",
+        "worker",
+        "wgpu",
+        "naga",
+        "winit",
+        "pixels",
+    ]
+    .iter()
+    .any(|prefix| target.starts_with(prefix))
+}
+
+pub fn metrics_grid(ui: &mut Ui) {
+    let metrics = worker::plugin_log::plugin_metrics();
+    if metrics.is_empty() {
+        ui.label("no metrics reported");
+        return;
+    }
+    egui::Grid::new("plugin metrics grid")
+        .striped(true)
+        .show(ui, |ui| {
+            for (name, value, age) in metrics {
+                ui.label(name);
+                ui.label(format!("{:.4}", value));
+                ui.label(format!("{:.1}s ago", age));
+                ui.end_row();
+            }
+        });
+}
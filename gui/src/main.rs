@@ -1,29 +1,41 @@
+mod build_panel;
 mod gui;
 mod gui_framework;
+mod log_panel;
 
 mod pan_zoom_debounce;
+mod plugin_picker;
+mod remote_panel;
 mod renderer;
+mod watchdog_panel;
 
 use crate::gui_framework::Framework;
 use anyhow::Result;
 use egui_wgpu_backend::wgpu::Extent3d;
 use gui::GuiState;
-use log::error;
+use log::{debug, error, info};
 use pan_zoom_debounce::PanZoomDebounce;
 use pixels::wgpu;
 use pixels::{PixelsBuilder, SurfaceTexture};
 use renderer::TransformRenderer;
-use std::io::Write;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 use winit::{
     dpi::LogicalSize,
-    event::{Event, VirtualKeyCode},
+    event::{Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 use winit_input_helper::WinitInputHelper;
 use worker::config_manager::ConfigManager;
+use worker::rebuild::BuildConfig;
 
-use worker::fractal_worker2::FractalWorker;
+use worker::builtin_plugins::default_builtin_path;
+use worker::fractal_worker2::{FractalWorker, RenderSettings};
+use worker::plugin_dir::{default_plugin_dir, find_plugin, PluginKind};
+use worker::plugin_process::PluginProcess;
 use worker::util::measure_execution_time;
 
 #[derive(Debug, structopt::StructOpt)]
@@ -33,10 +45,32 @@ struct Args {
     #[structopt(short, long, default_value = "1024")]
     height: u32,
 
+    /// defaults to the one from the config, or the first one in the plugin dir, or the built-in one.
+    /// built-in plugins are selected with `builtin:<name>`, eg. `builtin:mandelbrot_f64`
     #[structopt(short, long)]
-    fractal_lib: String,
+    fractal_lib: Option<String>,
+    /// defaults to the one from the config, or the first one in the plugin dir, or the built-in one
     #[structopt(short, long)]
-    color_lib: String,
+    color_lib: Option<String>,
+    /// where to look for libraries, defaults to the one from the config,
+    /// or $XDG_DATA_HOME/rust-mandelbrot/plugins if it exists, or target/release
+    #[structopt(long)]
+    plugin_dir: Option<PathBuf>,
+
+    /// take the libraries, plugin dir and build settings from this config file
+    #[structopt(long)]
+    config: Option<PathBuf>,
+
+    /// run the plugins in a separate process, so that a crashing plugin doesn't take the app down
+    #[structopt(long)]
+    out_of_process: bool,
+    /// serve plugin requests on stdin/stdout, used by --out-of-process
+    #[structopt(long, hidden = true)]
+    plugin_process: bool,
+    /// also render on this render server (`host:port`, see the `worker_server` binary),
+    /// can be given more than once. adds to the ones from the config.
+    #[structopt(long = "remote-worker")]
+    remote_workers: Vec<String>,
 
     #[structopt(long, default_value = "1.25")]
     extra_scale_factor: f32,
@@ -44,28 +78,77 @@ struct Args {
 
 #[paw::main]
 fn main(args: Args) -> Result<()> {
+    if args.plugin_process {
+        return worker::plugin_process::run_child();
+    }
     let mut window_width = args.width;
     let mut window_height = args.height;
-    let fractal_lib = args.fractal_lib;
-    let color_lib = args.color_lib;
     let extra_scale_factor = args.extra_scale_factor;
 
-    let mut config_manager =
-        ConfigManager::new(window_width, window_height, &fractal_lib, &color_lib);
-
     // env_logger::init();
-    env_logger::builder()
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{} {} {}: {}",
-                buf.timestamp_millis(),
-                record.module_path().unwrap_or("unknown"),
-                record.level(),
-                record.args()
-            )
+    let log_lines = log_panel::init(
+        env_logger::builder()
+            .format(|buf, record| {
+                writeln!(
+                    buf,
+                    "{} {} {}: {}",
+                    buf.timestamp_millis(),
+                    record.module_path().unwrap_or("unknown"),
+                    record.level(),
+                    record.args()
+                )
+            })
+            .build(),
+    );
+
+    let loaded_config = args
+        .config
+        .as_ref()
+        .map(ConfigManager::read_from_path)
+        .transpose()?;
+    let plugin_dir = args
+        .plugin_dir
+        .or_else(|| {
+            let config = loaded_config.as_ref()?.config();
+            config.plugin_dir.as_ref().map(PathBuf::from)
         })
-        .init();
+        .unwrap_or_else(default_plugin_dir);
+    let fractal_lib = match args.fractal_lib.or_else(|| {
+        let config = loaded_config.as_ref()?.config();
+        Some(config.fractal_config.path.clone())
+    }) {
+        Some(path) => path,
+        None => first_plugin(&plugin_dir, PluginKind::Fractal),
+    };
+    let color_lib = match args.color_lib.or_else(|| {
+        let config = loaded_config.as_ref()?.config();
+        Some(config.color_config.path.clone())
+    }) {
+        Some(path) => path,
+        None => first_plugin(&plugin_dir, PluginKind::Color),
+    };
+
+    let mut config_manager = match loaded_config {
+        Some(mut config_manager) => {
+            // the command line wins over the config
+            config_manager.update(|config| {
+                config.fractal_config.path = fractal_lib.clone();
+                config.color_config.path = color_lib.clone();
+            });
+            config_manager
+        }
+        None => ConfigManager::new(window_width, window_height, &fractal_lib, &color_lib),
+    };
+    config_manager.update(|config| {
+        config.plugin_dir = Some(plugin_dir.display().to_string());
+        config.out_of_process |= args.out_of_process;
+        for addr in &args.remote_workers {
+            if !config.remote_workers.contains(addr) {
+                config.remote_workers.push(addr.clone());
+            }
+        }
+    });
+
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
@@ -98,14 +181,33 @@ fn main(args: Args) -> Result<()> {
     };
 
     let mut pan_zoom = PanZoomDebounce::new(window_width, window_height);
-    let mut worker = FractalWorker::new(window_width, window_height, &fractal_lib, &color_lib);
-    let mut gui_state = GuiState::default();
+    let plugin_process = if config_manager.config().out_of_process {
+        Some(PluginProcess::new()?)
+    } else {
+        None
+    };
+    let mut worker = FractalWorker::with_plugin_process(
+        window_width,
+        window_height,
+        &fractal_lib,
+        &color_lib,
+        plugin_process,
+    )?;
+    worker.set_build_configs(
+        BuildConfig::from(&config_manager.config().fractal_config),
+        BuildConfig::from(&config_manager.config().color_config),
+    );
+    worker.configure(RenderSettings::from(config_manager.config()));
+    let mut gui_state = GuiState::new(log_lines, &config_manager, &plugin_dir);
     let mut transform_renderer = TransformRenderer::new(&pixels, window_width, window_height);
 
     event_loop.run(move |event, _, control_flow| {
         // Update egui inputs
         if let Event::WindowEvent { event, .. } = &event {
             framework.handle_event(event);
+            if let WindowEvent::Focused(focused) = event {
+                worker.set_focused(*focused);
+            }
         }
 
         if input.update(&event) {
@@ -146,6 +248,12 @@ fn main(args: Args) -> Result<()> {
             if !framework.wants_pointer_input() {
                 pan_zoom.handle_input(window_width, window_height, &input, &pixels);
             }
+            worker.set_cursor(
+                input
+                    .mouse()
+                    .and_then(|pos| pixels.window_pos_to_pixel(pos).ok())
+                    .map(|(x, y)| [x as u32, y as u32]),
+            );
 
             window.request_redraw();
         }
@@ -185,8 +293,6 @@ fn main(args: Args) -> Result<()> {
                         pixels.get_frame(),
                         &mut worker,
                         &pan_zoom,
-                        &color_lib,
-                        &fractal_lib,
                     );
                 });
 
@@ -231,3 +337,23 @@ fn main(args: Args) -> Result<()> {
         }
     });
 }
+
+/// the first library of `kind` in the plugin dir, or the built-in one if there is none
+fn first_plugin(plugin_dir: &Path, kind: PluginKind) -> String {
+    let plugin = find_plugin(plugin_dir, kind).unwrap_or_else(|e| {
+        debug!("failed to scan {}: {}", plugin_dir.display(), e);
+        None
+    });
+    let path = match plugin {
+        Some(plugin) => plugin.path,
+        None => {
+            info!(
+                "no {} library found in {}, using the built-in one",
+                kind,
+                plugin_dir.display()
+            );
+            default_builtin_path(kind)
+        }
+    };
+    path.display().to_string()
+}
@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use egui::{Color32, Ui};
+use worker::{
+    builtin_plugins::{builtin_name, builtin_plugin_infos},
+    config_manager::ConfigManager,
+    fractal_worker2::FractalWorker,
+    plugin_dir::{scan_plugin_dir_in_background, PluginInfo, PluginKind},
+};
+
+/// lists the libraries in the plugin directory and swaps them in
+#[derive(Debug)]
+pub struct PluginPicker {
+    dir: String,
+    plugins: Option<Vec<PluginInfo>>,
+    scan: Option<Receiver<anyhow::Result<Vec<PluginInfo>>>>,
+    error: Option<String>,
+}
+
+impl PluginPicker {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_string_lossy().into_owned(),
+            plugins: None,
+            scan: None,
+            error: None,
+        }
+    }
+
+    /// starts a scan if there is no list yet, and picks up the result of a running one
+    fn poll_scan(&mut self) {
+        if self.plugins.is_none() && self.scan.is_none() {
+            self.scan = Some(scan_plugin_dir_in_background(PathBuf::from(&self.dir)));
+        }
+        let res = match self.scan.as_ref().map(|scan| scan.try_recv()) {
+            Some(Ok(res)) => res,
+            _ => return,
+        };
+        self.scan = None;
+        let mut plugins = match res {
+            Ok(plugins) => {
+                self.error = None;
+                plugins
+            }
+            Err(e) => {
+                self.error = Some(format!("failed to scan {}: {}", self.dir, e));
+                vec![]
+            }
+        };
+        plugins.extend(builtin_plugin_infos());
+        plugins.sort_by_key(|plugin| plugin.kind);
+        self.plugins = Some(plugins);
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        config_manager: &mut ConfigManager,
+        worker: &mut FractalWorker,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("plugin dir");
+            ui.text_edit_singleline(&mut self.dir);
+            if ui.button("rescan").clicked() {
+                self.plugins = None;
+                self.scan = None;
+                let dir = self.dir.clone();
+                config_manager.update(|config| config.plugin_dir = Some(dir));
+            }
+        });
+        process_ui(ui, config_manager, worker);
+        self.poll_scan();
+        if self.scan.is_some() {
+            ui.label("scanning...");
+            ui.ctx().request_repaint();
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+
+        let plugins = self.plugins.clone().unwrap_or_default();
+        for kind in [Some(PluginKind::Fractal), Some(PluginKind::Color), None] {
+            match kind {
+                Some(kind) => ui.label(format!("{} libraries", kind)),
+                None if plugins.iter().any(|p| p.kind.is_none()) => {
+                    ui.label("other libraries (not loaded yet)")
+                }
+                None => continue,
+            };
+            let current = match kind {
+                Some(PluginKind::Fractal) => vec![worker.get_fractal_lib_path().to_owned()],
+                Some(PluginKind::Color) => vec![worker.get_color_lib_path().to_owned()],
+                None => vec![
+                    worker.get_fractal_lib_path().to_owned(),
+                    worker.get_color_lib_path().to_owned(),
+                ],
+            };
+            egui::Grid::new(format!("{:?} plugins grid", kind))
+                .num_columns(5)
+                .striped(true)
+                .show(ui, |ui| {
+                    for plugin in plugins.iter().filter(|p| p.kind == kind) {
+                        ui.label(&plugin.name);
+                        match &plugin.interface_version {
+                            Some(version) => ui.label(format!("interface {}", version)),
+                            None => ui.label(""),
+                        };
+                        ui.label(
+                            plugin
+                                .modified
+                                .map(|t| {
+                                    chrono::DateTime::<chrono::Local>::from(t)
+                                        .format("%F %T")
+                                        .to_string()
+                                })
+                                .unwrap_or_default(),
+                        );
+                        if builtin_name(&plugin.path).is_some() {
+                            ui.label("built-in");
+                        } else {
+                            ui.label(format!("{} KiB", plugin.size / 1024));
+                        }
+                        if current.iter().any(|path| is_same_file(&plugin.path, path)) {
+                            ui.label("in use");
+                        } else if ui.button("use").clicked() {
+                            self.use_plugin(plugin, config_manager, worker);
+                        }
+                        ui.end_row();
+                    }
+                });
+        }
+    }
+
+    fn use_plugin(
+        &mut self,
+        plugin: &PluginInfo,
+        config_manager: &mut ConfigManager,
+        worker: &mut FractalWorker,
+    ) {
+        let res = self.inspect(plugin).and_then(|kind| {
+            match kind {
+                PluginKind::Fractal => worker.set_fractal_lib(&plugin.path)?,
+                PluginKind::Color => worker.set_color_lib(&plugin.path)?,
+            }
+            Ok(kind)
+        });
+        match res {
+            Ok(kind) => {
+                let path = plugin.path.to_string_lossy().into_owned();
+                config_manager.update(|config| match kind {
+                    PluginKind::Fractal => config.fractal_config.path = path,
+                    PluginKind::Color => config.color_config.path = path,
+                });
+                self.error = None;
+            }
+            Err(e) => {
+                log::error!("error switching library: {:#}", e);
+                self.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// the kind of a picked library, which loads it if it wasn't yet
+    fn inspect(&mut self, plugin: &PluginInfo) -> anyhow::Result<PluginKind> {
+        let inspected = plugin.inspected()?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not a fractal or color library",
+                plugin.path.display()
+            )
+        })?;
+        if let Some(plugins) = &mut self.plugins {
+            if let Some(listed) = plugins.iter_mut().find(|p| p.path == plugin.path) {
+                *listed = inspected.clone();
+            }
+            plugins.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+        }
+        inspected
+            .kind
+            .ok_or_else(|| anyhow::anyhow!("unknown library kind"))
+    }
+}
+
+/// switching the plugin process on and off, and what happened to it
+fn process_ui(ui: &mut Ui, config_manager: &mut ConfigManager, worker: &mut FractalWorker) {
+    ui.horizontal(|ui| {
+        let mut out_of_process = worker.is_out_of_process();
+        if ui
+            .checkbox(&mut out_of_process, "run plugins in a separate process")
+            .changed()
+        {
+            match worker.set_out_of_process(out_of_process) {
+                Ok(()) => config_manager.update(|config| config.out_of_process = out_of_process),
+                Err(e) => log::error!("error switching the plugin process: {:#}", e),
+            }
+        }
+        if worker.is_out_of_process() && ui.button("restart").clicked() {
+            worker.restart_plugin_process();
+        }
+    });
+    if let Some(status) = worker.get_plugin_process_status() {
+        match status.pid {
+            Some(pid) => ui.label(format!("plugin process {} running", pid)),
+            None => ui.label("plugin process not running"),
+        };
+        if let Some(failure) = &status.last_failure {
+            ui.colored_label(
+                Color32::RED,
+                format!("crashed {} times, last time: {}", status.crashes, failure),
+            );
+        }
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| PathBuf::from(p));
+    canonical(a) == canonical(b)
+}
@@ -0,0 +1,58 @@
+use egui::{Color32, Ui};
+use worker::{config_manager::ConfigManager, fractal_worker2::FractalWorker};
+
+/// the edited list of render servers, applied to the config and the worker together
+#[derive(Debug, Default)]
+pub struct RemotePanel {
+    /// one `host:port` per line
+    addrs: String,
+}
+
+impl RemotePanel {
+    pub fn new(config_manager: &ConfigManager) -> Self {
+        Self {
+            addrs: config_manager.config().remote_workers.join("\n"),
+        }
+    }
+
+    pub fn ui(
+        &mut self,
+        ui: &mut Ui,
+        config_manager: &mut ConfigManager,
+        worker: &mut FractalWorker,
+    ) {
+        ui.label("render servers, one host:port per line");
+        ui.add(egui::TextEdit::multiline(&mut self.addrs).desired_rows(2));
+        if ui.button("apply").clicked() {
+            let addrs = self
+                .addrs
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            config_manager.update(|config| config.remote_workers = addrs.clone());
+            worker.set_remote_workers(addrs);
+        }
+
+        let stats = worker.get_remote_stats();
+        if stats.is_empty() {
+            return;
+        }
+        egui::Grid::new("remote workers grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                for stats in stats {
+                    ui.label(&stats.addr);
+                    match &stats.error {
+                        Some(error) => ui.colored_label(
+                            Color32::RED,
+                            format!("{} chunks, then failed: {}", stats.chunks, error),
+                        ),
+                        None => ui.label(format!("{} chunks", stats.chunks)),
+                    };
+                    ui.end_row();
+                }
+            });
+    }
+}
@@ -0,0 +1,107 @@
+use std::{path::Path, time::Duration};
+
+use egui::{Color32, Ui};
+use worker::{
+    config_manager::ConfigManager, fractal_worker2::FractalWorker, watchdog::SlowChunk,
+    wire::WireOptions,
+};
+
+/// a warning next to the progress bar while a chunk is stuck
+pub fn stuck_label(ui: &mut Ui, worker: &FractalWorker) {
+    let stuck = worker
+        .get_slow_chunks()
+        .into_iter()
+        .filter(|slow_chunk| !slow_chunk.finished)
+        .collect::<Vec<_>>();
+    if let Some(slow_chunk) = stuck.first() {
+        ui.colored_label(
+            Color32::RED,
+            format!(
+                "{} chunk(s) over the time budget, eg. at {:?} for {:.1?} with {}",
+                stuck.len(),
+                slow_chunk.pos,
+                slow_chunk.elapsed,
+                lib_name(&slow_chunk.plugins.fractal_lib),
+            ),
+        );
+    }
+}
+
+pub fn ui(ui: &mut Ui, config_manager: &mut ConfigManager, worker: &mut FractalWorker) {
+    ui.horizontal(|ui| {
+        ui.label("chunk time budget (0 = off)");
+        let mut budget = worker.get_chunk_budget().as_secs_f64();
+        let changed = ui
+            .add(
+                egui::DragValue::new(&mut budget)
+                    .clamp_range(0.0..=600.0)
+                    .speed(0.1)
+                    .suffix(" s"),
+            )
+            .changed();
+        if changed {
+            let budget = Duration::from_secs_f64(budget);
+            worker.set_chunk_budget(budget);
+            config_manager.update(|config| config.chunk_budget_ms = budget.as_millis() as u64);
+        }
+    });
+
+    let slow_chunks = worker.get_slow_chunks();
+    if slow_chunks.is_empty() {
+        ui.label("no chunks over budget in this render");
+        return;
+    }
+    egui::Grid::new("slow chunks grid")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            for slow_chunk in slow_chunks.iter() {
+                slow_chunk_row(ui, slow_chunk);
+            }
+        });
+}
+
+fn slow_chunk_row(ui: &mut Ui, slow_chunk: &SlowChunk) {
+    let status = match (slow_chunk.finished, slow_chunk.killed_process) {
+        (_, true) => "plugin process killed",
+        (true, false) => "finished late",
+        (false, false) => "still running",
+    };
+    let color = if slow_chunk.finished {
+        Color32::YELLOW
+    } else {
+        Color32::RED
+    };
+    ui.colored_label(
+        color,
+        format!(
+            "{} cells at {:?}, {:.1?}",
+            slow_chunk.cells, slow_chunk.pos, slow_chunk.elapsed
+        ),
+    );
+    ui.label(status);
+    ui.end_row();
+
+    let plugins = &slow_chunk.plugins;
+    ui.label(lib_name(&plugins.fractal_lib));
+    ui.label(options_text(&plugins.fractal_options));
+    ui.end_row();
+    ui.label(lib_name(&plugins.color_lib));
+    ui.label(options_text(&plugins.color_options));
+    ui.end_row();
+}
+
+fn lib_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn options_text(options: &WireOptions) -> String {
+    options
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,37 +1,234 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
 use color_func::{prelude::*, RChunk};
-use impl_util::compute_colors_rmp;
+use impl_util::{
+    abort_on_panic, catch_panic, catch_panic_or, cell_values_rmp, compute_colors_rmp,
+    compute_colors_tile_rmp, config_helper::OptionSetter, host_log, Neighborhood,
+};
 use mandelbrot_f64::MandelbrotData;
 
-#[derive(Debug, Clone, Copy)]
-pub struct BasicLumaColorFunc {}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumaMode {
+    Sine,
+    /// histogram equalization of the escape iteration counts
+    Histogram,
+    /// stretch the 1st to 99th percentile of the escape iteration counts over the full range
+    AutoRange,
+}
+
+impl FromStr for LumaMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sine" => Ok(LumaMode::Sine),
+            "histogram" => Ok(LumaMode::Histogram),
+            "auto_range" => Ok(LumaMode::AutoRange),
+            _ => Err(format!(
+                "unknown mode {:?}, expected one of sine, histogram, auto_range",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for LumaMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LumaMode::Sine => write!(f, "sine"),
+            LumaMode::Histogram => write!(f, "histogram"),
+            LumaMode::AutoRange => write!(f, "auto_range"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicLumaColorFunc {
+    mode: LumaMode,
+    /// strength of the slope shading, 0 turns it off
+    emboss: f32,
+    stats: Option<RColorStats>,
+    // derived from stats
+    cumulative_histogram: Vec<f64>,
+}
 
 impl BasicLumaColorFunc {
+    pub fn new() -> Self {
+        Self {
+            mode: LumaMode::Sine,
+            emboss: 0.0,
+            stats: None,
+            cumulative_histogram: vec![],
+        }
+    }
+
+    fn histogram_luma(&self, stats: &RColorStats, iter: f64) -> f32 {
+        let bin_width = stats.bin_width();
+        if bin_width <= 0.0 || self.cumulative_histogram.len() < 2 {
+            return 1.0;
+        }
+        let pos = ((iter - stats.min) / bin_width).clamp(0.0, stats.histogram.len() as f64);
+        let i = (pos.floor() as usize).min(self.cumulative_histogram.len() - 2);
+        let (lo, hi) = (
+            self.cumulative_histogram[i],
+            self.cumulative_histogram[i + 1],
+        );
+        (lo + (hi - lo) * (pos - i as f64)) as f32
+    }
+
+    fn auto_range_luma(&self, stats: &RColorStats, iter: f64) -> f32 {
+        let lo = stats.quantile(0.01);
+        let hi = stats.quantile(0.99);
+        if hi <= lo {
+            return 1.0;
+        }
+        ((iter - lo) / (hi - lo)).clamp(0.0, 1.0) as f32
+    }
+
     fn compute_color_impl(&self, data: &MandelbrotData) -> [u8; 3] {
-        let MandelbrotData { iter, outside } = *data;
-        let luma = if outside {
-            ((iter as f32).sqrt().sin().powi(2) * 255.0) as u8
-        } else {
-            0
-        };
+        let luma = self.luma(data);
+        let luma = (luma * 255.0) as u8;
         [luma, luma, luma]
         // [luma, 0, 0]
     }
+
+    /// lights the surface formed by the iteration counts from the top left
+    fn compute_embossed_color_impl(&self, cells: &Neighborhood<MandelbrotData>) -> [u8; 3] {
+        let center = cells.center();
+        let shade = match cells.get(-1, -1) {
+            Some(top_left) if center.outside && top_left.outside => {
+                let slope = center.iter as f32 - top_left.iter as f32;
+                1.0 + self.emboss * slope / (1.0 + slope.abs())
+            }
+            _ => 1.0,
+        };
+        let luma = (self.luma(center) * shade).clamp(0.0, 1.0);
+        let luma = (luma * 255.0) as u8;
+        [luma, luma, luma]
+    }
+
+    fn luma(&self, data: &MandelbrotData) -> f32 {
+        let MandelbrotData { iter, outside } = *data;
+        if !outside {
+            return 0.0;
+        }
+        match (self.mode, &self.stats) {
+            (LumaMode::Histogram, Some(stats)) => self.histogram_luma(stats, iter as f64),
+            (LumaMode::AutoRange, Some(stats)) => self.auto_range_luma(stats, iter as f64),
+            // fall back to the plain coloring until the first statistics arrive
+            _ => (iter as f32).sqrt().sin().powi(2),
+        }
+    }
+}
+
+impl Default for BasicLumaColorFunc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<BasicLumaColorFunc> for RColorFuncBox {
+    fn from(inner: BasicLumaColorFunc) -> Self {
+        RColorFuncBox::from_value(inner, TD_Opaque)
+    }
 }
 
 impl RColorFunc for BasicLumaColorFunc {
-    fn compute_colors(&self, chunk: &RChunk) -> RVec<RColor> {
+    fn compute_colors(&self, chunk: &RChunk) -> RResult<RVec<RColor>, RString> {
         compute_colors_rmp(chunk, |d| self.compute_color_impl(d))
     }
+
+    fn with_option(&self, name: RStr, value: RStr) -> RResult<RColorFuncBox, RString> {
+        catch_panic(|| {
+            OptionSetter::new(self, name, value)
+                .option("mode", |s, v| s.mode = v)
+                .option("emboss", |s, v| s.emboss = v)
+                .finish()
+                .into_result()
+                .map_err(RString::into_string)
+        })
+    }
+
+    fn get_options(&self) -> ROptionsMap {
+        catch_panic_or("get_options", ROptionsMap::default, || {
+            ROptionsMap::from_iter(
+                [
+                    ("mode", format!("{}", self.mode)),
+                    ("emboss", format!("{}", self.emboss)),
+                ]
+                .map(|(k, v)| (RString::from(k), RString::from(v))),
+            )
+        })
+    }
+
+    fn wants_statistics(&self) -> bool {
+        catch_panic_or("wants_statistics", || false, || self.mode != LumaMode::Sine)
+    }
+
+    fn cell_values(&self, chunk: &RChunk) -> RResult<RVec<f64>, RString> {
+        cell_values_rmp(
+            chunk,
+            |d: &MandelbrotData| {
+                if d.outside {
+                    d.iter as f64
+                } else {
+                    f64::NAN
+                }
+            },
+        )
+    }
+
+    fn with_statistics(&self, stats: &RColorStats) -> RResult<RColorFuncBox, RString> {
+        catch_panic(|| {
+            Ok(Self {
+                stats: Some(stats.clone()),
+                cumulative_histogram: stats.cumulative_histogram(),
+                ..self.clone()
+            }
+            .into())
+        })
+    }
+
+    fn neighborhood_radius(&self) -> u32 {
+        catch_panic_or(
+            "neighborhood_radius",
+            || 0,
+            || {
+                if self.emboss != 0.0 {
+                    1
+                } else {
+                    0
+                }
+            },
+        )
+    }
+
+    fn compute_colors_tile(&self, tile: &RTile) -> RResult<RVec<RColor>, RString> {
+        compute_colors_tile_rmp(tile, |cells| self.compute_embossed_color_impl(cells))
+    }
 }
 
 #[cfg(feature = "cdylib")]
 #[export_root_module]
 pub fn get_color_lib_ref() -> ColorLib_Ref {
-    ColorLib { default_color_func }.leak_into_prefix()
+    color_lib()
 }
 
-#[cfg(feature = "cdylib")]
-#[no_mangle]
+/// the root module, also used by hosts that link this crate statically
+pub fn color_lib() -> ColorLib_Ref {
+    abort_on_panic("color_lib", || {
+        ColorLib {
+            default_color_func,
+            set_host_services: host_log::set_host_services,
+        }
+        .leak_into_prefix()
+    })
+}
+
+#[cfg_attr(feature = "cdylib", no_mangle)]
 pub extern "C" fn default_color_func() -> RColorFuncBox {
-    RColorFuncBox::from_value(BasicLumaColorFunc {}, TD_Opaque)
+    abort_on_panic("default_color_func", || {
+        RColorFuncBox::from_value(BasicLumaColorFunc::new(), TD_Opaque)
+    })
 }
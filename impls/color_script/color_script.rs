@@ -0,0 +1,216 @@
+//! colors the cells with a rhai script (see https://rhai.rs), given as the `script` option.
+//!
+//! the fields of a cell are variables of the script, eg. `outside` and `iter` for the cells of
+//! mandelbrot_f64, and `cell` is the whole cell. the script evaluates to `[r, g, b]` or
+//! `[r, g, b, a]`, with ints from 0 to 255 or floats from 0.0 to 1.0. it is compiled once when
+//! the option is set, and then evaluated for every cell.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use color_func::{prelude::*, RChunk};
+use impl_util::{
+    abort_on_panic, catch_panic, catch_panic_or, config_helper::OptionSetter, host_log,
+    try_compute_colors_rmp,
+};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+pub const DEFAULT_SCRIPT: &str =
+    "if outside { let t = (iter % 64) / 64.0; [t, t * t, 1.0 - t] } else { [0, 0, 0] }";
+
+/// per cell, so that a script that doesn't finish fails the render instead of hanging it
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_FUNCTION_EXPR_DEPTH: usize = 32;
+const MAX_STRING_SIZE: usize = 4096;
+const MAX_ARRAY_SIZE: usize = 4096;
+
+#[derive(Clone)]
+pub struct ScriptColorFunc {
+    script: String,
+    engine: Arc<Engine>,
+    ast: Arc<AST>,
+}
+
+impl ScriptColorFunc {
+    pub fn new() -> Self {
+        let engine = Arc::new(limited_engine());
+        let ast = Arc::new(
+            engine
+                .compile(DEFAULT_SCRIPT)
+                .expect("the default script compiles"),
+        );
+        Self {
+            script: DEFAULT_SCRIPT.to_owned(),
+            engine,
+            ast,
+        }
+    }
+
+    fn with_script(&self, script: &str) -> Result<Self, String> {
+        let ast = self
+            .engine
+            .compile(script)
+            .map_err(|e| format!("error compiling the script: {}", e))?;
+        Ok(Self {
+            script: script.to_owned(),
+            engine: self.engine.clone(),
+            ast: Arc::new(ast),
+        })
+    }
+
+    fn compute_color_impl(&self, scope: &mut Scope, cell: &Dynamic) -> Result<[u8; 4], String> {
+        scope.clear();
+        if let Some(fields) = cell.read_lock::<Map>() {
+            for (name, value) in fields.iter() {
+                scope.push_dynamic(name.as_str(), value.clone());
+            }
+        }
+        scope.push_dynamic("cell", cell.clone());
+        let color = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+        to_rgba(color)
+    }
+}
+
+fn limited_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_FUNCTION_EXPR_DEPTH)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE);
+    engine
+}
+
+/// `[r, g, b]` or `[r, g, b, a]` to rgba8
+fn to_rgba(color: Dynamic) -> Result<[u8; 4], String> {
+    let type_name = color.type_name();
+    let channels = color
+        .into_array()
+        .map_err(|_| format!("the script returned {}, expected an array", type_name))?;
+    if channels.len() != 3 && channels.len() != 4 {
+        return Err(format!(
+            "the script returned {} channels, expected 3 or 4",
+            channels.len()
+        ));
+    }
+    let mut rgba = [255; 4];
+    for (channel, value) in rgba.iter_mut().zip(channels) {
+        *channel = if let Ok(v) = value.as_int() {
+            v.clamp(0, 255) as u8
+        } else if let Ok(v) = value.as_float() {
+            (v * 255.0).round().clamp(0.0, 255.0) as u8
+        } else {
+            return Err(format!(
+                "the script returned a {} channel, expected an int or a float",
+                value.type_name()
+            ));
+        };
+    }
+    Ok(rgba)
+}
+
+impl Default for ScriptColorFunc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for ScriptColorFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScriptColorFunc")
+            .field("script", &self.script)
+            .finish()
+    }
+}
+
+impl From<ScriptColorFunc> for RColorFuncBox {
+    fn from(inner: ScriptColorFunc) -> Self {
+        RColorFuncBox::from_value(inner, TD_Opaque)
+    }
+}
+
+impl RColorFunc for ScriptColorFunc {
+    fn compute_colors(&self, chunk: &RChunk) -> RResult<RVec<RColor>, RString> {
+        let scope = RefCell::new(Scope::new());
+        try_compute_colors_rmp(chunk, |cell| {
+            self.compute_color_impl(&mut scope.borrow_mut(), cell)
+        })
+    }
+
+    fn with_option(&self, name: RStr, value: RStr) -> RResult<RColorFuncBox, RString> {
+        catch_panic(|| match name.as_str() {
+            "script" => self.with_script(value.as_str()).map(RColorFuncBox::from),
+            _ => OptionSetter::new(self, name, value)
+                .finish()
+                .into_result()
+                .map_err(RString::into_string),
+        })
+    }
+
+    fn get_options(&self) -> ROptionsMap {
+        catch_panic_or("get_options", ROptionsMap::default, || {
+            ROptionsMap::from_iter([(RString::from("script"), RString::from(self.script.as_str()))])
+        })
+    }
+}
+
+#[cfg(feature = "cdylib")]
+#[export_root_module]
+pub fn get_color_lib_ref() -> ColorLib_Ref {
+    color_lib()
+}
+
+/// the root module, also used by hosts that link this crate statically
+pub fn color_lib() -> ColorLib_Ref {
+    abort_on_panic("color_lib", || {
+        ColorLib {
+            default_color_func,
+            set_host_services: host_log::set_host_services,
+        }
+        .leak_into_prefix()
+    })
+}
+
+#[cfg_attr(feature = "cdylib", no_mangle)]
+pub extern "C" fn default_color_func() -> RColorFuncBox {
+    abort_on_panic("default_color_func", || {
+        RColorFuncBox::from_value(ScriptColorFunc::new(), TD_Opaque)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the MessagePack map `{"outside": true, "iter": 5}`
+    const CELL: &[u8] = b"\x82\xa7outside\xc3\xa4iter\x05";
+
+    fn colors(script: &str) -> Result<RVec<RColor>, RString> {
+        let mut chunk = RChunk::default();
+        chunk.push([0, 0], CELL);
+        let func = ScriptColorFunc::new()
+            .with_script(script)
+            .map_err(RString::from)?;
+        func.compute_colors(&chunk).into_result()
+    }
+
+    #[test]
+    fn colors_cells() {
+        let colors = colors("if outside { [iter, 0, 255] } else { [0, 0, 0] }").unwrap();
+        assert_eq!(colors[0].value, RColorValue::Rgba8([5, 0, 255, 255]));
+    }
+
+    #[test]
+    fn runaway_scripts_fail() {
+        let endless = colors("loop {} [0, 0, 0]").unwrap_err();
+        assert!(endless.contains("operations"), "{}", endless);
+        assert!(colors("let s = \"x\"; loop { s += s; }").is_err());
+        let deep = format!("{}1{}", "(".repeat(1000), ")".repeat(1000));
+        assert!(colors(&deep).is_err());
+    }
+}
@@ -1,4 +1,11 @@
-use impl_util::{compute_cells_rmp, config_helper::OptionSetter};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use impl_util::{
+    abort_on_panic, catch_panic, catch_panic_or, compute_cells_rmp, config_helper::OptionSetter,
+    host_log,
+};
 use num::complex::Complex64;
 use num::Zero;
 use serde::{Deserialize, Serialize};
@@ -20,6 +27,25 @@ pub struct MandelbrotCellFunc {
     //
     top_left: Complex64,
     pixel_size: Complex64,
+    /// shared by the copies of this func that render the same view
+    interior_counts: Arc<InteriorCounts>,
+}
+
+/// cells computed and how many of them are inside the set, for the
+/// `mandelbrot_f64.interior_fraction` metric of a whole render rather than of each chunk
+#[derive(Debug, Default)]
+struct InteriorCounts {
+    cells: AtomicU64,
+    interior: AtomicU64,
+}
+
+impl InteriorCounts {
+    /// the interior fraction of all cells counted so far
+    fn add(&self, cells: u64, interior: u64) -> f64 {
+        let cells = self.cells.fetch_add(cells, Ordering::Relaxed) + cells;
+        let interior = self.interior.fetch_add(interior, Ordering::Relaxed) + interior;
+        interior as f64 / cells as f64
+    }
 }
 
 impl MandelbrotCellFunc {
@@ -30,11 +56,16 @@ impl MandelbrotCellFunc {
         Complex64::new(0.0, self.pixel_size.im)
     }
 
+    fn top_left_for_center(&self) -> Complex64 {
+        self.center
+            - self.pixel_re() * ((self.width / 2) as f64)
+            - self.pixel_im() * ((self.height / 2) as f64)
+    }
+
     fn pos_to_complex(&self, pos: [u32; 2]) -> Complex64 {
         self.top_left + self.pixel_re().scale(pos[0] as f64) + self.pixel_im().scale(pos[1] as f64)
     }
 
-    #[cfg(feature = "cdylib")]
     fn default_for_size(width: u32, height: u32) -> Self {
         Self {
             width,
@@ -44,6 +75,15 @@ impl MandelbrotCellFunc {
             center: Complex64::new(0.0, 0.0),
             top_left: Complex64::new(-1.0, 1.0),
             pixel_size: Complex64::new(2.0 / (width as f64), -2.0 / (height as f64)),
+            interior_counts: Default::default(),
+        }
+    }
+
+    /// a copy for a different view, which starts counting the interior cells over
+    fn derive(&self) -> Self {
+        Self {
+            interior_counts: Default::default(),
+            ..self.clone()
         }
     }
 
@@ -81,102 +121,203 @@ impl From<MandelbrotCellFunc> for RFractalFuncBox {
 
 impl RFractalFunc for MandelbrotCellFunc {
     fn get_size(&self) -> Tuple2<u32, u32> {
-        Tuple2(self.width, self.height)
+        catch_panic_or(
+            "get_size",
+            || Tuple2(0, 0),
+            || Tuple2(self.width, self.height),
+        )
     }
 
-    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RChunk {
-        compute_cells_rmp(positions, |pos| self.compute_cell_impl(pos))
+    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RResult<RChunk, RString> {
+        let interior_count = Cell::new(0);
+        let res = compute_cells_rmp(positions, |pos| {
+            let data = self.compute_cell_impl(pos);
+            if !data.outside {
+                interior_count.set(interior_count.get() + 1);
+            }
+            data
+        });
+        if !positions.is_empty() {
+            let fraction = self
+                .interior_counts
+                .add(positions.len() as u64, interior_count.get());
+            host_log::metric("mandelbrot_f64.interior_fraction", fraction);
+        }
+        res
     }
 
     fn with_size(&self, width: u32, height: u32) -> RFractalFuncBox {
-        // middle doesn't change, just top-left
-        let middle = self.pos_to_complex([self.width / 2, self.height / 2]);
-        let top_left = middle
-            - self.pixel_re() * ((width / 2) as f64)
-            - self.pixel_im() * ((height / 2) as f64);
-        Self {
-            width,
-            height,
-            top_left,
-            ..self.clone()
-        }
-        .into()
+        catch_panic_or(
+            "with_size",
+            || self.clone().into(),
+            || {
+                // middle doesn't change, just top-left
+                let middle = self.pos_to_complex([self.width / 2, self.height / 2]);
+                let top_left = middle
+                    - self.pixel_re() * ((width / 2) as f64)
+                    - self.pixel_im() * ((height / 2) as f64);
+                Self {
+                    width,
+                    height,
+                    top_left,
+                    ..self.derive()
+                }
+                .into()
+            },
+        )
     }
 
     fn with_offset(&self, dx: i32, dy: i32) -> RFractalFuncBox {
-        let complex_offset = self.pixel_re().scale(dx as f64) + self.pixel_im().scale(dy as f64);
-        Self {
-            center: self.center + complex_offset,
-            top_left: self.top_left + complex_offset,
-            ..self.clone()
-        }
-        .into()
+        catch_panic_or(
+            "with_offset",
+            || self.clone().into(),
+            || {
+                let complex_offset =
+                    self.pixel_re().scale(dx as f64) + self.pixel_im().scale(dy as f64);
+                Self {
+                    center: self.center + complex_offset,
+                    top_left: self.top_left + complex_offset,
+                    ..self.derive()
+                }
+                .into()
+            },
+        )
     }
 
     fn add_zoom(&self, zoom_factor: f64) -> RFractalFuncBox {
-        let middle = self.pos_to_complex([self.width / 2, self.height / 2]);
-        let pixel_size = self.pixel_size.scale(1.0 / zoom_factor);
-        let top_left = middle
-            + Complex64::new(
-                -pixel_size.re * ((self.width / 2) as f64),
-                -pixel_size.im * ((self.height / 2) as f64),
-            );
-        Self {
-            top_left,
-            pixel_size,
-            ..self.clone()
-        }
-        .into()
+        catch_panic_or(
+            "add_zoom",
+            || self.clone().into(),
+            || {
+                let middle = self.pos_to_complex([self.width / 2, self.height / 2]);
+                let pixel_size = self.pixel_size.scale(1.0 / zoom_factor);
+                let top_left = middle
+                    + Complex64::new(
+                        -pixel_size.re * ((self.width / 2) as f64),
+                        -pixel_size.im * ((self.height / 2) as f64),
+                    );
+                Self {
+                    top_left,
+                    pixel_size,
+                    ..self.derive()
+                }
+                .into()
+            },
+        )
     }
 
     fn with_option(&self, name: RStr, value: RStr) -> RResult<RFractalFuncBox, RString> {
-        OptionSetter::new(self, name, value)
-            .option("center_re", |s, v| {
-                let diag = s.top_left - s.center;
-                s.center.re = v;
-                s.top_left = s.center + diag;
-            })
-            .option("center_im", |s, v| {
-                let diag = s.top_left - s.center;
-                s.center.im = v;
-                s.top_left = s.center + diag;
-            })
-            .option("max_iter", |s, v| s.max_iter = v)
-            .finish()
+        catch_panic(|| {
+            OptionSetter::new(&self.derive(), name, value)
+                .option("center_re", |s, v| {
+                    let diag = s.top_left - s.center;
+                    s.center.re = v;
+                    s.top_left = s.center + diag;
+                })
+                .option("center_im", |s, v| {
+                    let diag = s.top_left - s.center;
+                    s.center.im = v;
+                    s.top_left = s.center + diag;
+                })
+                .option("max_iter", |s, v| s.max_iter = v)
+                .option("pixel_size_re", |s, v| {
+                    s.pixel_size.re = v;
+                    s.top_left = s.top_left_for_center();
+                })
+                .option("pixel_size_im", |s, v| {
+                    s.pixel_size.im = v;
+                    s.top_left = s.top_left_for_center();
+                })
+                // square pixels, as saved by older versions
+                .option("pixel_size", |s, v: f64| {
+                    s.pixel_size = Complex64::new(v, -v);
+                    s.top_left = s.top_left_for_center();
+                })
+                .finish()
+                .into_result()
+                .map_err(RString::into_string)
+        })
     }
 
     fn get_options(&self) -> ROptionsMap {
-        ROptionsMap::from_iter(
-            [
-                // ("width", format!("{}", self.width)),
-                // ("height", format!("{}", self.height)),
-                ("max_iter", format!("{}", self.max_iter)),
-                // ("center", format!("{}", self.center)),
-                ("center_re", format!("{}", self.center.re)),
-                ("center_im", format!("{}", self.center.im)),
-                // ("top_left", format!("{}", self.top_left)),
-                // ("pixel_size", format!("{}", self.pixel_size)),
-                ("pixel_size", format!("{}", self.pixel_size.re)),
-            ]
-            .map(|(k, v)| (RString::from(k), RString::from(v))),
-        )
+        catch_panic_or("get_options", ROptionsMap::default, || {
+            ROptionsMap::from_iter(
+                [
+                    // ("width", format!("{}", self.width)),
+                    // ("height", format!("{}", self.height)),
+                    ("max_iter", format!("{}", self.max_iter)),
+                    // ("center", format!("{}", self.center)),
+                    ("center_re", format!("{}", self.center.re)),
+                    ("center_im", format!("{}", self.center.im)),
+                    // ("top_left", format!("{}", self.top_left)),
+                    ("pixel_size_re", format!("{}", self.pixel_size.re)),
+                    ("pixel_size_im", format!("{}", self.pixel_size.im)),
+                ]
+                .map(|(k, v)| (RString::from(k), RString::from(v))),
+            )
+        })
+    }
+
+    fn fill_safe_fields(&self) -> RVec<RString> {
+        catch_panic_or("fill_safe_fields", RVec::new, || {
+            RVec::from_iter(["outside", "iter"].map(RString::from))
+        })
     }
 }
 
 #[cfg(feature = "cdylib")]
 #[export_root_module]
 pub fn get_fractal_lib_ref() -> FractalLib_Ref {
-    FractalLib {
-        default_fractal_func_for_size,
-    }
-    .leak_into_prefix()
+    fractal_lib()
 }
 
-#[cfg(feature = "cdylib")]
-#[no_mangle]
+/// the root module, also used by hosts that link this crate statically
+pub fn fractal_lib() -> FractalLib_Ref {
+    abort_on_panic("fractal_lib", || {
+        FractalLib {
+            default_fractal_func_for_size,
+            set_host_services: host_log::set_host_services,
+        }
+        .leak_into_prefix()
+    })
+}
+
+#[cfg_attr(feature = "cdylib", no_mangle)]
 pub extern "C" fn default_fractal_func_for_size(width: u32, height: u32) -> RFractalFuncBox {
-    RFractalFuncBox::from_value(
-        MandelbrotCellFunc::default_for_size(width, height),
-        TD_Opaque,
-    )
+    abort_on_panic("default_fractal_func_for_size", || {
+        RFractalFuncBox::from_value(
+            MandelbrotCellFunc::default_for_size(width, height),
+            TD_Opaque,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_round_trip() {
+        let func: RFractalFuncBox = MandelbrotCellFunc::default_for_size(300, 200).into();
+        let func = func.with_offset(17, -5).add_zoom(3.0);
+        let options = func.get_options();
+
+        let mut replayed: RFractalFuncBox = MandelbrotCellFunc::default_for_size(300, 200).into();
+        for Tuple2(name, value) in options.iter() {
+            replayed = replayed
+                .with_option(name.as_rstr(), value.as_rstr())
+                .unwrap();
+        }
+        assert_eq!(replayed.get_options(), options);
+        let positions = [[0, 0], [299, 0], [150, 100], [0, 199]];
+        assert_eq!(
+            replayed
+                .compute_cells(RSlice::from(&positions[..]))
+                .unwrap()
+                .data,
+            func.compute_cells(RSlice::from(&positions[..]))
+                .unwrap()
+                .data,
+        );
+    }
 }
@@ -0,0 +1,119 @@
+use std::cell::Cell;
+use std::sync::RwLock;
+
+use abi_stable::std_types::RStr;
+use fractal_func::RHostServices;
+use log::{LevelFilter, Log, Metadata, Record};
+
+static HOST_SERVICES: RwLock<Option<RHostServices>> = RwLock::new(None);
+
+thread_local! {
+    /// set while a call into the host is running on this thread
+    static IN_HOST_CALL: Cell<bool> = const { Cell::new(false) };
+}
+
+struct HostLogger(RHostServices);
+
+impl HostLogger {
+    /// runs `func` unless this thread is already in a call into the host. that happens when the
+    /// plugin is linked into the host statically and this became the host's logger too, in which
+    /// case the host would only hand the record back here.
+    fn guarded<T>(func: impl FnOnce() -> T) -> Option<T> {
+        if IN_HOST_CALL.with(|in_call| in_call.replace(true)) {
+            return None;
+        }
+        let res = func();
+        IN_HOST_CALL.with(|in_call| in_call.set(false));
+        Some(res)
+    }
+}
+
+impl Log for HostLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        Self::guarded(|| {
+            (self.0.log_enabled)(metadata.level() as u8, RStr::from(metadata.target()))
+        })
+        .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let message = record.args().to_string();
+            Self::guarded(|| {
+                (self.0.log)(
+                    record.level() as u8,
+                    RStr::from(record.target()),
+                    RStr::from(message.as_str()),
+                )
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// sends this plugin's `log` output and metrics to the host.
+/// meant to be put directly into the root module, eg. `FractalLib { set_host_services, .. }`
+pub extern "C" fn set_host_services(services: RHostServices) {
+    crate::catch_panic_or(
+        "set_host_services",
+        || (),
+        || {
+            if let Ok(mut guard) = HOST_SERVICES.write() {
+                *guard = Some(services);
+            }
+            // this fails if the plugin is linked into the host statically and the host has a
+            // logger, in which case `log` is shared with the host and already goes to it
+            if log::set_boxed_logger(Box::new(HostLogger(services))).is_ok() {
+                log::set_max_level(LevelFilter::Trace);
+            }
+        },
+    )
+}
+
+/// records the latest value of a named metric in the host (does nothing before `set_host_services`)
+pub fn metric(name: &str, value: f64) {
+    if let Ok(guard) = HOST_SERVICES.read() {
+        if let Some(services) = guard.as_ref() {
+            (services.metric)(RStr::from(name), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    /// like a host that shares `log` with the plugin, and has no logger of its own
+    extern "C" fn log(level: u8, target: RStr<'_>, message: RStr<'_>) {
+        RECEIVED.fetch_add(1, Ordering::Relaxed);
+        log::logger().log(
+            &Record::builder()
+                .level(log::Level::Info)
+                .target(target.as_str())
+                .args(format_args!("{} {}", level, message))
+                .build(),
+        );
+    }
+
+    extern "C" fn log_enabled(_level: u8, _target: RStr<'_>) -> bool {
+        true
+    }
+
+    extern "C" fn metric(_name: RStr<'_>, _value: f64) {}
+
+    #[test]
+    fn host_logger_does_not_recurse() {
+        set_host_services(RHostServices {
+            log,
+            log_enabled,
+            metric,
+        });
+        log::info!("hello");
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,42 +1,245 @@
 pub mod config_helper;
+pub mod host_log;
 
-use abi_stable::std_types::{RSlice, RVec, Tuple2};
-use color_func::RColor;
+pub use log;
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use abi_stable::std_types::{RResult, RSlice, RString, RVec, Tuple2};
+use color_func::{RColor, RColorValue, RTile};
 use fractal_func::RChunk;
 use rmp_serde::{self, Serializer};
 use serde::{Deserialize, Serialize};
 
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
+/// runs `func`, turning both errors and panics into an `RErr`.
+/// panics must not unwind across the `extern "C"` plugin boundary, so every plugin entry point
+/// that can fail should go through this.
+pub fn catch_panic<T, F>(func: F) -> RResult<T, RString>
+where
+    F: FnOnce() -> Result<T, String>,
+{
+    match catch_unwind(AssertUnwindSafe(func)) {
+        Ok(res) => RResult::from(res.map_err(RString::from)),
+        Err(payload) => {
+            RResult::RErr(format!("plugin panicked: {}", panic_message(payload)).into())
+        }
+    }
+}
+
+/// like `catch_panic`, for the entry points that have no way to return an error: a panic is
+/// logged and the entry point returns `fallback()` instead
+pub fn catch_panic_or<T, F, D>(name: &str, fallback: D, func: F) -> T
+where
+    F: FnOnce() -> T,
+    D: FnOnce() -> T,
+{
+    catch_unwind(AssertUnwindSafe(func)).unwrap_or_else(|payload| {
+        log::error!("plugin panicked in {}: {}", name, panic_message(payload));
+        fallback()
+    })
+}
+
+/// for root module exports that have nothing to fall back to: a panic is logged, then the process
+/// aborts instead of unwinding into the host
+pub fn abort_on_panic<T, F>(name: &str, func: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    catch_panic_or(name, || std::process::abort(), func)
+}
+
 #[inline]
-pub fn compute_cells_rmp<F, C>(positions: RSlice<[u32; 2]>, func: F) -> RChunk
+pub fn compute_cells_rmp<F, C>(positions: RSlice<[u32; 2]>, func: F) -> RResult<RChunk, RString>
 where
     F: Fn([u32; 2]) -> C,
     C: Serialize,
 {
-    let mut pos_indexes = RVec::with_capacity(positions.len());
-    let mut data = RVec::with_capacity(positions.len());
-    let mut serializer = Serializer::new(&mut data).with_struct_map();
+    catch_panic(|| {
+        let mut pos_indexes = RVec::with_capacity(positions.len());
+        let mut data = RVec::with_capacity(positions.len());
+        let mut serializer = Serializer::new(&mut data).with_struct_map();
+
+        for &pos in positions {
+            let cell = func(pos);
+            let data_start_index = serializer.get_ref().len();
+            cell.serialize(&mut serializer)
+                .map_err(|e| format!("error encoding cell at {:?}: {}", pos, e))?;
+            pos_indexes.push(Tuple2(pos, data_start_index));
+        }
+
+        Ok(RChunk { data, pos_indexes })
+    })
+}
+
+#[inline]
+pub fn cell_values_rmp<'de, F, C>(chunk: &'de RChunk, func: F) -> RResult<RVec<f64>, RString>
+where
+    F: Fn(&C) -> f64,
+    C: Deserialize<'de>,
+{
+    catch_panic(|| {
+        let mut values = RVec::with_capacity(chunk.len());
+        for (pos, data) in chunk.iter() {
+            let cell = rmp_serde::from_slice(data)
+                .map_err(|e| format!("error decoding cell at {:?}: {}", pos, e))?;
+            values.push(func(&cell));
+        }
+        Ok(values)
+    })
+}
+
+#[inline]
+pub fn compute_colors_rmp<'de, F, C, V>(
+    chunk: &'de RChunk,
+    func: F,
+) -> RResult<RVec<RColor>, RString>
+where
+    F: Fn(&C) -> V,
+    C: Deserialize<'de>,
+    V: Into<RColorValue>,
+{
+    catch_panic(|| {
+        let mut colors = RVec::with_capacity(chunk.len());
+        for (pos, data) in chunk.iter() {
+            let cell = rmp_serde::from_slice(data)
+                .map_err(|e| format!("error decoding cell at {:?}: {}", pos, e))?;
+            let value = func(&cell).into();
+            colors.push(RColor { pos, value });
+        }
+        Ok(colors)
+    })
+}
+
+/// like `compute_colors_rmp`, for colorings that can fail on a cell
+#[inline]
+pub fn try_compute_colors_rmp<'de, F, C, V>(
+    chunk: &'de RChunk,
+    func: F,
+) -> RResult<RVec<RColor>, RString>
+where
+    F: Fn(&C) -> Result<V, String>,
+    C: Deserialize<'de>,
+    V: Into<RColorValue>,
+{
+    catch_panic(|| {
+        let mut colors = RVec::with_capacity(chunk.len());
+        for (pos, data) in chunk.iter() {
+            let cell = rmp_serde::from_slice(data)
+                .map_err(|e| format!("error decoding cell at {:?}: {}", pos, e))?;
+            let value = func(&cell)
+                .map_err(|e| format!("error coloring cell at {:?}: {}", pos, e))?
+                .into();
+            colors.push(RColor { pos, value });
+        }
+        Ok(colors)
+    })
+}
 
-    for &pos in positions {
-        let cell = func(pos);
-        let data_start_index = serializer.get_ref().len();
-        cell.serialize(&mut serializer).unwrap();
-        pos_indexes.push(Tuple2(pos, data_start_index));
+/// a decoded cell and the cells around it, see `compute_colors_tile_rmp`
+pub struct Neighborhood<'a, C> {
+    cells: &'a HashMap<[u32; 2], C>,
+    spacing: i64,
+    pos: [u32; 2],
+}
+
+impl<'a, C> Neighborhood<'a, C> {
+    pub fn pos(&self) -> [u32; 2] {
+        self.pos
+    }
+
+    pub fn center(&self) -> &'a C {
+        self.get(0, 0).expect("center cell is always present")
     }
 
-    RChunk { data, pos_indexes }
+    /// the cell (dx, dy) steps of the tile's spacing away, if it is inside the canvas and within
+    /// the tile's radius
+    pub fn get(&self, dx: i32, dy: i32) -> Option<&'a C> {
+        let x = self.pos[0] as i64 + dx as i64 * self.spacing;
+        let y = self.pos[1] as i64 + dy as i64 * self.spacing;
+        let pos = [u32::try_from(x).ok()?, u32::try_from(y).ok()?];
+        self.cells.get(&pos)
+    }
 }
 
 #[inline]
-pub fn compute_colors_rmp<'de, F, C>(chunk: &'de RChunk, func: F) -> RVec<RColor>
+pub fn compute_colors_tile_rmp<'de, F, C, V>(
+    tile: &'de RTile,
+    func: F,
+) -> RResult<RVec<RColor>, RString>
 where
-    F: Fn(&C) -> [u8; 3],
+    F: Fn(&Neighborhood<C>) -> V,
     C: Deserialize<'de>,
+    V: Into<RColorValue>,
 {
-    let mut colors = RVec::with_capacity(chunk.len());
-    for (pos, data) in chunk.iter() {
-        let cell = rmp_serde::from_slice(data).unwrap();
-        let rgb = func(&cell);
-        colors.push(RColor { pos, rgb });
+    catch_panic(|| {
+        let mut cells = HashMap::with_capacity(tile.chunk.len());
+        for (pos, data) in tile.chunk.iter() {
+            let cell = rmp_serde::from_slice(data)
+                .map_err(|e| format!("error decoding cell at {:?}: {}", pos, e))?;
+            cells.insert(pos, cell);
+        }
+
+        let mut colors = RVec::with_capacity(tile.center_count);
+        for pos in tile.center_positions() {
+            let neighborhood = Neighborhood {
+                cells: &cells,
+                spacing: tile.spacing.max(1) as i64,
+                pos,
+            };
+            let value = func(&neighborhood).into();
+            colors.push(RColor { pos, value });
+        }
+        Ok(colors)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_panic_turns_panics_into_errors() {
+        let res: RResult<(), RString> = catch_panic(|| panic!("boom"));
+        assert_eq!(res.unwrap_err().as_str(), "plugin panicked: boom");
+        assert_eq!(catch_panic(|| Ok::<_, String>(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn catch_panic_or_falls_back() {
+        assert_eq!(catch_panic_or("test", || 0, || panic!("boom")), 0);
+        assert_eq!(catch_panic_or("test", || 0, || 1), 1);
+    }
+
+    #[test]
+    fn neighborhoods_step_by_the_tile_spacing() {
+        let mut chunk = RChunk::default();
+        for (pos, value) in [([8, 8], 1u32), ([0, 8], 2), ([7, 8], 3)] {
+            chunk.push(pos, &rmp_serde::to_vec(&value).unwrap());
+        }
+        let tile = RTile {
+            chunk,
+            center_count: 1,
+            radius: 1,
+            spacing: 8,
+        };
+        let colors = compute_colors_tile_rmp(&tile, |cells: &Neighborhood<u32>| {
+            assert_eq!(cells.get(0, -1), None);
+            let left = *cells.get(-1, 0).unwrap();
+            RColorValue::from([left as u8, *cells.center() as u8, 0])
+        })
+        .unwrap();
+        assert_eq!(colors[0].value, RColorValue::from([2, 1, 0]));
     }
-    colors
 }
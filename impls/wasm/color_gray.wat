@@ -0,0 +1,123 @@
+;; a gray ramp as a wasm color func, see interface/wasm.md for the interface.
+;; it reads the cells of mandelbrot_f64 and mandelbrot.wat (the MessagePack map
+;; `{"outside": bool, "iter": uint}`, with the keys in that order).
+(module
+  (import "host" "log" (func $log (param i32 i32 i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  (data (i32.const 0) "periodbrightness")
+  (data (i32.const 64)
+    "\00\00\00\00\06\00\00\00"
+    "\06\00\00\00\0a\00\00\00")
+  (data (i32.const 128) "unexpected cell encoding")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    (global.set $heap
+      (i32.and
+        (i32.add (i32.add (local.get $ptr) (local.get $len)) (i32.const 7))
+        (i32.const -8)))
+    (block $done
+      (loop $grow
+        (br_if $done
+          (i32.le_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+        (if (i32.lt_s (memory.grow (i32.const 1)) (i32.const 0))
+          (then (unreachable)))
+        (br $grow)))
+    (local.get $ptr))
+
+  (func (export "param_count") (result i32)
+    (i32.const 2))
+
+  (func (export "param_name") (param $index i32) (result i64)
+    (local $entry i32)
+    (local.set $entry (i32.add (i32.const 64) (i32.mul (local.get $index) (i32.const 8))))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (i32.load (local.get $entry))) (i64.const 32))
+      (i64.extend_i32_u (i32.load offset=4 (local.get $entry)))))
+
+  ;; params: period, brightness
+  (func (export "default_params") (param $params i32)
+    (f64.store offset=0 (local.get $params) (f64.const 64))
+    (f64.store offset=8 (local.get $params) (f64.const 1)))
+
+  ;; reads a big endian unsigned int of `$len` bytes
+  (func $read_be (param $ptr i32) (param $len i32) (result i32)
+    (local $value i32)
+    (block $done
+      (loop $bytes
+        (br_if $done (i32.eqz (local.get $len)))
+        (local.set $value
+          (i32.or
+            (i32.shl (local.get $value) (i32.const 8))
+            (i32.load8_u (local.get $ptr))))
+        (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+        (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+        (br $bytes)))
+    (local.get $value))
+
+  (func (export "compute_colors")
+    (param $params i32) (param $data i32) (param $offsets i32) (param $count i32) (param $out i32)
+    (result i32)
+    (local $i i32)
+    (local $cell i32)
+    (local $tag i32)
+    (local $iter i32)
+    (local $gray f64)
+    (local $gray8 i32)
+    (block $cells_done
+      (loop $cells
+        (br_if $cells_done (i32.ge_u (local.get $i) (local.get $count)))
+        (local.set $cell (i32.add (local.get $data) (i32.load (local.get $offsets))))
+        (if (i32.or
+              (i32.ne (i32.load8_u (local.get $cell)) (i32.const 0x82))
+              (i32.ne (i32.load8_u offset=10 (local.get $cell)) (i32.const 0xa4)))
+          (then
+            (call $log (i32.const 1) (i32.const 128) (i32.const 24))
+            (return (i32.const 1))))
+        (local.set $tag (i32.load8_u offset=15 (local.get $cell)))
+        (local.set $iter
+          (if (result i32) (i32.lt_u (local.get $tag) (i32.const 0x80))
+            (then (local.get $tag))
+            (else
+              (if (result i32) (i32.eq (local.get $tag) (i32.const 0xcc))
+                (then (call $read_be (i32.add (local.get $cell) (i32.const 16)) (i32.const 1)))
+                (else
+                  (if (result i32) (i32.eq (local.get $tag) (i32.const 0xcd))
+                    (then (call $read_be (i32.add (local.get $cell) (i32.const 16)) (i32.const 2)))
+                    (else
+                      (if (result i32) (i32.eq (local.get $tag) (i32.const 0xce))
+                        (then
+                          (call $read_be (i32.add (local.get $cell) (i32.const 16)) (i32.const 4)))
+                        (else
+                          (call $log (i32.const 1) (i32.const 128) (i32.const 24))
+                          (return (i32.const 2)))))))))))
+        ;; inside is black, outside ramps up every `period` iterations
+        (local.set $gray (f64.const 0))
+        (if (i32.eq (i32.load8_u offset=9 (local.get $cell)) (i32.const 0xc3))
+          (then
+            (local.set $gray
+              (f64.div
+                (f64.convert_i32_u
+                  (i32.rem_u
+                    (local.get $iter)
+                    (i32.trunc_sat_f64_u (f64.max (f64.load (local.get $params)) (f64.const 1)))))
+                (f64.max (f64.load (local.get $params)) (f64.const 1))))))
+        (local.set $gray8
+          (i32.trunc_sat_f64_u
+            (f64.min
+              (f64.mul (f64.mul (local.get $gray) (f64.load offset=8 (local.get $params)))
+                (f64.const 255))
+              (f64.const 255))))
+        (i32.store8 offset=0 (local.get $out) (local.get $gray8))
+        (i32.store8 offset=1 (local.get $out) (local.get $gray8))
+        (i32.store8 offset=2 (local.get $out) (local.get $gray8))
+        (i32.store8 offset=3 (local.get $out) (i32.const 255))
+        (local.set $offsets (i32.add (local.get $offsets) (i32.const 4)))
+        (local.set $out (i32.add (local.get $out) (i32.const 4)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $cells)))
+    (i32.const 0))
+)
@@ -0,0 +1,148 @@
+;; the mandelbrot set as a wasm fractal func, see interface/wasm.md for the interface.
+;; the cells are encoded like the ones of mandelbrot_f64 (the MessagePack map
+;; `{"outside": bool, "iter": uint}`), so that the native color funcs can color them.
+(module
+  (memory (export "memory") 1)
+  ;; everything above the param names is handed out by `alloc`
+  (global $heap (mut i32) (i32.const 1024))
+
+  ;; the param names, and their (offset, len) pairs at 64
+  (data (i32.const 0) "center_recenter_impixel_sizemax_iter")
+  (data (i32.const 64)
+    "\00\00\00\00\09\00\00\00"
+    "\09\00\00\00\09\00\00\00"
+    "\12\00\00\00\0a\00\00\00"
+    "\1c\00\00\00\08\00\00\00")
+  ;; the keys of a cell
+  (data (i32.const 128) "\82\a7outside")
+  (data (i32.const 144) "\a4iter\ce")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $heap))
+    ;; keep everything aligned for the f64 params
+    (global.set $heap
+      (i32.and
+        (i32.add (i32.add (local.get $ptr) (local.get $len)) (i32.const 7))
+        (i32.const -8)))
+    (block $done
+      (loop $grow
+        (br_if $done
+          (i32.le_u (global.get $heap) (i32.mul (memory.size) (i32.const 65536))))
+        (if (i32.lt_s (memory.grow (i32.const 1)) (i32.const 0))
+          (then (unreachable)))
+        (br $grow)))
+    (local.get $ptr))
+
+  (func (export "param_count") (result i32)
+    (i32.const 4))
+
+  (func (export "param_name") (param $index i32) (result i64)
+    (local $entry i32)
+    (local.set $entry (i32.add (i32.const 64) (i32.mul (local.get $index) (i32.const 8))))
+    (i64.or
+      (i64.shl (i64.extend_i32_u (i32.load (local.get $entry))) (i64.const 32))
+      (i64.extend_i32_u (i32.load offset=4 (local.get $entry)))))
+
+  ;; params: center_re, center_im, pixel_size, max_iter
+  (func (export "default_params") (param $width i32) (param $height i32) (param $params i32)
+    (f64.store offset=0 (local.get $params) (f64.const -0.5))
+    (f64.store offset=8 (local.get $params) (f64.const 0))
+    (f64.store offset=16 (local.get $params)
+      (f64.div (f64.const 3) (f64.convert_i32_s (local.get $width))))
+    (f64.store offset=24 (local.get $params) (f64.const 1024)))
+
+  (func (export "with_offset")
+    (param $params i32) (param $width i32) (param $height i32) (param $dx i32) (param $dy i32)
+    (local $pixel_size f64)
+    (local.set $pixel_size (f64.load offset=16 (local.get $params)))
+    (f64.store offset=0 (local.get $params)
+      (f64.add
+        (f64.load offset=0 (local.get $params))
+        (f64.mul (f64.convert_i32_s (local.get $dx)) (local.get $pixel_size))))
+    (f64.store offset=8 (local.get $params)
+      (f64.sub
+        (f64.load offset=8 (local.get $params))
+        (f64.mul (f64.convert_i32_s (local.get $dy)) (local.get $pixel_size)))))
+
+  (func (export "add_zoom")
+    (param $params i32) (param $width i32) (param $height i32) (param $zoom_factor f64)
+    (f64.store offset=16 (local.get $params)
+      (f64.div (f64.load offset=16 (local.get $params)) (local.get $zoom_factor))))
+
+  (func (export "cell_size") (result i32)
+    (i32.const 20))
+
+  (func (export "compute_cells")
+    (param $params i32) (param $width i32) (param $height i32)
+    (param $positions i32) (param $count i32) (param $out i32)
+    (result i32)
+    (local $i i32)
+    (local $pixel_size f64)
+    (local $max_iter i32)
+    (local $c_re f64)
+    (local $c_im f64)
+    (local $z_re f64)
+    (local $z_im f64)
+    (local $z_re2 f64)
+    (local $z_im2 f64)
+    (local $iter i32)
+    (local $outside i32)
+    (local.set $pixel_size (f64.load offset=16 (local.get $params)))
+    (local.set $max_iter (i32.trunc_sat_f64_s (f64.load offset=24 (local.get $params))))
+    (block $cells_done
+      (loop $cells
+        (br_if $cells_done (i32.ge_u (local.get $i) (local.get $count)))
+        (local.set $c_re
+          (f64.add
+            (f64.load offset=0 (local.get $params))
+            (f64.mul
+              (f64.sub
+                (f64.convert_i32_u (i32.load offset=0 (local.get $positions)))
+                (f64.convert_i32_u (i32.shr_u (local.get $width) (i32.const 1))))
+              (local.get $pixel_size))))
+        (local.set $c_im
+          (f64.sub
+            (f64.load offset=8 (local.get $params))
+            (f64.mul
+              (f64.sub
+                (f64.convert_i32_u (i32.load offset=4 (local.get $positions)))
+                (f64.convert_i32_u (i32.shr_u (local.get $height) (i32.const 1))))
+              (local.get $pixel_size))))
+        (local.set $z_re (f64.const 0))
+        (local.set $z_im (f64.const 0))
+        (local.set $iter (i32.const 0))
+        (local.set $outside (i32.const 0))
+        (block $iter_done
+          (loop $iterate
+            (br_if $iter_done (i32.ge_s (local.get $iter) (local.get $max_iter)))
+            (local.set $z_re2 (f64.mul (local.get $z_re) (local.get $z_re)))
+            (local.set $z_im2 (f64.mul (local.get $z_im) (local.get $z_im)))
+            (if (f64.ge (f64.add (local.get $z_re2) (local.get $z_im2)) (f64.const 4))
+              (then
+                (local.set $outside (i32.const 1))
+                (br $iter_done)))
+            (local.set $z_im
+              (f64.add
+                (f64.mul (f64.mul (f64.const 2) (local.get $z_re)) (local.get $z_im))
+                (local.get $c_im)))
+            (local.set $z_re
+              (f64.add (f64.sub (local.get $z_re2) (local.get $z_im2)) (local.get $c_re)))
+            (local.set $iter (i32.add (local.get $iter) (i32.const 1)))
+            (br $iterate)))
+        (if (i32.eqz (local.get $outside))
+          (then (local.set $iter (i32.const 0))))
+        ;; fixmap of 2, "outside": bool, "iter": uint32 in big endian
+        (memory.copy (local.get $out) (i32.const 128) (i32.const 9))
+        (i32.store8 offset=9 (local.get $out) (i32.add (i32.const 0xc2) (local.get $outside)))
+        (memory.copy (i32.add (local.get $out) (i32.const 10)) (i32.const 144) (i32.const 6))
+        (i32.store8 offset=16 (local.get $out) (i32.shr_u (local.get $iter) (i32.const 24)))
+        (i32.store8 offset=17 (local.get $out) (i32.shr_u (local.get $iter) (i32.const 16)))
+        (i32.store8 offset=18 (local.get $out) (i32.shr_u (local.get $iter) (i32.const 8)))
+        (i32.store8 offset=19 (local.get $out) (local.get $iter))
+        (local.set $positions (i32.add (local.get $positions) (i32.const 8)))
+        (local.set $out (i32.add (local.get $out) (i32.const 20)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $cells)))
+    (i32.const 0))
+)
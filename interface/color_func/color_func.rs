@@ -7,11 +7,12 @@ use abi_stable::package_version_strings;
 use abi_stable::sabi_types::VersionStrings;
 use abi_stable::std_types::RArc;
 use abi_stable::std_types::RStr;
-use abi_stable::std_types::{RHashMap, RResult, RString, RVec};
+use abi_stable::std_types::{RHashMap, RResult, RString, RVec, Tuple2};
 use abi_stable::{sabi_trait, std_types::RBox, StableAbi};
 
 pub use fractal_func::RCell;
 pub use fractal_func::RChunk;
+pub use fractal_func::RHostServices;
 
 #[repr(C)]
 #[derive(StableAbi)]
@@ -20,13 +21,15 @@ pub use fractal_func::RChunk;
 pub struct ColorLib {
     #[sabi(last_prefix_field)]
     pub default_color_func: extern "C" fn() -> RColorFuncBox,
+    #[sabi(missing_field(option))]
+    pub set_host_services: extern "C" fn(services: RHostServices),
 }
 
 /// The RootModule trait defines how to load the root module of a library.
 impl RootModule for ColorLib_Ref {
     abi_stable::declare_root_module_statics! {ColorLib_Ref}
-    const BASE_NAME: &'static str = "example_library";
-    const NAME: &'static str = "example_library";
+    const BASE_NAME: &'static str = "color_lib";
+    const NAME: &'static str = "color_lib";
     const VERSION_STRINGS: VersionStrings = package_version_strings!();
 }
 
@@ -38,14 +41,170 @@ pub fn load_root_module_in_directory(directory: &Path) -> Result<ColorLib_Ref, L
 #[derive(Debug, Clone, StableAbi)]
 pub struct RColor {
     pub pos: [u32; 2],
-    pub rgb: [u8; 3],
+    pub value: RColorValue,
+}
+
+/// a color, in whichever precision the color func works in.
+/// the integer variants are sRGB encoded, all alpha is straight (not premultiplied).
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, StableAbi)]
+pub enum RColorValue {
+    Rgb8([u8; 3]),
+    Rgba8([u8; 4]),
+    Rgba16([u16; 4]),
+    /// linear light, may go above 1.0 (the host tone maps it down to the display range)
+    LinearF32([f32; 4]),
+}
+
+impl RColorValue {
+    /// linear light rgba
+    pub fn to_linear(&self) -> [f32; 4] {
+        match *self {
+            RColorValue::Rgb8([r, g, b]) => [
+                srgb_u8_to_linear(r),
+                srgb_u8_to_linear(g),
+                srgb_u8_to_linear(b),
+                1.0,
+            ],
+            RColorValue::Rgba8([r, g, b, a]) => [
+                srgb_u8_to_linear(r),
+                srgb_u8_to_linear(g),
+                srgb_u8_to_linear(b),
+                a as f32 / 255.0,
+            ],
+            RColorValue::Rgba16([r, g, b, a]) => [
+                srgb_to_linear(r as f32 / 65535.0),
+                srgb_to_linear(g as f32 / 65535.0),
+                srgb_to_linear(b as f32 / 65535.0),
+                a as f32 / 65535.0,
+            ],
+            RColorValue::LinearF32(rgba) => rgba,
+        }
+    }
+
+    /// whether this color carries more precision than the 8-bit display buffer can show
+    pub fn is_high_precision(&self) -> bool {
+        matches!(self, RColorValue::Rgba16(_) | RColorValue::LinearF32(_))
+    }
+}
+
+impl From<[u8; 3]> for RColorValue {
+    fn from(rgb: [u8; 3]) -> Self {
+        RColorValue::Rgb8(rgb)
+    }
+}
+impl From<[u8; 4]> for RColorValue {
+    fn from(rgba: [u8; 4]) -> Self {
+        RColorValue::Rgba8(rgba)
+    }
+}
+impl From<[u16; 4]> for RColorValue {
+    fn from(rgba: [u16; 4]) -> Self {
+        RColorValue::Rgba16(rgba)
+    }
+}
+impl From<[f32; 4]> for RColorValue {
+    fn from(rgba: [f32; 4]) -> Self {
+        RColorValue::LinearF32(rgba)
+    }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_u8_to_linear(v: u8) -> f32 {
+    srgb_to_linear(v as f32 / 255.0)
 }
 
 pub type ROptionsMap = RHashMap<RString, RString>;
 
+/// cells to color, followed by the cells around them (the apron) that are only there so that the
+/// color func can look at neighbors. see `RColorFunc::neighborhood_radius`
+#[repr(C)]
+#[derive(Debug, Clone, StableAbi)]
+pub struct RTile {
+    pub chunk: RChunk,
+    /// the first `center_count` cells of `chunk` are the ones to color
+    pub center_count: usize,
+    pub radius: u32,
+    /// the distance between neighboring cells, eg. 8 in the progressive pass that renders every
+    /// 8th cell. the apron is `radius` steps of it wide
+    pub spacing: u32,
+}
+
+impl RTile {
+    pub fn center_positions(&self) -> impl Iterator<Item = [u32; 2]> + '_ {
+        self.chunk.positions().take(self.center_count)
+    }
+}
+
+/// statistics over the values returned by `RColorFunc::cell_values`, collected by the host from
+/// every chunk rendered so far
+#[repr(C)]
+#[derive(Debug, Clone, PartialEq, StableAbi)]
+pub struct RColorStats {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    /// evenly spaced bins from `min` to `max`
+    pub histogram: RVec<u64>,
+    /// sorted tuples of (q, value at quantile q)
+    pub quantiles: RVec<Tuple2<f64, f64>>,
+}
+
+impl RColorStats {
+    pub fn bin_width(&self) -> f64 {
+        (self.max - self.min) / (self.histogram.len().max(1) as f64)
+    }
+
+    /// the value at quantile `q` (0 to 1), interpolated between the collected quantiles
+    pub fn quantile(&self, q: f64) -> f64 {
+        let mut prev: Option<&Tuple2<f64, f64>> = None;
+        for cur in self.quantiles.iter() {
+            if q <= cur.0 {
+                return match prev {
+                    Some(Tuple2(prev_q, prev_v)) if cur.0 > *prev_q => {
+                        prev_v + (cur.1 - prev_v) * (q - prev_q) / (cur.0 - prev_q)
+                    }
+                    _ => cur.1,
+                };
+            }
+            prev = Some(cur);
+        }
+        prev.map(|Tuple2(_, v)| *v).unwrap_or(self.max)
+    }
+
+    /// fraction of values below each bin edge, `histogram.len() + 1` entries from 0 to 1.
+    /// this is what histogram equalization needs, and it's cheaper to build once than per cell.
+    pub fn cumulative_histogram(&self) -> Vec<f64> {
+        let total = self.count.max(1) as f64;
+        let mut acc = 0;
+        let mut cdf = Vec::with_capacity(self.histogram.len() + 1);
+        cdf.push(0.0);
+        for &n in self.histogram.iter() {
+            acc += n;
+            cdf.push(acc as f64 / total);
+        }
+        cdf
+    }
+}
+
 #[sabi_trait]
 pub trait RColorFunc: Clone + Debug + Sync + Send + 'static {
-    fn compute_colors(&self, chunk: &RChunk) -> RVec<RColor>;
+    fn compute_colors(&self, chunk: &RChunk) -> RResult<RVec<RColor>, RString>;
 
     fn with_option(&self, _name: RStr, _value: RStr) -> RResult<RColorFuncBox, RString> {
         RResult::RErr(RString::from("unimplemented"))
@@ -54,6 +213,30 @@ pub trait RColorFunc: Clone + Debug + Sync + Send + 'static {
     fn get_options(&self) -> ROptionsMap {
         ROptionsMap::default()
     }
+
+    /// if true, the host calls `cell_values` on every chunk, and passes statistics over all the
+    /// values to `with_statistics` whenever they change meaningfully (recoloring already-drawn chunks)
+    fn wants_statistics(&self) -> bool {
+        false
+    }
+    /// one value per cell of the chunk, in order. NaN values are left out of the statistics.
+    fn cell_values(&self, _chunk: &RChunk) -> RResult<RVec<f64>, RString> {
+        RResult::RErr(RString::from("unimplemented"))
+    }
+    fn with_statistics(&self, _stats: &RColorStats) -> RResult<RColorFuncBox, RString> {
+        RResult::RErr(RString::from("unimplemented"))
+    }
+
+    /// if non-zero, the host calls `compute_colors_tile` instead of `compute_colors`, with every
+    /// cell within this many steps of `RTile::spacing` (clipped to the canvas) included in the
+    /// tile
+    fn neighborhood_radius(&self) -> u32 {
+        0
+    }
+    /// colors for the first `tile.center_count` cells of the tile
+    fn compute_colors_tile(&self, _tile: &RTile) -> RResult<RVec<RColor>, RString> {
+        RResult::RErr(RString::from("unimplemented"))
+    }
 }
 
 pub type RColorFuncBox = RColorFunc_TO<RBox<()>>;
@@ -62,11 +245,15 @@ pub type RColorFuncArc = RColorFunc_TO<RArc<()>>;
 /// re-exports for convenient wildcard-import by users or implementations of this trait
 pub mod prelude {
     pub use super::RColor;
+    pub use super::RColorStats;
+    pub use super::RColorValue;
     pub use super::ROptionsMap;
+    pub use super::RTile;
     pub use super::{ColorLib, ColorLib_Ref};
     pub use super::{RColorFunc, RColorFuncArc, RColorFuncBox};
 
     pub use fractal_func::RCell;
+    pub use fractal_func::RHostServices;
 
     pub use abi_stable::std_types::{
         RHashMap, RResult, RSlice, RStr, RString, RVec, Tuple2, Tuple3,
@@ -22,13 +22,27 @@ use abi_stable::{
 pub struct FractalLib {
     #[sabi(last_prefix_field)]
     pub default_fractal_func_for_size: extern "C" fn(width: u32, height: u32) -> RFractalFuncBox,
+    #[sabi(missing_field(option))]
+    pub set_host_services: extern "C" fn(services: RHostServices),
+}
+
+/// callbacks into the host, handed to each plugin right after it is loaded
+/// (see `impl_util::host_log` for the plugin side)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, StableAbi)]
+pub struct RHostServices {
+    /// `level` uses the numbering of `log::Level`: 1 is error, 5 is trace
+    pub log: extern "C" fn(level: u8, target: RStr<'_>, message: RStr<'_>),
+    pub log_enabled: extern "C" fn(level: u8, target: RStr<'_>) -> bool,
+    /// records the latest value of a named metric, for display in the host
+    pub metric: extern "C" fn(name: RStr<'_>, value: f64),
 }
 
 /// The RootModule trait defines how to load the root module of a library.
 impl RootModule for FractalLib_Ref {
     abi_stable::declare_root_module_statics! {FractalLib_Ref}
-    const BASE_NAME: &'static str = "example_library";
-    const NAME: &'static str = "example_library";
+    const BASE_NAME: &'static str = "fractal_lib";
+    const NAME: &'static str = "fractal_lib";
     const VERSION_STRINGS: VersionStrings = package_version_strings!();
 }
 
@@ -44,7 +58,7 @@ pub struct RCell {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, StableAbi)]
+#[derive(Debug, Clone, Default, StableAbi)]
 pub struct RChunk {
     // tuple of (pos, data_start_index)
     // the last one goes to the end of the data array
@@ -58,6 +72,26 @@ impl RChunk {
     pub fn len(&self) -> usize {
         self.pos_indexes.len()
     }
+    pub fn push(&mut self, pos: [u32; 2], data: &[u8]) {
+        self.pos_indexes.push(Tuple2(pos, self.data.len()));
+        self.data.extend_from_slice(data);
+    }
+    pub fn extend(&mut self, other: &RChunk) {
+        for (pos, data) in other.iter() {
+            self.push(pos, data);
+        }
+    }
+    pub fn get(&self, index: usize) -> Option<([u32; 2], &[u8])> {
+        let Tuple2(pos, data_start_index) = *self.pos_indexes.get(index)?;
+        let data_end_index = match self.pos_indexes.get(index + 1) {
+            Some(Tuple2(_, i)) => *i,
+            None => self.data.len(),
+        };
+        Some((pos, &self.data[data_start_index..data_end_index]))
+    }
+    pub fn positions(&self) -> impl Iterator<Item = [u32; 2]> + '_ {
+        self.pos_indexes.iter().map(|Tuple2(pos, _)| *pos)
+    }
     pub fn iter(&self) -> impl Iterator<Item = ([u32; 2], &[u8])> {
         self.pos_indexes
             .iter()
@@ -80,8 +114,10 @@ pub type ROptionsMap = RHashMap<RString, RString>;
 pub trait RFractalFunc: Clone + Debug + Sync + Send + 'static {
     fn get_size(&self) -> Tuple2<u32, u32>;
 
-    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RChunk;
+    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RResult<RChunk, RString>;
 
+    /// keeps the cell at `(width / 2, height / 2)` where it is, so that the host can move the
+    /// cells it already has along with it
     fn with_size(&self, width: u32, height: u32) -> RFractalFuncBox;
     fn with_offset(&self, dx: i32, dy: i32) -> RFractalFuncBox;
     fn add_zoom(&self, zoom_factor: f64) -> RFractalFuncBox;
@@ -93,6 +129,20 @@ pub trait RFractalFunc: Clone + Debug + Sync + Send + 'static {
     fn get_options(&self) -> ROptionsMap {
         ROptionsMap::default()
     }
+
+    /// the fields of the (MessagePack map) cell data that decide everything about a cell. if the
+    /// border cells of a rectangle all agree on these, the host may fill the inside of the
+    /// rectangle with copies of a border cell instead of computing it. empty opts out.
+    fn fill_safe_fields(&self) -> RVec<RString> {
+        RVec::new()
+    }
+
+    /// whether `with_size` and `add_zoom` really change the view, so that a bigger and more
+    /// zoomed in copy samples between the cells of this one. the host only refines edges (with
+    /// sub-pixel samples) for funcs that can.
+    fn can_resample(&self) -> bool {
+        true
+    }
 }
 
 pub type RFractalFuncBox = RFractalFunc_TO<RBox<()>>;
@@ -101,6 +151,7 @@ pub type RFractalFuncArc = RFractalFunc_TO<RArc<()>>;
 pub mod prelude {
     pub use super::RCell;
     pub use super::RChunk;
+    pub use super::RHostServices;
     pub use super::ROptionsMap;
     pub use super::{FractalLib, FractalLib_Ref};
     pub use super::{RFractalFunc, RFractalFuncArc, RFractalFuncBox};
@@ -0,0 +1,23 @@
+//! renders chunks for the gui on another machine, start the gui with `--remote-worker host:port`
+
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+use worker::render_server::{RenderServer, DEFAULT_ADDR};
+
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// the address to listen on, eg. `0.0.0.0:7878` to be reachable from other machines.
+    /// there is no authentication, so only do that on a trusted network.
+    #[structopt(short, long, default_value = DEFAULT_ADDR)]
+    listen: String,
+    /// the libraries the gui may ask for, by their file names. nothing else is loaded.
+    #[structopt(long)]
+    plugin_dir: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let args = Args::from_args();
+    RenderServer::bind(&args.listen, args.plugin_dir)?.run()
+}
@@ -0,0 +1,425 @@
+use fractal_func::RChunk;
+use rmpv::Value;
+
+use crate::fractal_worker2::check_chunk_positions;
+use crate::plugin_host::FractalFunc;
+
+/// rectangles with fewer cells than this on a side are computed without looking at their border
+const MIN_FILL_SIDE: u32 = 4;
+
+/// computes the cells at `positions` like `FractalFunc::compute_cells`, but Mariani-Silver style:
+/// the border of the chunk's rectangle is computed first, and if all of its cells agree on
+/// `fields` (see `RFractalFunc::fill_safe_fields`), the inside is filled with copies of a border
+/// cell. otherwise the rectangle is split in two, and each half is checked the same way. only
+/// the cells at `positions` and the borders around them are computed, the rest of the rectangle
+/// is left out.
+pub fn compute_cells_filled(
+    fractal_func: &FractalFunc,
+    positions: &[[u32; 2]],
+    fields: &[String],
+) -> Result<RChunk, String> {
+    let lattice = match Lattice::of(positions) {
+        Some(lattice) => lattice,
+        None => return fractal_func.compute_cells(positions),
+    };
+    let cell_count = (lattice.columns * lattice.rows) as usize;
+    let mut requested = vec![false; cell_count];
+    for &pos in positions {
+        requested[lattice.index(pos)] = true;
+    }
+    let mut filler = Filler {
+        fractal_func,
+        fields,
+        requested_sums: requested_sums(&requested, lattice.columns),
+        requested,
+        cells: vec![None; cell_count],
+        computed: RChunk::default(),
+        lattice,
+    };
+    filler.fill()?;
+
+    let mut chunk = RChunk::default();
+    for &pos in positions {
+        let index = filler.cells[filler.lattice.index(pos)]
+            .ok_or_else(|| format!("fractal func: no cell for {:?}", pos))?;
+        let (_, data) = filler.computed.get(index).unwrap();
+        chunk.push(pos, data);
+    }
+    Ok(chunk)
+}
+
+/// the cells of a chunk are every `spacing`th cell of the rectangle that they span, like in a
+/// progressive pass. the fill works in the coordinates of that lattice.
+#[derive(Debug, Clone, Copy)]
+struct Lattice {
+    origin: [u32; 2],
+    spacing: u32,
+    columns: u32,
+    rows: u32,
+}
+
+impl Lattice {
+    /// none if the chunk is too small or too sparse for filling to pay off
+    fn of(positions: &[[u32; 2]]) -> Option<Self> {
+        let (mut min, mut max) = ([u32::MAX; 2], [0; 2]);
+        let mut trailing_zeros = u32::BITS - 1;
+        for &[x, y] in positions {
+            min = [min[0].min(x), min[1].min(y)];
+            max = [max[0].max(x), max[1].max(y)];
+            trailing_zeros = trailing_zeros.min((x | y).trailing_zeros());
+        }
+        let spacing = 1 << trailing_zeros;
+        let lattice = Lattice {
+            origin: min,
+            spacing,
+            columns: max[0].checked_sub(min[0])? / spacing + 1,
+            rows: max[1].checked_sub(min[1])? / spacing + 1,
+        };
+        let cell_count = lattice.columns as usize * lattice.rows as usize;
+        let useful =
+            lattice.columns.min(lattice.rows) >= MIN_FILL_SIDE && positions.len() * 2 >= cell_count;
+        useful.then_some(lattice)
+    }
+
+    fn pos(&self, [column, row]: [u32; 2]) -> [u32; 2] {
+        [
+            self.origin[0] + column * self.spacing,
+            self.origin[1] + row * self.spacing,
+        ]
+    }
+
+    fn cell_index(&self, [column, row]: [u32; 2]) -> usize {
+        (column + row * self.columns) as usize
+    }
+
+    /// the index of the cell at the screen position `pos`
+    fn index(&self, [x, y]: [u32; 2]) -> usize {
+        self.cell_index([
+            (x - self.origin[0]) / self.spacing,
+            (y - self.origin[1]) / self.spacing,
+        ])
+    }
+}
+
+struct Filler<'a> {
+    fractal_func: &'a FractalFunc,
+    fields: &'a [String],
+    lattice: Lattice,
+    /// for every cell of the lattice, whether it is one of the positions
+    requested: Vec<bool>,
+    /// see `requested_sums`
+    requested_sums: Vec<u32>,
+    /// for every cell of the lattice, where its data is in `computed`. filled cells share the
+    /// data of a border cell.
+    cells: Vec<Option<usize>>,
+    computed: RChunk,
+}
+
+impl Filler<'_> {
+    fn fill(&mut self) -> Result<(), String> {
+        // inclusive [first column, first row, last column, last row]
+        let mut rects = vec![[0, 0, self.lattice.columns - 1, self.lattice.rows - 1]];
+        while let Some(rect) = rects.pop() {
+            let [x0, y0, x1, y1] = rect;
+            let (width, height) = (x1 - x0 + 1, y1 - y0 + 1);
+            let border = border_cells(rect);
+            if width <= 2 || height <= 2 || self.requested_in([x0 + 1, y0 + 1, x1 - 1, y1 - 1]) == 0
+            {
+                // nothing inside to fill
+                self.compute_requested(&border)?;
+                continue;
+            }
+            self.compute(&border)?;
+            let interior = (y0 + 1..y1).flat_map(|y| (x0 + 1..x1).map(move |x| [x, y]));
+            if width.min(height) < MIN_FILL_SIDE {
+                self.compute_requested(&interior.collect::<Vec<_>>())?;
+            } else if self.is_uniform(&border) {
+                let source = self.cells[self.lattice.cell_index(border[0])];
+                for cell in interior {
+                    let index = self.lattice.cell_index(cell);
+                    self.cells[index] = self.cells[index].or(source);
+                }
+            } else if width >= height {
+                let middle = x0 + (width - 1) / 2;
+                rects.push([x0, y0, middle, y1]);
+                rects.push([middle, y0, x1, y1]);
+            } else {
+                let middle = y0 + (height - 1) / 2;
+                rects.push([x0, y0, x1, middle]);
+                rects.push([x0, middle, x1, y1]);
+            }
+        }
+        Ok(())
+    }
+
+    /// how many of the lattice cells in the inclusive rectangle were requested
+    fn requested_in(&self, [x0, y0, x1, y1]: [u32; 4]) -> u32 {
+        let stride = self.lattice.columns as usize + 1;
+        let sum = |x: u32, y: u32| self.requested_sums[x as usize + y as usize * stride];
+        sum(x1 + 1, y1 + 1) + sum(x0, y0) - sum(x0, y1 + 1) - sum(x1 + 1, y0)
+    }
+
+    /// like `compute`, for the requested ones of `cells`
+    fn compute_requested(&mut self, cells: &[[u32; 2]]) -> Result<(), String> {
+        let requested: Vec<[u32; 2]> = cells
+            .iter()
+            .copied()
+            .filter(|&cell| self.requested[self.lattice.cell_index(cell)])
+            .collect();
+        self.compute(&requested)
+    }
+
+    /// computes the lattice `cells` that aren't known yet, in one call
+    fn compute(&mut self, cells: &[[u32; 2]]) -> Result<(), String> {
+        let positions: Vec<[u32; 2]> = cells
+            .iter()
+            .filter(|&&cell| self.cells[self.lattice.cell_index(cell)].is_none())
+            .map(|&cell| self.lattice.pos(cell))
+            .collect();
+        if positions.is_empty() {
+            return Ok(());
+        }
+        let chunk = self
+            .fractal_func
+            .compute_cells(&positions)
+            .map_err(|e| format!("fractal func: {}", e))?;
+        check_chunk_positions(&chunk, &positions)?;
+        for (pos, data) in chunk.iter() {
+            self.cells[self.lattice.index(pos)] = Some(self.computed.len());
+            self.computed.push(pos, data);
+        }
+        Ok(())
+    }
+
+    /// whether the computed lattice `cells` all have the same fill-safe fields
+    fn is_uniform(&self, cells: &[[u32; 2]]) -> bool {
+        let mut keys = cells.iter().map(|&cell| {
+            let index = self.cells[self.lattice.cell_index(cell)]?;
+            let (_, data) = self.computed.get(index)?;
+            fill_key(data, self.fields)
+        });
+        match keys.next() {
+            Some(Some(first)) => keys.all(|key| key.as_ref() == Some(&first)),
+            _ => false,
+        }
+    }
+}
+
+/// the values of `fields` in the MessagePack map of a cell, none if one is missing
+fn fill_key(mut data: &[u8], fields: &[String]) -> Option<Vec<Value>> {
+    let value = rmpv::decode::read_value(&mut data).ok()?;
+    let map = value.as_map()?;
+    fields
+        .iter()
+        .map(|field| {
+            map.iter()
+                .find(|(key, _)| key.as_str() == Some(field))
+                .map(|(_, value)| value.clone())
+        })
+        .collect()
+}
+
+/// the number of `true` cells above and left of every corner of a grid with `columns` columns,
+/// row by row, so that the count in any rectangle takes four lookups
+fn requested_sums(cells: &[bool], columns: u32) -> Vec<u32> {
+    let stride = columns as usize + 1;
+    let rows = cells.len() / columns as usize;
+    let mut sums = vec![0; stride * (rows + 1)];
+    for y in 0..rows {
+        let mut row_sum = 0;
+        for x in 0..columns as usize {
+            row_sum += cells[x + y * columns as usize] as u32;
+            sums[(x + 1) + (y + 1) * stride] = sums[(x + 1) + y * stride] + row_sum;
+        }
+    }
+    sums
+}
+
+/// the cells around the edge of an inclusive lattice rectangle, each one once
+fn border_cells([x0, y0, x1, y1]: [u32; 4]) -> Vec<[u32; 2]> {
+    let mut cells: Vec<[u32; 2]> = (x0..=x1).map(|x| [x, y0]).collect();
+    if y1 > y0 {
+        cells.extend((x0..=x1).map(|x| [x, y1]));
+    }
+    for y in y0 + 1..y1 {
+        cells.push([x0, y]);
+        if x1 > x0 {
+            cells.push([x1, y]);
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use abi_stable::{
+        sabi_trait::TD_Opaque,
+        std_types::{RResult, RSlice, RString, Tuple2},
+    };
+    use fractal_func::prelude::{RFractalFunc, RFractalFuncBox};
+
+    use super::*;
+
+    /// a fractal func that notes the cells it computes. with `distinct`, every cell is different,
+    /// so nothing is filled. it answers with the cells `shift` columns to the left.
+    #[derive(Debug, Clone, Default)]
+    struct Recording {
+        computed: Arc<Mutex<Vec<[u32; 2]>>>,
+        distinct: bool,
+        shift: u32,
+    }
+
+    impl Recording {
+        fn func(&self) -> FractalFunc {
+            FractalFunc::from_box(RFractalFuncBox::from_value(self.clone(), TD_Opaque))
+        }
+    }
+
+    impl RFractalFunc for Recording {
+        fn get_size(&self) -> Tuple2<u32, u32> {
+            Tuple2(1024, 1024)
+        }
+
+        fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RResult<RChunk, RString> {
+            self.computed.lock().unwrap().extend(positions.iter());
+            let mut chunk = RChunk::default();
+            for &[x, y] in positions.iter() {
+                let iter = if self.distinct { x + y * 1024 } else { 0 };
+                let cell = Value::Map(vec![(Value::from("iter"), Value::from(iter))]);
+                let mut data = Vec::new();
+                rmpv::encode::write_value(&mut data, &cell).unwrap();
+                chunk.push([x.wrapping_sub(self.shift), y], &data);
+            }
+            RResult::ROk(chunk)
+        }
+
+        fn with_size(&self, _width: u32, _height: u32) -> RFractalFuncBox {
+            RFractalFuncBox::from_value(self.clone(), TD_Opaque)
+        }
+
+        fn with_offset(&self, _dx: i32, _dy: i32) -> RFractalFuncBox {
+            RFractalFuncBox::from_value(self.clone(), TD_Opaque)
+        }
+
+        fn add_zoom(&self, _zoom_factor: f64) -> RFractalFuncBox {
+            RFractalFuncBox::from_value(self.clone(), TD_Opaque)
+        }
+    }
+
+    fn block(origin: [u32; 2], spacing: u32, side: u32) -> Vec<[u32; 2]> {
+        (0..side)
+            .flat_map(|row| (0..side).map(move |column| [column, row]))
+            .map(|[column, row]| [origin[0] + column * spacing, origin[1] + row * spacing])
+            .collect()
+    }
+
+    #[test]
+    fn lattice_follows_the_spacing_of_the_pass() {
+        let dense = Lattice::of(&block([3, 5], 1, 8)).unwrap();
+        let pass = Lattice::of(&block([16, 8], 8, 6)).unwrap();
+
+        assert_eq!((dense.origin, dense.spacing), ([3, 5], 1));
+        assert_eq!((dense.columns, dense.rows), (8, 8));
+        assert_eq!((pass.origin, pass.spacing), ([16, 8], 8));
+        assert_eq!((pass.columns, pass.rows), (6, 6));
+        assert_eq!(pass.pos([2, 3]), [32, 32]);
+        assert_eq!(pass.index([32, 32]), pass.cell_index([2, 3]));
+    }
+
+    #[test]
+    fn small_or_sparse_chunks_are_not_filled() {
+        let sparse: Vec<_> = block([0, 0], 1, 8).into_iter().step_by(3).collect();
+
+        assert!(Lattice::of(&[]).is_none());
+        assert!(Lattice::of(&[[4, 4]]).is_none());
+        assert!(Lattice::of(&block([0, 0], 1, MIN_FILL_SIDE - 1)).is_none());
+        assert!(Lattice::of(&sparse).is_none());
+        assert!(Lattice::of(&[[0, 0], [0, 64], [64, 0], [64, 64]]).is_none());
+    }
+
+    #[test]
+    fn border_cells_are_each_listed_once() {
+        let border = border_cells([1, 2, 4, 5]);
+        let unique: HashSet<_> = border.iter().copied().collect();
+
+        assert_eq!(border.len(), 12);
+        assert_eq!(unique.len(), border.len());
+        assert!(border
+            .iter()
+            .all(|&[x, y]| x == 1 || x == 4 || y == 2 || y == 5));
+        assert_eq!(border_cells([0, 3, 3, 3]).len(), 4);
+        assert_eq!(border_cells([2, 0, 2, 3]).len(), 4);
+        assert_eq!(border_cells([2, 2, 2, 2]), vec![[2, 2]]);
+    }
+
+    #[test]
+    fn fill_key_reads_the_named_fields() {
+        let cell = Value::Map(vec![
+            (Value::from("iterations"), Value::from(12)),
+            (Value::from("outside"), Value::from(true)),
+            (Value::from("distance"), Value::from(0.5)),
+        ]);
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &cell).unwrap();
+        let fields = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            fill_key(&data, &fields(&["outside", "iterations"])),
+            Some(vec![Value::from(true), Value::from(12)])
+        );
+        assert_eq!(fill_key(&data, &fields(&["iterations", "missing"])), None);
+        assert_eq!(fill_key(&[0xc1], &fields(&["iterations"])), None);
+    }
+
+    #[test]
+    fn uniform_rectangles_are_filled() {
+        let recording = Recording::default();
+        let positions = block([8, 8], 2, 32);
+        let chunk = compute_cells_filled(&recording.func(), &positions, &["iter".to_owned()]);
+
+        assert_eq!(chunk.unwrap().positions().collect::<Vec<_>>(), positions);
+        // just the border
+        assert_eq!(recording.computed.lock().unwrap().len(), 4 * 31);
+    }
+
+    #[test]
+    fn only_the_requested_cells_and_their_borders_are_computed() {
+        let recording = Recording {
+            distinct: true,
+            ..Default::default()
+        };
+        // a chunk with its bottom right quadrant rendered already
+        let positions: Vec<_> = block([0, 0], 1, 16)
+            .into_iter()
+            .filter(|&[x, y]| x < 8 || y < 8)
+            .collect();
+        let chunk = compute_cells_filled(&recording.func(), &positions, &["iter".to_owned()]);
+        let computed = recording.computed.lock().unwrap();
+        let unique: HashSet<_> = computed.iter().collect();
+
+        assert_eq!(chunk.unwrap().positions().collect::<Vec<_>>(), positions);
+        assert_eq!(unique.len(), computed.len());
+        assert!(computed.len() < 16 * 16, "{}", computed.len());
+        assert!(!unique.contains(&[12, 12]));
+    }
+
+    #[test]
+    fn cells_at_other_positions_are_refused() {
+        let recording = Recording {
+            shift: 1,
+            ..Default::default()
+        };
+        let positions = block([8, 8], 1, 8);
+        let err = compute_cells_filled(&recording.func(), &positions, &["iter".to_owned()]);
+
+        assert!(err.unwrap_err().contains("wasn't requested"));
+    }
+}
@@ -0,0 +1,88 @@
+//! plugins that are linked into the host, so that it works without any library on disk.
+//! they are selected with a library path like `builtin:mandelbrot_f64`, and any library from a
+//! file overrides them.
+
+use std::path::{Path, PathBuf};
+
+use abi_stable::library::RootModule;
+use color_func::prelude::ColorLib_Ref;
+use fractal_func::prelude::FractalLib_Ref;
+
+use crate::plugin_dir::{PluginInfo, PluginKind};
+
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+/// the name of a built-in plugin and the function creating its root module
+pub type BuiltinPlugin<M> = (&'static str, fn() -> M);
+
+/// a root module type that has built-in implementations
+pub trait BuiltinPlugins: RootModule {
+    const KIND: PluginKind;
+
+    fn builtin_plugins() -> &'static [BuiltinPlugin<Self>];
+
+    fn builtin(name: &str) -> Option<Self> {
+        Self::builtin_plugins()
+            .iter()
+            .find(|(builtin_name, _)| *builtin_name == name)
+            .map(|(_, root_module)| root_module())
+    }
+}
+
+impl BuiltinPlugins for FractalLib_Ref {
+    const KIND: PluginKind = PluginKind::Fractal;
+
+    fn builtin_plugins() -> &'static [BuiltinPlugin<Self>] {
+        &[("mandelbrot_f64", mandelbrot_f64::fractal_lib)]
+    }
+}
+
+impl BuiltinPlugins for ColorLib_Ref {
+    const KIND: PluginKind = PluginKind::Color;
+
+    fn builtin_plugins() -> &'static [BuiltinPlugin<Self>] {
+        &[
+            ("color_luma_basic", color_luma_basic::color_lib),
+            ("color_script", color_script::color_lib),
+        ]
+    }
+}
+
+/// the name of the built-in plugin that `path` refers to, if it refers to one
+pub fn builtin_name(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix(BUILTIN_PREFIX)
+}
+
+pub fn builtin_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}", BUILTIN_PREFIX, name))
+}
+
+/// the path of the first built-in plugin of the given kind
+pub fn default_builtin_path(kind: PluginKind) -> PathBuf {
+    let name = match kind {
+        PluginKind::Fractal => FractalLib_Ref::builtin_plugins()[0].0,
+        PluginKind::Color => ColorLib_Ref::builtin_plugins()[0].0,
+    };
+    builtin_path(name)
+}
+
+/// all built-in plugins, listed like the libraries in the plugin directory
+pub fn builtin_plugin_infos() -> Vec<PluginInfo> {
+    let mut infos = builtin_infos::<FractalLib_Ref>();
+    infos.extend(builtin_infos::<ColorLib_Ref>());
+    infos
+}
+
+fn builtin_infos<M: BuiltinPlugins>() -> Vec<PluginInfo> {
+    M::builtin_plugins()
+        .iter()
+        .map(|(name, _)| PluginInfo {
+            path: builtin_path(name),
+            name: name.to_string(),
+            kind: Some(M::KIND),
+            interface_version: Some(M::VERSION_STRINGS.version.as_str().to_owned()),
+            size: 0,
+            modified: None,
+        })
+        .collect()
+}
@@ -0,0 +1,243 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// the order that the chunks of a render are handed to the threads in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChunkOrder {
+    /// a spiral out from the middle of the screen
+    #[default]
+    CenterOut,
+    /// a spiral out from the mouse cursor, or from the middle when it isn't over the screen
+    NearCursor,
+    /// along a Hilbert curve, so that chunks that follow each other are neighbors
+    Hilbert,
+    /// row by row from the top
+    Scanline,
+    /// shuffled, the same seed gives the same order
+    Random,
+}
+
+impl ChunkOrder {
+    pub const ALL: [ChunkOrder; 5] = [
+        ChunkOrder::CenterOut,
+        ChunkOrder::NearCursor,
+        ChunkOrder::Hilbert,
+        ChunkOrder::Scanline,
+        ChunkOrder::Random,
+    ];
+}
+
+/// everything that decides the order of the chunks of a render
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkSchedule {
+    pub order: ChunkOrder,
+    /// for `ChunkOrder::Random`
+    pub seed: u64,
+    /// for `ChunkOrder::NearCursor`, in cells
+    pub cursor: Option<[u32; 2]>,
+}
+
+impl ChunkSchedule {
+    /// sorts `chunks` so that the ones to render first come first
+    pub fn sort(&self, chunks: &mut [Vec<[u32; 2]>], width: u32, height: u32) {
+        let middle = [width / 2, height / 2];
+        // the chunks at the edges can be smaller
+        let extent = chunks
+            .iter()
+            .map(|chunk| Bounds::of(chunk).extent())
+            .max()
+            .unwrap_or(1);
+        match self.order {
+            ChunkOrder::CenterOut => sort_outwards(chunks, middle, extent),
+            ChunkOrder::NearCursor => {
+                let cursor = self.cursor.filter(|&[x, y]| x < width && y < height);
+                sort_outwards(chunks, cursor.unwrap_or(middle), extent)
+            }
+            ChunkOrder::Hilbert => {
+                let side = (width.max(height) / extent + 1).next_power_of_two();
+                chunks.sort_by_cached_key(|chunk| {
+                    let [x, y] = Bounds::of(chunk).center();
+                    hilbert_index(side, x as u32 / extent, y as u32 / extent)
+                });
+            }
+            ChunkOrder::Scanline => chunks.sort_by_cached_key(|chunk| {
+                let [x, y] = Bounds::of(chunk).min;
+                (y, x)
+            }),
+            ChunkOrder::Random => chunks.shuffle(&mut StdRng::seed_from_u64(self.seed)),
+        }
+    }
+}
+
+/// rings of chunks around `focus`, each one clockwise
+fn sort_outwards(chunks: &mut [Vec<[u32; 2]>], focus: [u32; 2], extent: u32) {
+    chunks.sort_by_cached_key(|chunk| {
+        let [x, y] = Bounds::of(chunk).center();
+        let (dx, dy) = (x - focus[0] as f64, y - focus[1] as f64);
+        let ring = (dx.abs().max(dy.abs()) / extent as f64).round() as u64;
+        let angle = (dy.atan2(dx) * 1e6) as i64;
+        (ring, angle)
+    });
+}
+
+/// the cells that a chunk spans
+struct Bounds {
+    min: [u32; 2],
+    max: [u32; 2],
+}
+
+impl Bounds {
+    fn of(chunk: &[[u32; 2]]) -> Self {
+        let mut bounds = Bounds {
+            min: [u32::MAX; 2],
+            max: [0; 2],
+        };
+        for &[x, y] in chunk {
+            bounds.min = [bounds.min[0].min(x), bounds.min[1].min(y)];
+            bounds.max = [bounds.max[0].max(x), bounds.max[1].max(y)];
+        }
+        bounds
+    }
+
+    fn center(&self) -> [f64; 2] {
+        [0, 1].map(|i| (self.min[i] as f64 + self.max[i] as f64) / 2.0)
+    }
+
+    fn extent(&self) -> u32 {
+        (self.max[0] - self.min[0]).max(self.max[1] - self.min[1]) + 1
+    }
+}
+
+/// the distance along a Hilbert curve through a `side` by `side` grid, `side` is a power of two
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant, so that the curve inside it lines up with its neighbors
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the `size` by `size` chunks of a `width` by `height` screen, in scanline order
+    fn grid(width: u32, height: u32, size: u32) -> Vec<Vec<[u32; 2]>> {
+        let mut chunks = Vec::new();
+        for y0 in (0..height).step_by(size as usize) {
+            for x0 in (0..width).step_by(size as usize) {
+                let chunk = (y0..(y0 + size).min(height))
+                    .flat_map(|y| (x0..(x0 + size).min(width)).map(move |x| [x, y]))
+                    .collect();
+                chunks.push(chunk);
+            }
+        }
+        chunks
+    }
+
+    fn sorted(order: ChunkOrder, cursor: Option<[u32; 2]>) -> Vec<Vec<[u32; 2]>> {
+        let mut chunks = grid(96, 64, 16);
+        let schedule = ChunkSchedule {
+            order,
+            seed: 7,
+            cursor,
+        };
+        schedule.sort(&mut chunks, 96, 64);
+        chunks
+    }
+
+    fn is_neighbor(a: &[[u32; 2]], b: &[[u32; 2]]) -> bool {
+        let (a, b) = (Bounds::of(a).min, Bounds::of(b).min);
+        a[0].abs_diff(b[0]) + a[1].abs_diff(b[1]) == 16
+    }
+
+    #[test]
+    fn hilbert_index_visits_every_cell_once_between_neighbors() {
+        for side in [1, 2, 4, 8] {
+            let mut cells = vec![[0; 2]; (side * side) as usize];
+            for y in 0..side {
+                for x in 0..side {
+                    cells[hilbert_index(side, x, y) as usize] = [x, y];
+                }
+            }
+            assert_eq!(cells[0], [0, 0]);
+            for pair in cells.windows(2) {
+                let [[x0, y0], [x1, y1]] = [pair[0], pair[1]];
+                assert_eq!(x0.abs_diff(x1) + y0.abs_diff(y1), 1, "{:?}", pair);
+            }
+        }
+    }
+
+    #[test]
+    fn hilbert_order_moves_between_neighboring_chunks() {
+        let mut chunks = grid(64, 64, 16);
+        let schedule = ChunkSchedule {
+            order: ChunkOrder::Hilbert,
+            ..Default::default()
+        };
+        schedule.sort(&mut chunks, 64, 64);
+
+        assert_eq!(Bounds::of(&chunks[0]).min, [0, 0]);
+        assert!(chunks
+            .windows(2)
+            .all(|pair| is_neighbor(&pair[0], &pair[1])));
+    }
+
+    #[test]
+    fn spirals_start_at_their_focus_and_move_outwards() {
+        let center_out = sorted(ChunkOrder::CenterOut, None);
+        let near_cursor = sorted(ChunkOrder::NearCursor, Some([90, 60]));
+        let ring = |chunk: &[[u32; 2]], focus: [f64; 2]| {
+            let [x, y] = Bounds::of(chunk).center();
+            ((x - focus[0]).abs().max((y - focus[1]).abs()) / 16.0).round()
+        };
+
+        let middle = Bounds::of(&center_out[0]).min;
+
+        assert_eq!(middle, [48, 32]);
+        assert!(center_out[1..9].iter().all(|chunk| {
+            let [x, y] = Bounds::of(chunk).min;
+            x.abs_diff(middle[0]).max(y.abs_diff(middle[1])) == 16
+        }));
+        assert!(center_out
+            .windows(2)
+            .all(|pair| ring(&pair[0], [48.0, 32.0]) <= ring(&pair[1], [48.0, 32.0])));
+        assert_eq!(Bounds::of(&near_cursor[0]).min, [80, 48]);
+        assert!(near_cursor
+            .windows(2)
+            .all(|pair| ring(&pair[0], [90.0, 60.0]) <= ring(&pair[1], [90.0, 60.0])));
+    }
+
+    #[test]
+    fn cursor_outside_the_screen_spirals_from_the_middle() {
+        assert_eq!(
+            sorted(ChunkOrder::NearCursor, Some([200, 10])),
+            sorted(ChunkOrder::CenterOut, None)
+        );
+        assert_eq!(
+            sorted(ChunkOrder::NearCursor, None),
+            sorted(ChunkOrder::CenterOut, None)
+        );
+    }
+
+    #[test]
+    fn scanline_and_random_orders() {
+        let random = sorted(ChunkOrder::Random, None);
+
+        assert_eq!(sorted(ChunkOrder::Scanline, None), grid(96, 64, 16));
+        assert_eq!(random, sorted(ChunkOrder::Random, None));
+        assert_ne!(random, grid(96, 64, 16));
+    }
+}
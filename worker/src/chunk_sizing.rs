@@ -0,0 +1,194 @@
+use std::time::Duration;
+
+use fractal_func::RChunk;
+
+/// the side of the square regions that cell costs are kept for, in cells
+const REGION_SIZE: u32 = 16;
+/// adaptive chunks are split until they are expected to take about this long on one thread
+const TARGET_CHUNK_TIME: Duration = Duration::from_millis(10);
+/// the range of adaptive chunk sizes, per side in cells
+pub const MIN_CHUNK_SIZE: u32 = 8;
+pub const MAX_CHUNK_SIZE: u32 = 128;
+
+/// how long a cell takes to render in each region of the screen, as measured on the chunks of
+/// earlier renders. the regions move along with pans, zooms and resizes, so that the costs of a
+/// view carry over to the next one.
+#[derive(Debug, Clone, Default)]
+pub struct CellCosts {
+    width: u32,
+    height: u32,
+    columns: u32,
+    /// seconds per cell, NaN where nothing was measured
+    costs: Vec<f32>,
+}
+
+impl CellCosts {
+    pub fn new(width: u32, height: u32) -> Self {
+        let columns = width.div_ceil(REGION_SIZE);
+        let rows = height.div_ceil(REGION_SIZE);
+        Self {
+            width,
+            height,
+            columns,
+            costs: vec![f32::NAN; (columns * rows) as usize],
+        }
+    }
+
+    fn index(&self, [x, y]: [u32; 2]) -> Option<usize> {
+        (x < self.width && y < self.height)
+            .then(|| ((x / REGION_SIZE) + (y / REGION_SIZE) * self.columns) as usize)
+    }
+
+    pub fn record(&mut self, chunk: &RChunk, elapsed: Duration) {
+        if chunk.is_empty() {
+            return;
+        }
+        let cost = elapsed.as_secs_f32() / chunk.len() as f32;
+        for pos in chunk.positions() {
+            if let Some(index) = self.index(pos) {
+                self.costs[index] = cost;
+            }
+        }
+    }
+
+    /// the costs for a screen of `width` by `height`, after the old one moved by `offset` cells
+    /// and was then zoomed into around the middle by `zoom_factor`
+    pub fn moved(&self, width: u32, height: u32, offset: [i32; 2], zoom_factor: f64) -> Self {
+        let mut moved = Self::new(width, height);
+        let middle = [width / 2, height / 2].map(f64::from);
+        for (index, cost) in moved.costs.iter_mut().enumerate() {
+            let region = [index as u32 % moved.columns, index as u32 / moved.columns];
+            let old_pos = [0, 1].map(|i| {
+                let center = (region[i] * REGION_SIZE + REGION_SIZE / 2) as f64;
+                middle[i] + (center - middle[i]) / zoom_factor - offset[i] as f64
+            });
+            if old_pos.iter().all(|&v| v >= 0.0) {
+                if let Some(old_index) = self.index(old_pos.map(|v| v as u32)) {
+                    *cost = self.costs[old_index];
+                }
+            }
+        }
+        moved
+    }
+
+    /// the expected time for `positions`, if enough of them are in regions that were measured
+    fn estimate(&self, positions: &[[u32; 2]]) -> Option<f32> {
+        let (mut sum, mut known) = (0.0, 0);
+        for &pos in positions {
+            match self.index(pos).map(|index| self.costs[index]) {
+                Some(cost) if !cost.is_nan() => {
+                    sum += cost;
+                    known += 1;
+                }
+                _ => (),
+            }
+        }
+        (known * 2 >= positions.len()).then(|| sum / known as f32 * positions.len() as f32)
+    }
+
+    /// splits the cells of the square tile at `origin` into quadrants until each one is expected
+    /// to take about `TARGET_CHUNK_TIME`. where nothing was measured, it splits down to
+    /// `fallback_size` instead. the cells are every `spacing`th one, like in a progressive pass.
+    pub fn split_tile(
+        &self,
+        positions: Vec<[u32; 2]>,
+        origin: [u32; 2],
+        side: u32,
+        spacing: u32,
+        fallback_size: u32,
+        chunks: &mut Vec<Vec<[u32; 2]>>,
+    ) {
+        if positions.is_empty() {
+            return;
+        }
+        let can_split = side > MIN_CHUNK_SIZE * spacing;
+        let split = can_split
+            && match self.estimate(&positions) {
+                Some(time) => time > TARGET_CHUNK_TIME.as_secs_f32(),
+                None => side > fallback_size * spacing,
+            };
+        if !split {
+            chunks.push(positions);
+            return;
+        }
+        let half = side / 2;
+        let mut quadrants: [Vec<[u32; 2]>; 4] = Default::default();
+        for pos in positions {
+            let right = pos[0] >= origin[0] + half;
+            let bottom = pos[1] >= origin[1] + half;
+            quadrants[right as usize + 2 * bottom as usize].push(pos);
+        }
+        for (quadrant, positions) in quadrants.into_iter().enumerate() {
+            let origin = [
+                origin[0] + half * (quadrant as u32 & 1),
+                origin[1] + half * (quadrant as u32 >> 1),
+            ];
+            self.split_tile(positions, origin, half, spacing, fallback_size, chunks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(origin: [u32; 2], side: u32, spacing: u32) -> Vec<[u32; 2]> {
+        (0..side / spacing)
+            .flat_map(|row| (0..side / spacing).map(move |column| [column, row]))
+            .map(|[column, row]| [origin[0] + column * spacing, origin[1] + row * spacing])
+            .collect()
+    }
+
+    fn chunk(positions: &[[u32; 2]]) -> RChunk {
+        let mut chunk = RChunk::default();
+        for &pos in positions {
+            chunk.push(pos, &[]);
+        }
+        chunk
+    }
+
+    fn split(costs: &CellCosts, origin: [u32; 2], side: u32, spacing: u32) -> Vec<Vec<[u32; 2]>> {
+        let mut chunks = Vec::new();
+        let positions = square(origin, side, spacing);
+        costs.split_tile(positions, origin, side, spacing, 16, &mut chunks);
+        chunks
+    }
+
+    #[test]
+    fn unmeasured_tiles_split_down_to_the_fallback_size() {
+        let costs = CellCosts::new(256, 256);
+        let dense = split(&costs, [0, 0], 64, 1);
+        let pass = split(&costs, [128, 0], 128, 4);
+
+        assert_eq!(dense.len(), 16);
+        assert!(dense.iter().all(|chunk| chunk.len() == 16 * 16));
+        assert_eq!(pass.len(), 4);
+        assert!(pass.iter().all(|chunk| chunk.len() == 16 * 16));
+    }
+
+    #[test]
+    fn tiles_split_by_their_measured_cost() {
+        let mut costs = CellCosts::new(256, 128);
+        costs.record(&chunk(&square([0, 0], 128, 1)), Duration::from_millis(1));
+        costs.record(&chunk(&square([128, 0], 128, 1)), Duration::from_secs(10));
+        let cheap = split(&costs, [0, 0], 128, 1);
+        let expensive = split(&costs, [128, 0], 128, 1);
+
+        assert_eq!(cheap.len(), 1);
+        assert_eq!(expensive.len(), (128 / MIN_CHUNK_SIZE).pow(2) as usize);
+        assert_eq!(expensive.concat().len(), 128 * 128);
+    }
+
+    #[test]
+    fn costs_move_with_the_view() {
+        let mut costs = CellCosts::new(64, 64);
+        costs.record(&chunk(&square([0, 0], 16, 1)), Duration::from_secs(1));
+        let panned = costs.moved(64, 64, [16, 0], 1.0);
+        let zoomed = costs.moved(64, 64, [0, 0], 2.0);
+
+        assert_eq!(costs.estimate(&square([0, 0], 16, 1)), Some(1.0));
+        assert_eq!(panned.estimate(&square([0, 0], 16, 1)), None);
+        assert_eq!(panned.estimate(&square([16, 0], 16, 1)), Some(1.0));
+        assert_eq!(zoomed.estimate(&square([0, 0], 16, 1)), None);
+    }
+}
@@ -0,0 +1,591 @@
+use std::cmp::min;
+
+use color_func::{linear_to_srgb, RColor};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapOperator {
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 3] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::Aces,
+    ];
+
+    fn apply(self, v: f32) -> f32 {
+        match self {
+            ToneMapOperator::Clamp => v,
+            ToneMapOperator::Reinhard => v / (1.0 + v),
+            ToneMapOperator::Aces => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    pub exposure: f32,
+    pub dither: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Clamp,
+            exposure: 1.0,
+            dither: true,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// linear rgba -> sRGB encoded rgba, all in 0 to 1
+    pub fn map(&self, rgba: [f32; 4]) -> [f32; 4] {
+        let [r, g, b, a] = rgba;
+        let channel = |v: f32| {
+            let v = self.operator.apply((v * self.exposure).max(0.0));
+            linear_to_srgb(v.clamp(0.0, 1.0))
+        };
+        [channel(r), channel(g), channel(b), a.clamp(0.0, 1.0)]
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// ordered dither offset for a pixel, in (-0.5, 0.5) of one 8-bit step
+fn dither_offset(x: u32, y: u32) -> f32 {
+    (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0 - 0.5
+}
+
+/// see `ColorBuffer::high_contrast_pixels`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Refinement {
+    /// no refinement looked at the pixel since it was drawn
+    Pending,
+    /// a refinement found it to be no high contrast pixel
+    Unneeded,
+    /// it has a refined color
+    Done,
+}
+
+/// the full-precision colors of everything drawn so far, in linear light.
+/// the 8-bit screen buffer is derived from this by tone mapping and dithering.
+#[derive(Debug, Clone, Default)]
+pub struct ColorBuffer {
+    width: u32,
+    height: u32,
+    /// linear rgba, straight alpha. alpha 0 means nothing was drawn there (yet)
+    pixels: Vec<[f32; 4]>,
+    /// the size of the block that each pixel was filled from, 1 for a pixel of its own cell and
+    /// 0 for nothing
+    block_sizes: Vec<u8>,
+    /// how far the refinement of each pixel got, see `high_contrast_pixels`
+    refinement: Vec<Refinement>,
+}
+
+impl ColorBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; (width * height) as usize],
+            block_sizes: vec![0; (width * height) as usize],
+            refinement: vec![Refinement::Pending; (width * height) as usize],
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill([0.0; 4]);
+        self.block_sizes.fill(0);
+        self.refinement.fill(Refinement::Pending);
+    }
+
+    /// changes the size, with everything moved by `offset` pixels like in `translate`
+    pub fn resize(&mut self, width: u32, height: u32, offset: [i32; 2]) {
+        let old_size = (self.width, self.height);
+        *self = Self {
+            width,
+            height,
+            pixels: resize_grid(&self.pixels, old_size, (width, height), offset, [0.0; 4]),
+            block_sizes: resize_grid(&self.block_sizes, old_size, (width, height), offset, 0),
+            refinement: resize_grid(
+                &self.refinement,
+                old_size,
+                (width, height),
+                offset,
+                Refinement::Pending,
+            ),
+        };
+    }
+
+    /// moves everything by `offset` pixels, what moves in from outside is blank
+    pub fn translate(&mut self, offset: [i32; 2]) {
+        translate_grid(
+            &mut self.pixels,
+            self.width as usize,
+            self.height as usize,
+            offset.map(i64::from),
+            [0.0; 4],
+        );
+        translate_grid(
+            &mut self.block_sizes,
+            self.width as usize,
+            self.height as usize,
+            offset.map(i64::from),
+            0,
+        );
+        translate_grid(
+            &mut self.refinement,
+            self.width as usize,
+            self.height as usize,
+            offset.map(i64::from),
+            Refinement::Pending,
+        );
+    }
+
+    /// moves the display values in `screen` like `translate` moves the colors, which is much
+    /// cheaper than a redraw. only the dither pattern moves along.
+    pub fn translate_screen(&self, offset: [i32; 2], screen: &mut [u8]) {
+        let [dx, dy] = offset.map(i64::from);
+        translate_grid(
+            screen,
+            self.width as usize * 4,
+            self.height as usize,
+            [dx * 4, dy],
+            0,
+        );
+    }
+
+    /// writes `colors` into the buffer, and their display values into `screen`. with a
+    /// `block_size` above 1, the cells of a progressive render also fill their block (see
+    /// `cell_block_size`), except for the pixels that a finer block or a cell of their own
+    /// already filled. colors outside the screen are dropped.
+    pub fn draw_colors(
+        &mut self,
+        colors: &[RColor],
+        block_size: u32,
+        tone_mapping: &ToneMapping,
+        screen: &mut [u8],
+    ) {
+        for rcolor in colors {
+            let [x, y] = rcolor.pos;
+            if x >= self.width || y >= self.height {
+                continue;
+            }
+            let rgba = rcolor.value.to_linear();
+            let cell_block_size = cell_block_size(rcolor.pos, block_size);
+            if cell_block_size > 1 {
+                for py in y..min(y + cell_block_size, self.height) {
+                    for px in x..min(x + cell_block_size, self.width) {
+                        let filled = self.block_sizes[(px + py * self.width) as usize];
+                        if filled == 0 || filled as u32 > cell_block_size {
+                            self.draw_pixel(
+                                px,
+                                py,
+                                rgba,
+                                cell_block_size as u8,
+                                tone_mapping,
+                                screen,
+                            );
+                        }
+                    }
+                }
+            }
+            self.draw_pixel(x, y, rgba, 1, tone_mapping, screen);
+        }
+    }
+
+    /// writes refined linear colors that replace the color of a pixel's own cell, until the cell
+    /// is drawn again
+    pub fn draw_pixels(
+        &mut self,
+        pixels: &[([u32; 2], [f32; 4])],
+        tone_mapping: &ToneMapping,
+        screen: &mut [u8],
+    ) {
+        for &([x, y], rgba) in pixels {
+            if x < self.width && y < self.height {
+                self.draw_pixel(x, y, rgba, 1, tone_mapping, screen);
+                self.refinement[(x + y * self.width) as usize] = Refinement::Done;
+            }
+        }
+    }
+
+    /// the linear color of the pixel at `pos`, if its own cell was drawn there
+    pub fn get_pixel(&self, [x, y]: [u32; 2]) -> Option<[f32; 4]> {
+        let idx = (x + y * self.width) as usize;
+        (self.block_sizes[idx] == 1).then(|| self.pixels[idx])
+    }
+
+    /// the pixels of their own cell whose tone mapped color differs from a neighbor's by more
+    /// than `threshold` in a channel, all in 0 to 1, and that weren't refined yet. only the
+    /// neighbors of pixels that no refinement looked at yet are compared, see `settle`.
+    pub fn high_contrast_pixels(
+        &self,
+        tone_mapping: &ToneMapping,
+        threshold: f32,
+    ) -> Vec<[u32; 2]> {
+        let mapped = self
+            .pixels
+            .iter()
+            .zip(&self.block_sizes)
+            .map(|(rgba, &block_size)| (block_size == 1).then(|| tone_mapping.map(*rgba)))
+            .collect_vec();
+        let pending = |idx: usize| self.refinement[idx] == Refinement::Pending;
+        let differs = |a: usize, b: usize| match (mapped[a], mapped[b]) {
+            (Some(a_rgba), Some(b_rgba)) if pending(a) || pending(b) => a_rgba
+                .iter()
+                .zip(b_rgba)
+                .any(|(a, b)| (a - b).abs() > threshold),
+            _ => false,
+        };
+        let mut marked = vec![false; mapped.len()];
+        let width = self.width as usize;
+        for idx in 0..mapped.len() {
+            let right = idx + 1;
+            if right % width != 0 && differs(idx, right) {
+                marked[idx] = true;
+                marked[right] = true;
+            }
+            let below = idx + width;
+            if below < mapped.len() && differs(idx, below) {
+                marked[idx] = true;
+                marked[below] = true;
+            }
+        }
+        marked
+            .iter()
+            .zip(&self.refinement)
+            .enumerate()
+            .filter(|(_, (&marked, &refinement))| marked && refinement != Refinement::Done)
+            .map(|(idx, _)| [(idx % width) as u32, (idx / width) as u32])
+            .collect()
+    }
+
+    /// notes that a refinement looked at the pixels of their own cell and found `high_contrast`,
+    /// so that the others are only compared again once a neighbor changes
+    pub fn settle(&mut self, high_contrast: &[[u32; 2]]) {
+        for (refinement, &block_size) in self.refinement.iter_mut().zip(&self.block_sizes) {
+            if *refinement == Refinement::Pending && block_size == 1 {
+                *refinement = Refinement::Unneeded;
+            }
+        }
+        for &[x, y] in high_contrast {
+            let idx = (x + y * self.width) as usize;
+            if self.refinement[idx] == Refinement::Unneeded {
+                self.refinement[idx] = Refinement::Pending;
+            }
+        }
+    }
+
+    fn draw_pixel(
+        &mut self,
+        x: u32,
+        y: u32,
+        rgba: [f32; 4],
+        block_size: u8,
+        tone_mapping: &ToneMapping,
+        screen: &mut [u8],
+    ) {
+        let idx = (x + y * self.width) as usize;
+        self.pixels[idx] = rgba;
+        self.block_sizes[idx] = block_size;
+        self.refinement[idx] = Refinement::Pending;
+        screen[idx * 4..idx * 4 + 4].copy_from_slice(&to_display(rgba, x, y, tone_mapping));
+    }
+
+    /// rewrites the whole screen, eg. after the tone mapping changed
+    pub fn redraw(&self, tone_mapping: &ToneMapping, screen: &mut [u8]) {
+        for (idx, (rgba, out)) in self
+            .pixels
+            .iter()
+            .zip(screen.chunks_exact_mut(4))
+            .enumerate()
+        {
+            let x = idx as u32 % self.width;
+            let y = idx as u32 / self.width;
+            out.copy_from_slice(&to_display(*rgba, x, y, tone_mapping));
+        }
+    }
+
+    /// the linear colors as they were drawn, not tone mapped, so values above 1 are kept.
+    /// pixels that nothing was drawn to yet are transparent black.
+    pub fn to_linear_rgba(&self) -> Vec<f32> {
+        self.pixels
+            .iter()
+            .flat_map(|rgba| if rgba[3] <= 0.0 { [0.0; 4] } else { *rgba })
+            .collect()
+    }
+
+    /// tone mapped but not dithered, with 16 bits per channel
+    pub fn to_rgba16(&self, tone_mapping: &ToneMapping) -> Vec<u16> {
+        self.pixels
+            .iter()
+            .flat_map(|rgba| {
+                if rgba[3] <= 0.0 {
+                    return [0; 4];
+                }
+                tone_mapping
+                    .map(*rgba)
+                    .map(|v| (v * 65535.0).round() as u16)
+            })
+            .collect()
+    }
+}
+
+/// the size of the block that the cell at `pos` stands in for, in a progressive render that
+/// starts with every `block_size`th cell and halves the spacing with every pass. `block_size` is
+/// a power of two, and 1 for a render that isn't progressive.
+pub fn cell_block_size([x, y]: [u32; 2], block_size: u32) -> u32 {
+    1 << (x | y).trailing_zeros().min(block_size.trailing_zeros())
+}
+
+/// a row major grid of `new_size`, with the cells of `cells` moved by `offset`
+fn resize_grid<T: Copy>(
+    cells: &[T],
+    old_size: (u32, u32),
+    new_size: (u32, u32),
+    offset: [i32; 2],
+    blank: T,
+) -> Vec<T> {
+    let mut resized = vec![blank; (new_size.0 * new_size.1) as usize];
+    let [dx, dy] = offset.map(i64::from);
+    let (old_width, new_width) = (old_size.0 as i64, new_size.0 as i64);
+    let xs = (-dx).max(0)..old_width.min(new_width - dx);
+    if !xs.is_empty() {
+        let row_len = (xs.end - xs.start) as usize;
+        for y in (-dy).max(0)..(old_size.1 as i64).min(new_size.1 as i64 - dy) {
+            let src = (y * old_width + xs.start) as usize;
+            let dst = ((y + dy) * new_width + xs.start + dx) as usize;
+            resized[dst..dst + row_len].copy_from_slice(&cells[src..src + row_len]);
+        }
+    }
+    resized
+}
+
+/// moves the cells of a row major grid by `offset`, in place
+fn translate_grid<T: Copy>(
+    cells: &mut [T],
+    width: usize,
+    height: usize,
+    offset: [i64; 2],
+    blank: T,
+) {
+    let [dx, dy] = offset;
+    if dx.unsigned_abs() as usize >= width || dy.unsigned_abs() as usize >= height {
+        cells.fill(blank);
+        return;
+    }
+    let height = height as i64;
+    let row_len = width - dx.unsigned_abs() as usize;
+    let (src_x, dst_x) = (dx.min(0).unsigned_abs() as usize, dx.max(0) as usize);
+    // going against the direction of the move, so that no row is overwritten before it moved
+    let rows = dy.max(0)..height + dy.min(0);
+    let rows: Box<dyn Iterator<Item = i64>> = if dy > 0 {
+        Box::new(rows.rev())
+    } else {
+        Box::new(rows)
+    };
+    for dst_y in rows {
+        let src_row = (dst_y - dy) as usize * width;
+        let dst_row = dst_y as usize * width;
+        cells.copy_within(src_row + src_x..src_row + src_x + row_len, dst_row + dst_x);
+        cells[dst_row..dst_row + dst_x].fill(blank);
+        cells[dst_row + dst_x + row_len..dst_row + width].fill(blank);
+    }
+    let exposed_rows = if dy > 0 {
+        0..dy as usize
+    } else {
+        (height + dy) as usize..height as usize
+    };
+    cells[exposed_rows.start * width..exposed_rows.end * width].fill(blank);
+}
+
+fn to_display(rgba: [f32; 4], x: u32, y: u32, tone_mapping: &ToneMapping) -> [u8; 4] {
+    if rgba[3] <= 0.0 {
+        return [0; 4];
+    }
+    let offset = if tone_mapping.dither {
+        dither_offset(x, y)
+    } else {
+        0.0
+    };
+    tone_mapping
+        .map(rgba)
+        .map(|v| (v * 255.0 + offset).round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_func::srgb_to_linear;
+
+    fn draw(buffer: &mut ColorBuffer, colors: &[([u32; 2], [u8; 3])], screen: &mut [u8]) {
+        let colors = colors
+            .iter()
+            .map(|&(pos, rgb)| RColor {
+                pos,
+                value: rgb.into(),
+            })
+            .collect_vec();
+        buffer.draw_colors(&colors, 1, &ToneMapping::default(), screen);
+    }
+
+    #[test]
+    fn only_new_contrasts_are_refined() {
+        let tone_mapping = ToneMapping::default();
+        let mut buffer = ColorBuffer::new(4, 1);
+        let mut screen = vec![0; 4 * 4];
+        let colors = [[0; 3], [0; 3], [255; 3], [255; 3]];
+        draw(
+            &mut buffer,
+            &[0, 1, 2, 3].map(|x| ([x, 0], colors[x as usize])),
+            &mut screen,
+        );
+        let found = buffer.high_contrast_pixels(&tone_mapping, 0.1);
+        assert_eq!(found, [[1, 0], [2, 0]]);
+        buffer.settle(&found);
+
+        buffer.draw_pixels(&[([1, 0], [0.2; 4])], &tone_mapping, &mut screen);
+        // the refined pixel now differs from its left neighbor, which was looked at already
+        assert_eq!(buffer.high_contrast_pixels(&tone_mapping, 0.1), [[2, 0]]);
+        // everything moves along with the pixels, the new one on the left is compared
+        buffer.translate([1, 0]);
+        draw(&mut buffer, &[([0, 0], [255; 3])], &mut screen);
+        assert_eq!(
+            buffer.high_contrast_pixels(&tone_mapping, 0.1),
+            [[0, 0], [1, 0], [3, 0]]
+        );
+        // a new color for a cell has it compared again
+        draw(&mut buffer, &[([3, 0], [0; 3])], &mut screen);
+        assert_eq!(
+            buffer.high_contrast_pixels(&tone_mapping, 0.1),
+            [[0, 0], [1, 0], [3, 0]]
+        );
+    }
+
+    #[test]
+    fn colors_outside_the_screen_are_dropped() {
+        let mut buffer = ColorBuffer::new(2, 2);
+        let mut screen = vec![0; 2 * 2 * 4];
+        draw(
+            &mut buffer,
+            &[([2, 0], [255; 3]), ([0, 2], [255; 3]), ([1, 1], [255; 3])],
+            &mut screen,
+        );
+
+        assert_eq!(screen[..12], [0; 12]);
+        assert_eq!(screen[12..], [255; 4]);
+    }
+
+    #[test]
+    fn linear_export_keeps_the_full_range() {
+        let mut buffer = ColorBuffer::new(2, 1);
+        let mut screen = vec![0; 2 * 4];
+        buffer.draw_pixels(
+            &[([0, 0], [4.0, 0.5, 0.0, 1.0])],
+            &ToneMapping::default(),
+            &mut screen,
+        );
+
+        assert_eq!(
+            buffer.to_linear_rgba(),
+            [4.0, 0.5, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        // the 16-bit export is tone mapped, the clamp cuts off the red
+        let rgba16 = buffer.to_rgba16(&ToneMapping::default());
+        assert_eq!([rgba16[0], rgba16[3]], [65535; 2]);
+        assert!((1..65535).contains(&rgba16[1]));
+    }
+
+    fn assert_close(a: [f32; 4], b: [f32; 4]) {
+        assert!(
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6),
+            "{:?} {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn tone_mapping_operators() {
+        let mapping = |operator, exposure| ToneMapping {
+            operator,
+            exposure,
+            dither: false,
+        };
+        let clamp = mapping(ToneMapOperator::Clamp, 1.0);
+        let reinhard = mapping(ToneMapOperator::Reinhard, 1.0);
+
+        assert_close(clamp.map([0.0, 1.0, 4.0, 2.0]), [0.0, 1.0, 1.0, 1.0]);
+        assert_close(clamp.map([-1.0, 0.0, 0.0, -1.0]), [0.0; 4]);
+        assert_close(
+            mapping(ToneMapOperator::Clamp, 2.0).map([0.5; 4]),
+            clamp.map([1.0, 1.0, 1.0, 0.5]),
+        );
+        assert_close(
+            reinhard.map([1.0; 4]),
+            [
+                linear_to_srgb(0.5),
+                linear_to_srgb(0.5),
+                linear_to_srgb(0.5),
+                1.0,
+            ],
+        );
+        for operator in ToneMapOperator::ALL {
+            let curve = (0..64)
+                .map(|i| mapping(operator, 1.0).map([i as f32 / 8.0; 4])[0])
+                .collect_vec();
+            assert!(
+                curve.windows(2).all(|pair| pair[0] <= pair[1]),
+                "{:?}",
+                operator
+            );
+            assert!(
+                curve.iter().all(|v| (0.0..=1.0).contains(v)),
+                "{:?}",
+                operator
+            );
+        }
+    }
+
+    #[test]
+    fn dithering_averages_to_the_undithered_color() {
+        let offsets = (0..4)
+            .flat_map(|y| (0..4).map(move |x| dither_offset(x, y)))
+            .collect_vec();
+        assert!(offsets.iter().all(|v| v.abs() < 0.5));
+        assert_eq!(offsets.iter().map(|v| v.to_bits()).unique().count(), 16);
+        assert_eq!(offsets.iter().sum::<f32>(), 0.0);
+
+        let tone_mapping = ToneMapping::default();
+        let rgba = [srgb_to_linear(100.25 / 255.0), 0.0, 1.0, 1.0];
+        let pixels = (0..4)
+            .flat_map(|y| (0..4).map(move |x| to_display(rgba, x, y, &tone_mapping)))
+            .collect_vec();
+        let mean = pixels.iter().map(|p| p[0] as f32).sum::<f32>() / 16.0;
+        assert!((mean - 100.25).abs() <= 1.0 / 16.0, "{}", mean);
+        assert!(pixels.iter().all(|p| p[1..] == [0, 255, 255]));
+
+        let undithered = ToneMapping {
+            dither: false,
+            ..tone_mapping
+        };
+        assert_eq!(to_display(rgba, 1, 2, &undithered), [100, 0, 255, 255]);
+        assert_eq!(
+            to_display([1.0, 1.0, 1.0, 0.0], 1, 2, &tone_mapping),
+            [0; 4]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use abi_stable::std_types::{RVec, Tuple2};
+use color_func::RColorStats;
+
+pub const HISTOGRAM_BINS: usize = 256;
+pub const QUANTILES: [f64; 11] = [0.0, 0.01, 0.05, 0.1, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99, 1.0];
+
+/// recompute once the number of values has grown by this factor since the last time
+const GROWTH_FACTOR: f64 = 1.5;
+/// a quantile has to move by this fraction of the value range to count as a meaningful change
+const QUANTILE_CHANGE_THRESHOLD: f64 = 0.01;
+
+/// decides when statistics over the cell values need to be recomputed, and whether the result
+/// differs enough from what the color func already has to be worth recoloring everything
+#[derive(Debug, Default)]
+pub struct ColorStatsCollector {
+    value_count_at_last_update: usize,
+    applied_stats: Option<RColorStats>,
+}
+
+impl ColorStatsCollector {
+    pub fn reset(&mut self) {
+        self.value_count_at_last_update = 0;
+    }
+
+    /// forget the statistics applied to the color func (eg. because the color func was replaced)
+    pub fn clear_applied(&mut self) {
+        self.value_count_at_last_update = 0;
+        self.applied_stats = None;
+    }
+
+    fn is_due(&self, value_count: usize, finished: bool) -> bool {
+        value_count > 0
+            && ((value_count as f64) >= (self.value_count_at_last_update as f64) * GROWTH_FACTOR
+                || (finished && value_count != self.value_count_at_last_update))
+    }
+
+    /// whether statistics over `value_count` values are due, in which case they count as the
+    /// latest ones from now on. they are handed to `finish` once computed.
+    pub fn start(&mut self, value_count: usize, finished: bool) -> bool {
+        if !self.is_due(value_count, finished) {
+            return false;
+        }
+        self.value_count_at_last_update = value_count;
+        true
+    }
+
+    /// returns the statistics computed after `start` if they changed meaningfully since the last
+    /// ones applied
+    pub fn finish(&mut self, stats: Option<RColorStats>) -> Option<RColorStats> {
+        let stats = stats?;
+        match &self.applied_stats {
+            Some(applied) if !changed_meaningfully(applied, &stats) => None,
+            _ => {
+                self.applied_stats = Some(stats.clone());
+                Some(stats)
+            }
+        }
+    }
+}
+
+/// too slow for the gui thread on a large screen, the worker calls it on a thread of its own
+pub fn compute_stats(chunk_values: &[Arc<RVec<f64>>]) -> Option<RColorStats> {
+    let mut values: Vec<f64> = chunk_values
+        .iter()
+        .flat_map(|v| v.iter().copied())
+        .filter(|v| !v.is_nan())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    // not a parallel sort, the render keeps the threads busy until it's done
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let count = values.len();
+    let min = values[0];
+    let max = values[count - 1];
+
+    let mut histogram = RVec::from(vec![0u64; HISTOGRAM_BINS]);
+    let bin_width = (max - min) / (HISTOGRAM_BINS as f64);
+    for &v in values.iter() {
+        let bin = if bin_width > 0.0 {
+            (((v - min) / bin_width) as usize).min(HISTOGRAM_BINS - 1)
+        } else {
+            0
+        };
+        histogram[bin] += 1;
+    }
+
+    let quantiles = QUANTILES
+        .iter()
+        .map(|&q| {
+            let idx = (q * (count - 1) as f64).round() as usize;
+            Tuple2(q, values[idx])
+        })
+        .collect();
+
+    Some(RColorStats {
+        count: count as u64,
+        min,
+        max,
+        histogram,
+        quantiles,
+    })
+}
+
+fn changed_meaningfully(old: &RColorStats, new: &RColorStats) -> bool {
+    let range = (new.max - new.min).abs().max(f64::EPSILON);
+    old.quantiles.len() != new.quantiles.len()
+        || old
+            .quantiles
+            .iter()
+            .zip(new.quantiles.iter())
+            .any(|(a, b)| ((a.1 - b.1) / range).abs() > QUANTILE_CHANGE_THRESHOLD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: impl IntoIterator<Item = f64>) -> Vec<Arc<RVec<f64>>> {
+        vec![Arc::new(values.into_iter().collect())]
+    }
+
+    #[test]
+    fn stats_are_due_as_the_values_grow() {
+        let mut collector = ColorStatsCollector::default();
+
+        assert!(!collector.start(0, false));
+        assert!(collector.start(100, false));
+        assert!(!collector.start(140, false));
+        assert!(collector.start(150, false));
+        assert!(collector.start(160, true));
+        assert!(!collector.start(160, true));
+    }
+
+    #[test]
+    fn only_meaningful_changes_are_applied() {
+        let mut collector = ColorStatsCollector::default();
+        let stats = compute_stats(&values((0..=100).map(f64::from))).unwrap();
+        let nudged = compute_stats(&values((0..=100).map(|v| v as f64 * 1.001))).unwrap();
+        let skewed = compute_stats(&values((0..=100).map(|v| (v * v) as f64 / 100.0))).unwrap();
+
+        assert!(collector.finish(None).is_none());
+        assert!(collector.finish(Some(stats)).is_some());
+        assert!(collector.finish(Some(nudged)).is_none());
+        assert!(collector.finish(Some(skewed)).is_some());
+    }
+
+    #[test]
+    fn stats_skip_missing_values() {
+        let stats = compute_stats(&values([3.0, f64::NAN, 1.0, 2.0])).unwrap();
+
+        assert_eq!((stats.count, stats.min, stats.max), (3, 1.0, 3.0));
+        assert_eq!(stats.histogram.iter().sum::<u64>(), 3);
+        assert_eq!(stats.quantiles[5], Tuple2(0.5, 2.0));
+        assert!(compute_stats(&values([f64::NAN])).is_none());
+    }
+}
@@ -7,6 +7,10 @@ use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use tempfile::NamedTempFile;
 
+use crate::chunk_order::ChunkOrder;
+use crate::render_pool::RenderPriority;
+use crate::watchdog::DEFAULT_CHUNK_BUDGET;
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FuncConfig {
     pub path: String,
@@ -14,6 +18,12 @@ pub struct FuncConfig {
     pub name: String,
     #[serde(default)]
     pub options: HashMap<String, String>,
+    /// shell command that rebuilds the library
+    #[serde(default)]
+    pub build_command: Option<String>,
+    /// files or directories to watch, a change triggers the build command
+    #[serde(default)]
+    pub watch_paths: Vec<String>,
 }
 
 impl FuncConfig {
@@ -30,8 +40,47 @@ pub struct Config {
     pub height: u32,
     #[serde(default = "Config::default_chunk_size")]
     pub chunk_size: usize,
+    /// size the chunks from how long cells took to render, see `FractalWorker::set_chunk_size`
+    #[serde(default)]
+    pub adaptive_chunk_size: bool,
     pub fractal_config: FuncConfig,
     pub color_config: FuncConfig,
+    /// where the gui looks for libraries to pick from
+    #[serde(default)]
+    pub plugin_dir: Option<String>,
+    /// run the plugins in a separate process, so that a crashing plugin doesn't take the app down
+    #[serde(default)]
+    pub out_of_process: bool,
+    /// render servers to send chunks to, as `host:port`, see the `worker_server` binary
+    #[serde(default)]
+    pub remote_workers: Vec<String>,
+    /// chunks that take longer than this are flagged, and a plugin process stuck on one is
+    /// restarted. zero disables the watchdog.
+    #[serde(default = "Config::default_chunk_budget_ms")]
+    pub chunk_budget_ms: u64,
+    /// render a coarse grid first and refine it, see `FractalWorker::set_progressive`
+    #[serde(default)]
+    pub progressive: bool,
+    /// the order that the chunks of a render are rendered in
+    #[serde(default)]
+    pub chunk_order: ChunkOrder,
+    /// for `ChunkOrder::Random`, so that renders can be reproduced
+    #[serde(default)]
+    pub chunk_order_seed: u64,
+    /// fill rectangles with uniform borders, see `FractalWorker::set_boundary_fill`
+    #[serde(default)]
+    pub boundary_fill: bool,
+    /// give the high contrast pixels more samples once a render is finished
+    #[serde(default = "Config::default_refine_edges")]
+    pub refine_edges: bool,
+    /// how many threads render, 0 is one per CPU
+    #[serde(default)]
+    pub render_threads: usize,
+    #[serde(default)]
+    pub render_priority: RenderPriority,
+    /// render at low priority while the window isn't focused
+    #[serde(default)]
+    pub background_mode: bool,
 }
 
 impl Config {
@@ -44,6 +93,12 @@ impl Config {
     fn default_chunk_size() -> usize {
         32
     }
+    fn default_chunk_budget_ms() -> u64 {
+        DEFAULT_CHUNK_BUDGET.as_millis() as u64
+    }
+    fn default_refine_edges() -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -61,16 +116,33 @@ impl ConfigManager {
                 width,
                 height,
                 chunk_size: Config::default_chunk_size(),
+                adaptive_chunk_size: false,
                 fractal_config: FuncConfig {
                     path: fractal_lib_path.to_owned(),
                     name: FuncConfig::default_name(),
                     options: Default::default(),
+                    build_command: None,
+                    watch_paths: vec![],
                 },
                 color_config: FuncConfig {
                     path: color_lib_path.to_owned(),
                     name: FuncConfig::default_name(),
                     options: Default::default(),
+                    build_command: None,
+                    watch_paths: vec![],
                 },
+                plugin_dir: None,
+                out_of_process: false,
+                remote_workers: vec![],
+                chunk_budget_ms: Config::default_chunk_budget_ms(),
+                progressive: false,
+                chunk_order: Default::default(),
+                chunk_order_seed: 0,
+                boundary_fill: false,
+                refine_edges: Config::default_refine_edges(),
+                render_threads: 0,
+                render_priority: Default::default(),
+                background_mode: false,
             },
         }
     }
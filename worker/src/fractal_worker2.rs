@@ -2,161 +2,828 @@ use std::{
     cmp::min,
     collections::HashSet,
     path::{Path, PathBuf},
-    sync::mpsc::{channel, Receiver},
-};
-
-use abi_stable::{library::RootModule, std_types::RSlice};
-use abi_stable::{
-    library::{lib_header_from_raw_library, RawLibrary},
-    std_types::{
-        RResult::{RErr, ROk},
-        RString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, RwLock,
     },
-    utils::leak_value,
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
+
+use abi_stable::std_types::RString;
 use anyhow::Context;
 use core_extensions::SelfOps;
 use itertools::Itertools;
-use log::info;
-use rand::{prelude::SliceRandom, thread_rng};
+use log::{error, info, warn};
 use rayon::{
     current_num_threads,
     iter::{IntoParallelIterator, ParallelIterator},
+    slice::ParallelSlice,
 };
 
-use color_func::{
-    prelude::{ColorLib_Ref, RColorFuncBox},
-    RColor,
-};
+use color_func::{prelude::ColorLib_Ref, RColor, RColorStats, RTile};
 use fractal_func::prelude::*;
 
-///////////////////////////////////////////////////////////////////////////////
+use crate::boundary_fill::compute_cells_filled;
+use crate::builtin_plugins::BuiltinPlugins;
+use crate::chunk_order::{ChunkOrder, ChunkSchedule};
+use crate::chunk_sizing::{CellCosts, MAX_CHUNK_SIZE};
+use crate::color_buffer::{cell_block_size, ColorBuffer, ToneMapping};
+use crate::color_stats::{compute_stats, ColorStatsCollector};
+use crate::config_manager::Config;
+use crate::plugin_host::{ColorFunc, FractalFunc, Plugin};
+use crate::plugin_log;
+use crate::plugin_process::{PluginProcess, ProcessStatus, RemoteFractalState};
+use crate::rebuild::{BuildConfig, BuildStatus, Rebuilder};
+use crate::refinement::{SubpixelSampler, CONTRAST_THRESHOLD};
+use crate::render_pool::{PoolConfig, RenderGate, RenderPool, RenderPriority};
+use crate::render_server::RemoteConnection;
+use crate::tiles::{apron_positions, chunk_spacing, CellIndex};
+use crate::watchdog::{ChunkWatchdog, RenderPlugins, SlowChunk, DEFAULT_CHUNK_BUDGET};
+use crate::wire::wire_options;
 
-/// this is similar to RootModule::load_from_file(), except that it supports reloading the module.
-/// (It still leaks the memory each time the module is loaded, though...)
-fn load_from_file<Lib: RootModule>(path: &Path) -> anyhow::Result<Lib> {
-    // copy the library to a unique path, to make sure it gets reloaded even if it was already loaded
-    let unique_name = {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        path.with_file_name(format!(
-            "{}-{}",
-            path.file_name().unwrap().to_string_lossy(),
-            timestamp
-        ))
-    };
-    std::fs::copy(&path, &unique_name).expect("Failed to copy lib to unique path");
-    let unique_lib_path = Path::new(&unique_name).canonicalize().unwrap();
-    let raw_library = RawLibrary::load_at(&unique_lib_path)?;
-    // if the library isn't leaked
-    // it would cause any use of the module to be a use after free.
-    //
-    // By leaking the library
-    // this allows the root module loader to do anything that'd prevent
-    // sound library unloading.
-    let lib = leak_value(raw_library);
+///////////////////////////////////////////////////////////////////////////////
 
-    let items = unsafe { lib_header_from_raw_library(lib)? };
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkerState {
+    Init,
+    Started,
+    Working {
+        total: usize,
+        completed: usize,
+    },
+    Interrupted,
+    Finished,
+    /// the render is finished, and its high contrast pixels get more samples
+    Refining {
+        total: usize,
+        completed: usize,
+    },
+    /// a plugin returned an error (or panicked) while rendering
+    Error(String),
+}
 
-    items.ensure_layout::<Lib>()?;
+/// how far the current render got
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderProgress {
+    pub total_chunks: usize,
+    pub completed_chunks: usize,
+    pub total_cells: usize,
+    pub completed_cells: usize,
+    /// since the render started, or until it finished
+    pub elapsed: Duration,
+}
 
-    // safety: the layout was checked in the code above,
-    let ret = unsafe {
-        items
-            .init_root_module_with_unchecked_layout::<Lib>()?
-            .initialization()
+impl RenderProgress {
+    /// range: 0 to 1
+    pub fn fraction(&self) -> f32 {
+        if self.total_cells == 0 {
+            return 0.0;
+        }
+        self.completed_cells as f32 / self.total_cells as f32
     }
-    .context("loading library")?;
 
-    // remove the file after we load it
-    std::fs::remove_file(&unique_lib_path)?;
+    /// over the whole render so far
+    pub fn cells_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.completed_cells as f64 / seconds
+        } else {
+            0.0
+        }
+    }
 
-    Ok(ret)
+    /// how much longer the render takes at the rate so far, none before the first chunk
+    pub fn eta(&self) -> Option<Duration> {
+        let cells_per_second = self.cells_per_second();
+        let remaining = self.total_cells.saturating_sub(self.completed_cells);
+        (cells_per_second > 0.0)
+            .then(|| Duration::from_secs_f64(remaining as f64 / cells_per_second))
+    }
 }
 
-///////////////////////////////////////////////////////////////////////////////
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum WorkerState {
-    Init,
-    Started,
-    Working { total: usize, completed: usize },
-    Interrupted,
-    Finished,
+#[derive(Debug)]
+struct RenderedChunk {
+    chunk: RChunk,
+    colors: RVec<RColor>,
+    /// from `RColorFunc::cell_values`, empty unless the color func wants statistics
+    values: RVec<f64>,
+    color_generation: u32,
+    /// how long it took to render here, `None` for the chunks of render servers
+    elapsed: Option<Duration>,
 }
 
 #[derive(Debug)]
 enum WorkerMessage {
     Init,
+    /// what the render thread is going to render, sent before the first chunk
+    Scheduled {
+        chunks: usize,
+        cells: usize,
+    },
     Finished,
-    Chunk(RChunk, RVec<RColor>, u32),
+    Chunk(RenderedChunk, u32),
+    /// new colors for the retained chunk at `index`
+    Recolored {
+        index: usize,
+        colors: RVec<RColor>,
+        values: Option<RVec<f64>>,
+        color_generation: u32,
+    },
+    /// the pixels that a refinement started with `color_generation` found to refine
+    Refining {
+        pixels: Vec<[u32; 2]>,
+        color_generation: u32,
+    },
+    /// refined colors for some of the pixels of a refinement started with `color_generation`
+    Refined {
+        pixels: Vec<([u32; 2], [f32; 4])>,
+        color_generation: u32,
+    },
+    /// statistics over the cell values of the render, none if there were no values
+    Statistics(Option<RColorStats>, u32),
+    Error(String),
 }
 
+/// the current color func, shared with the render thread so that it can be swapped mid-render.
+/// the generation changes every time, so that chunks colored by an old color func can be found
+/// and recolored.
+#[derive(Clone)]
+struct SharedColorFunc(Arc<RwLock<(u32, ColorFunc)>>);
+
+impl SharedColorFunc {
+    fn new(color_func: ColorFunc) -> Self {
+        Self(Arc::new(RwLock::new((0, color_func))))
+    }
+
+    fn get(&self) -> (u32, ColorFunc) {
+        let guard = self.0.read().unwrap();
+        (guard.0, guard.1.clone())
+    }
+
+    fn set(&self, color_func: ColorFunc) -> u32 {
+        let mut guard = self.0.write().unwrap();
+        guard.0 = guard.0.wrapping_add(1);
+        guard.1 = color_func;
+        guard.0
+    }
+}
+
+/// the spacing of the first pass of a progressive render
+pub const PROGRESSIVE_BLOCK_SIZE: u32 = 8;
+
 pub struct FractalWorker {
     // config
     width: u32,
     height: u32,
     chunk_size: usize,
+    /// size the chunks from `cell_costs`, with `chunk_size` where nothing was measured yet
+    adaptive_chunk_size: bool,
+    /// render every `PROGRESSIVE_BLOCK_SIZE`th cell first, drawn as blocks, then refine
+    progressive: bool,
+    chunk_schedule: ChunkSchedule,
+    /// fill rectangles with uniform borders instead of computing them, if the fractal func
+    /// declares fill-safe fields
+    boundary_fill: bool,
+    /// give the high contrast pixels more samples once a render is finished
+    refine_edges: bool,
+    /// 0 is one per CPU
+    render_threads: usize,
+    render_priority: RenderPriority,
+    /// render at low priority while the window isn't focused
+    background_mode: bool,
     // state
     epoch: u32,
     state: WorkerState,
+    progress: RenderProgress,
+    render_started: Instant,
+    /// since when the render is paused
+    paused_at: Option<Instant>,
+    /// holds the threads of the current render while it's paused
+    render_gate: Arc<RenderGate>,
+    pool: RenderPool,
+    /// whether the background mode lowered the priority of the pool's threads, which might not
+    /// be allowed to go back up
+    pool_lowered: bool,
+    focused: bool,
+    /// the block size that the chunks of the current render are drawn with
+    render_block_size: u32,
     receiver: Option<Receiver<WorkerMessage>>,
-    chunks: Vec<RChunk>,
+    sender: Option<Sender<WorkerMessage>>,
+    chunks: Vec<Arc<RChunk>>,
+    /// shared with the thread that computes the color statistics
+    chunk_values: Vec<Arc<RVec<f64>>>,
+    /// the color generation that each chunk was last colored with
+    chunk_color_generations: Vec<u32>,
+    /// recolored chunks that haven't come back yet
+    pending_recolors: usize,
+    /// the epoch and color generation that the last refinement started with
+    refined_render: Option<(u32, u32)>,
+    /// stops the running refinement
+    refinement_cancelled: Arc<AtomicBool>,
+    cell_costs: CellCosts,
+    color_stats: ColorStatsCollector,
+    /// statistics are being computed for the current render
+    computing_stats: bool,
+    color_buffer: ColorBuffer,
+    tone_mapping: ToneMapping,
     should_clear_screen: bool,
+    should_redraw_screen: bool,
+    /// how far the screen has to move to match the color buffer, after a pan
+    screen_offset: Option<[i32; 2]>,
     // FFI
     fractal_lib_path: PathBuf,
-    fractal_lib: FractalLib_Ref,
-    fractal_func: RFractalFuncBox,
+    fractal_lib: Plugin<FractalLib_Ref>,
+    fractal_func: FractalFunc,
     color_lib_path: PathBuf,
-    color_lib: ColorLib_Ref,
-    color_func: RColorFuncBox,
+    color_lib: Plugin<ColorLib_Ref>,
+    color_func: ColorFunc,
+    shared_color_func: SharedColorFunc,
+    color_generation: u32,
+    rebuilder: Rebuilder,
+    /// options of the old function objects that the reloaded ones didn't accept
+    dropped_options: Vec<String>,
+    /// runs the plugins when they are out of process
+    plugin_process: Option<PluginProcess>,
+    /// render servers that get chunks alongside the local threads, as `host:port`
+    remote_workers: Vec<String>,
+    remote_stats: Arc<Mutex<Vec<RemoteWorkerStats>>>,
+    /// chunks that take longer are flagged, zero disables the watchdog
+    chunk_budget: Duration,
+    /// the chunks of the current render that went over budget
+    slow_chunks: Arc<Mutex<Vec<SlowChunk>>>,
+}
+
+/// the render settings that `FractalWorker::configure` applies all at once, see the setters of
+/// the same names
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub remote_workers: Vec<String>,
+    pub chunk_budget: Duration,
+    pub chunk_size: usize,
+    pub adaptive_chunk_size: bool,
+    pub progressive: bool,
+    pub chunk_order: ChunkOrder,
+    pub chunk_order_seed: u64,
+    pub boundary_fill: bool,
+    pub refine_edges: bool,
+    pub render_threads: usize,
+    pub render_priority: RenderPriority,
+    pub background_mode: bool,
+}
+
+impl From<&Config> for RenderSettings {
+    fn from(config: &Config) -> Self {
+        Self {
+            remote_workers: config.remote_workers.clone(),
+            chunk_budget: Duration::from_millis(config.chunk_budget_ms),
+            chunk_size: config.chunk_size,
+            adaptive_chunk_size: config.adaptive_chunk_size,
+            progressive: config.progressive,
+            chunk_order: config.chunk_order,
+            chunk_order_seed: config.chunk_order_seed,
+            boundary_fill: config.boundary_fill,
+            refine_edges: config.refine_edges,
+            render_threads: config.render_threads,
+            render_priority: config.render_priority,
+            background_mode: config.background_mode,
+        }
+    }
+}
+
+/// how a render server did in the current render
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteWorkerStats {
+    pub addr: String,
+    pub chunks: usize,
+    /// why it stopped getting chunks
+    pub error: Option<String>,
 }
 
 impl FractalWorker {
-    pub fn new(width: u32, height: u32, fractal_lib_path: &str, color_lib_path: &str) -> Self {
+    pub fn new(
+        width: u32,
+        height: u32,
+        fractal_lib_path: &str,
+        color_lib_path: &str,
+    ) -> anyhow::Result<Self> {
+        Self::with_plugin_process(width, height, fractal_lib_path, color_lib_path, None)
+    }
+
+    /// with a plugin process, the libraries are never loaded into this process
+    pub fn with_plugin_process(
+        width: u32,
+        height: u32,
+        fractal_lib_path: &str,
+        color_lib_path: &str,
+        plugin_process: Option<PluginProcess>,
+    ) -> anyhow::Result<Self> {
         let fractal_lib_path: PathBuf = PathBuf::from(fractal_lib_path);
         let color_lib_path: PathBuf = PathBuf::from(color_lib_path);
 
-        let fractal_lib: FractalLib_Ref =
-            load_from_file(&fractal_lib_path).expect("failed to load fractal library");
-        let color_lib: ColorLib_Ref =
-            load_from_file(&color_lib_path).expect("failed to load color library");
-        Self {
+        let fractal_lib: Plugin<FractalLib_Ref> =
+            load_plugin(&fractal_lib_path, plugin_process.as_ref())
+                .context("failed to load fractal library")?;
+        let color_lib: Plugin<ColorLib_Ref> = load_plugin(&color_lib_path, plugin_process.as_ref())
+            .context("failed to load color library")?;
+        plugin_log::init_lib(&fractal_lib);
+        plugin_log::init_lib(&color_lib);
+        let fractal_func = fractal_lib.default_fractal_func_for_size(width, height);
+        let color_func = color_lib.default_color_func();
+        Ok(Self {
             width,
             height,
             //
             epoch: 0,
             state: WorkerState::Init,
+            progress: Default::default(),
+            render_started: Instant::now(),
+            paused_at: None,
+            render_gate: Default::default(),
+            pool: RenderPool::new(PoolConfig {
+                threads: 0,
+                priority: RenderPriority::Normal,
+            })?,
+            pool_lowered: false,
+            focused: true,
             receiver: None,
+            sender: None,
             chunk_size: 32,
+            adaptive_chunk_size: false,
+            progressive: false,
+            chunk_schedule: Default::default(),
+            boundary_fill: false,
+            refine_edges: true,
+            render_threads: 0,
+            render_priority: RenderPriority::Normal,
+            background_mode: false,
+            render_block_size: 1,
             chunks: vec![],
+            chunk_values: vec![],
+            chunk_color_generations: vec![],
+            pending_recolors: 0,
+            refined_render: None,
+            refinement_cancelled: Default::default(),
+            cell_costs: CellCosts::new(width, height),
+            color_stats: Default::default(),
+            computing_stats: false,
+            color_buffer: ColorBuffer::new(width, height),
+            tone_mapping: Default::default(),
             should_clear_screen: true,
+            should_redraw_screen: false,
+            screen_offset: None,
             //
             fractal_lib_path,
             fractal_lib,
-            fractal_func: fractal_lib.default_fractal_func_for_size()(width, height),
+            fractal_func,
             color_lib_path,
             color_lib,
-            color_func: color_lib.default_color_func()(),
+            shared_color_func: SharedColorFunc::new(color_func.clone()),
+            color_func,
+            color_generation: 0,
+            rebuilder: Default::default(),
+            dropped_options: vec![],
+            plugin_process,
+            remote_workers: vec![],
+            remote_stats: Default::default(),
+            chunk_budget: DEFAULT_CHUNK_BUDGET,
+            slow_chunks: Default::default(),
         }
-        .mutated(|s| s.start_worker(None, None, None))
+        .mutated(|s| s.start_worker(None)))
     }
 
     pub fn reload_libraries(&mut self) -> anyhow::Result<()> {
-        self.fractal_lib = load_from_file(&self.fractal_lib_path)?;
-        self.fractal_func =
-            self.fractal_lib.default_fractal_func_for_size()(self.width, self.height);
-        self.color_lib = load_from_file(&self.color_lib_path)?;
-        self.color_func = self.color_lib.default_color_func()();
+        if let Some(plugin_process) = &self.plugin_process {
+            // a fresh process loads fresh copies of the libraries
+            plugin_process.restart();
+        }
+        // load both before replacing anything, so that a failure keeps the old libraries running
+        let fractal_lib: Plugin<FractalLib_Ref> =
+            load_plugin(&self.fractal_lib_path, self.plugin_process.as_ref())
+                .context("failed to reload fractal library")?;
+        let color_lib: Plugin<ColorLib_Ref> =
+            load_plugin(&self.color_lib_path, self.plugin_process.as_ref())
+                .context("failed to reload color library")?;
+        self.install_libraries(Some(fractal_lib), Some(color_lib));
+        Ok(())
+    }
 
-        self.reset();
-        self.start_worker(None, None, None);
+    /// switches to a different fractal library, keeping the options it accepts
+    pub fn set_fractal_lib(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let fractal_lib: Plugin<FractalLib_Ref> =
+            load_plugin(path.as_ref(), self.plugin_process.as_ref())
+                .context("failed to load fractal library")?;
+        self.fractal_lib_path = path.as_ref().to_owned();
+        self.install_libraries(Some(fractal_lib), None);
+        Ok(())
+    }
 
+    /// switches to a different color library, keeping the options it accepts
+    pub fn set_color_lib(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let color_lib: Plugin<ColorLib_Ref> =
+            load_plugin(path.as_ref(), self.plugin_process.as_ref())
+                .context("failed to load color library")?;
+        self.color_lib_path = path.as_ref().to_owned();
+        self.install_libraries(None, Some(color_lib));
         Ok(())
     }
 
+    pub fn get_fractal_lib_path(&self) -> &Path {
+        &self.fractal_lib_path
+    }
+    pub fn get_color_lib_path(&self) -> &Path {
+        &self.color_lib_path
+    }
+
+    /// moves the plugins into a plugin process, or back into this one
+    pub fn set_out_of_process(&mut self, out_of_process: bool) -> anyhow::Result<()> {
+        if out_of_process == self.plugin_process.is_some() {
+            return Ok(());
+        }
+        let plugin_process = if out_of_process {
+            Some(PluginProcess::new()?)
+        } else {
+            None
+        };
+        let fractal_lib: Plugin<FractalLib_Ref> =
+            load_plugin(&self.fractal_lib_path, plugin_process.as_ref())
+                .context("failed to load fractal library")?;
+        let color_lib: Plugin<ColorLib_Ref> =
+            load_plugin(&self.color_lib_path, plugin_process.as_ref())
+                .context("failed to load color library")?;
+        self.plugin_process = plugin_process;
+        self.install_libraries(Some(fractal_lib), Some(color_lib));
+        Ok(())
+    }
+    pub fn is_out_of_process(&self) -> bool {
+        self.plugin_process.is_some()
+    }
+
+    /// applies all of `settings` with at most one restart of the render, where calling the
+    /// setters one by one could restart it for each
+    pub fn configure(&mut self, settings: RenderSettings) {
+        let RenderSettings {
+            remote_workers,
+            chunk_budget,
+            chunk_size,
+            adaptive_chunk_size,
+            progressive,
+            chunk_order,
+            chunk_order_seed,
+            boundary_fill,
+            refine_edges,
+            render_threads,
+            render_priority,
+            background_mode,
+        } = settings;
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE as usize);
+        let new_remote_workers = remote_workers != self.remote_workers;
+        let changes_render = (
+            chunk_size,
+            adaptive_chunk_size,
+            progressive,
+            chunk_order,
+            chunk_order_seed,
+            boundary_fill,
+        ) != (
+            self.chunk_size,
+            self.adaptive_chunk_size,
+            self.progressive,
+            self.chunk_schedule.order,
+            self.chunk_schedule.seed,
+            self.boundary_fill,
+        );
+
+        self.remote_workers = remote_workers;
+        self.chunk_budget = chunk_budget;
+        self.chunk_size = chunk_size;
+        self.adaptive_chunk_size = adaptive_chunk_size;
+        self.progressive = progressive;
+        self.chunk_schedule.order = chunk_order;
+        self.chunk_schedule.seed = chunk_order_seed;
+        self.boundary_fill = boundary_fill;
+        self.set_refine_edges(refine_edges);
+        self.render_threads = render_threads;
+        self.render_priority = render_priority;
+        self.background_mode = background_mode;
+        self.update_priority();
+        let new_pool = self.update_pool();
+
+        if new_remote_workers {
+            self.reset();
+            self.start_worker(None);
+        } else if changes_render && !self.is_finished() {
+            self.continue_render(None);
+        } else if new_pool {
+            self.continue_on_new_pool();
+        }
+    }
+
+    pub fn set_remote_workers(&mut self, remote_workers: Vec<String>) {
+        if remote_workers != self.remote_workers {
+            self.remote_workers = remote_workers;
+            self.reset();
+            self.start_worker(None);
+        }
+    }
+    pub fn get_remote_workers(&self) -> &[String] {
+        &self.remote_workers
+    }
+    pub fn get_remote_stats(&self) -> Vec<RemoteWorkerStats> {
+        self.remote_stats.lock().unwrap().clone()
+    }
+
+    /// an unfinished render continues with the new size
+    pub fn set_chunk_size(&mut self, chunk_size: usize, adaptive: bool) {
+        // bigger chunks wouldn't fit in a message to a plugin process or render server
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE as usize);
+        if (chunk_size, adaptive) == (self.chunk_size, self.adaptive_chunk_size) {
+            return;
+        }
+        self.chunk_size = chunk_size;
+        self.adaptive_chunk_size = adaptive;
+        if !self.is_finished() {
+            self.continue_render(None);
+        }
+    }
+    pub fn get_chunk_size(&self) -> (usize, bool) {
+        (self.chunk_size, self.adaptive_chunk_size)
+    }
+
+    /// an unfinished render continues in the new order
+    pub fn set_progressive(&mut self, progressive: bool) {
+        if progressive == self.progressive {
+            return;
+        }
+        self.progressive = progressive;
+        if !self.is_finished() {
+            self.continue_render(None);
+        }
+    }
+    pub fn is_progressive(&self) -> bool {
+        self.progressive
+    }
+
+    /// an unfinished render continues with the new setting
+    pub fn set_boundary_fill(&mut self, boundary_fill: bool) {
+        if boundary_fill == self.boundary_fill {
+            return;
+        }
+        self.boundary_fill = boundary_fill;
+        if !self.is_finished() {
+            self.continue_render(None);
+        }
+    }
+    pub fn is_boundary_fill(&self) -> bool {
+        self.boundary_fill
+    }
+    /// whether the fractal func declares fill-safe fields, without them boundary fill does nothing
+    pub fn supports_boundary_fill(&self) -> bool {
+        !self.fractal_func.fill_safe_fields().is_empty()
+    }
+
+    /// turning it off stops a running refinement, what it refined stays
+    pub fn set_refine_edges(&mut self, refine_edges: bool) {
+        self.refine_edges = refine_edges;
+        if !refine_edges {
+            self.cancel_refinement();
+        }
+    }
+    pub fn is_refining_edges(&self) -> bool {
+        self.refine_edges
+    }
+
+    /// `threads` 0 is one per CPU. an unfinished render continues on the new threads.
+    pub fn set_render_threads(&mut self, threads: usize, priority: RenderPriority) {
+        self.render_threads = threads;
+        self.render_priority = priority;
+        if self.update_pool() {
+            self.continue_on_new_pool();
+        }
+    }
+    pub fn get_render_threads(&self) -> (usize, RenderPriority) {
+        (self.render_threads, self.render_priority)
+    }
+    /// how many threads render right now
+    pub fn current_render_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// renders at low priority while the window isn't focused, see `set_focused`
+    pub fn set_background_mode(&mut self, background_mode: bool) {
+        self.background_mode = background_mode;
+        self.update_priority();
+    }
+    pub fn is_background_mode(&self) -> bool {
+        self.background_mode
+    }
+    /// whether the window has the focus, for the background mode. the threads of a running
+    /// render change their priority as they go.
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.update_priority();
+    }
+
+    /// the priority that the threads should have right now
+    fn current_priority(&self) -> RenderPriority {
+        if self.background_mode && !self.focused {
+            RenderPriority::Low
+        } else {
+            self.render_priority
+        }
+    }
+
+    fn update_priority(&mut self) {
+        let priority = self.current_priority();
+        self.pool_lowered |= priority != self.pool.config().priority;
+        self.render_gate.set_priority(priority);
+    }
+
+    /// rebuilds the pool if the thread count or priority changed, or its threads might still be
+    /// lowered by the background mode. true if it did.
+    fn update_pool(&mut self) -> bool {
+        let config = PoolConfig {
+            threads: self.render_threads,
+            priority: self.render_priority,
+        };
+        let renew = self.pool_lowered && self.current_priority() == config.priority;
+        if config == self.pool.config() && !renew {
+            return false;
+        }
+        match RenderPool::new(config) {
+            Ok(pool) => {
+                info!("render pool: {:?}", config);
+                self.pool = pool;
+                self.pool_lowered = false;
+                true
+            }
+            Err(e) => {
+                error!("failed to create the render pool: {}", e);
+                false
+            }
+        }
+    }
+
+    /// moves an unfinished render or refinement over to a new pool
+    fn continue_on_new_pool(&mut self) {
+        if let WorkerState::Refining { .. } = self.state {
+            // starts over on the new pool
+            self.refined_render = None;
+        }
+        if !self.is_finished() || self.refined_render.is_none() {
+            self.continue_render(None);
+        }
+    }
+
+    /// holds the threads of the render (and of the refinement after it) before their next chunk
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused == self.paused_at.is_some() {
+            return;
+        }
+        if paused {
+            self.paused_at = Some(Instant::now());
+        } else if let Some(paused_at) = self.paused_at.take() {
+            // the time spent paused doesn't count
+            self.render_started += paused_at.elapsed();
+        }
+        self.render_gate.set_paused(paused);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    /// an unfinished render continues in the new order
+    pub fn set_chunk_order(&mut self, order: ChunkOrder, seed: u64) {
+        if (order, seed) == (self.chunk_schedule.order, self.chunk_schedule.seed) {
+            return;
+        }
+        self.chunk_schedule.order = order;
+        self.chunk_schedule.seed = seed;
+        if !self.is_finished() {
+            self.continue_render(None);
+        }
+    }
+    pub fn get_chunk_order(&self) -> (ChunkOrder, u64) {
+        (self.chunk_schedule.order, self.chunk_schedule.seed)
+    }
+    /// where `ChunkOrder::NearCursor` starts the next render, in cells
+    pub fn set_cursor(&mut self, cursor: Option<[u32; 2]>) {
+        self.chunk_schedule.cursor = cursor;
+    }
+
+    /// applies from the next render on
+    pub fn set_chunk_budget(&mut self, chunk_budget: Duration) {
+        self.chunk_budget = chunk_budget;
+    }
+    pub fn get_chunk_budget(&self) -> Duration {
+        self.chunk_budget
+    }
+    pub fn get_slow_chunks(&self) -> Vec<SlowChunk> {
+        self.slow_chunks.lock().unwrap().clone()
+    }
+
+    pub fn get_plugin_process_status(&self) -> Option<ProcessStatus> {
+        self.plugin_process.as_ref().map(PluginProcess::status)
+    }
+
+    /// kills the plugin process (eg. when a plugin hangs) and renders again with a new one
+    pub fn restart_plugin_process(&mut self) {
+        if let Some(plugin_process) = &self.plugin_process {
+            plugin_process.restart();
+            self.reset();
+            self.start_worker(None);
+        }
+    }
+
+    /// replaces the function objects with the defaults of the new libraries, with the current
+    /// view and settings carried over. the old libraries are unloaded once the last render job
+    /// using them is done.
+    fn install_libraries(
+        &mut self,
+        fractal_lib: Option<Plugin<FractalLib_Ref>>,
+        color_lib: Option<Plugin<ColorLib_Ref>>,
+    ) {
+        let fractal_lib_changed = fractal_lib.is_some();
+        let mut dropped_options = vec![];
+        if let Some(fractal_lib) = fractal_lib {
+            plugin_log::init_lib(&fractal_lib);
+            let (fractal_func, dropped) = replay_options(
+                fractal_lib.default_fractal_func_for_size(self.width, self.height),
+                &self.fractal_func.get_options(),
+                FractalFunc::with_option,
+            );
+            dropped_options.extend(dropped);
+            self.fractal_func = fractal_func;
+            self.fractal_lib = fractal_lib;
+        }
+        if let Some(color_lib) = color_lib {
+            plugin_log::init_lib(&color_lib);
+            let (color_func, dropped) = replay_options(
+                color_lib.default_color_func(),
+                &self.color_func.get_options(),
+                ColorFunc::with_option,
+            );
+            dropped_options.extend(dropped);
+            self.set_color_func(color_func);
+            self.color_lib = color_lib;
+            self.color_stats.clear_applied();
+        }
+        for msg in dropped_options.iter() {
+            warn!("option dropped on reload: {}", msg);
+        }
+        self.dropped_options = dropped_options;
+
+        if fractal_lib_changed {
+            self.reset();
+            self.start_worker(None);
+        } else {
+            self.continue_render(None);
+        }
+    }
+
+    pub fn set_build_configs(&mut self, fractal: BuildConfig, color: BuildConfig) {
+        self.rebuilder.set_configs(vec![fractal, color]);
+    }
+    pub fn get_build_configs(&self) -> &[BuildConfig] {
+        self.rebuilder.configs()
+    }
+
+    /// runs the build commands, and reloads the libraries if they succeed.
+    /// without build commands, this just reloads.
+    pub fn rebuild_and_reload(&mut self) {
+        self.rebuilder.start_build();
+    }
+
+    pub fn set_watch_sources(&mut self, watch: bool) {
+        self.rebuilder.set_watching(watch);
+    }
+    pub fn is_watching_sources(&self) -> bool {
+        self.rebuilder.is_watching()
+    }
+
+    /// the options that couldn't be carried over by the last reload
+    pub fn get_dropped_options(&self) -> &[String] {
+        &self.dropped_options
+    }
+
+    pub fn get_build_status(&self) -> BuildStatus {
+        self.rebuilder.status().clone()
+    }
+    pub fn get_build_output(&self) -> &str {
+        self.rebuilder.output()
+    }
+
+    fn poll_rebuild(&mut self) {
+        if self.rebuilder.poll() {
+            info!("rebuild succeeded, reloading libraries");
+            if let Err(e) = self.reload_libraries() {
+                error!("error reloading libraries: {:#}", e);
+                self.rebuilder.set_reload_failed(format!("{:#}", e));
+            }
+        }
+    }
+
     pub fn get_size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
@@ -171,46 +838,66 @@ impl FractalWorker {
     pub fn reset_fractal_options(&mut self) {
         self.reset();
         self.start_worker(
-            self.fractal_lib.default_fractal_func_for_size()(
-                min(self.width, self.height),
-                min(self.width, self.height),
-            )
-            .with_size(self.width, self.height),
-            None,
-            None,
+            self.fractal_lib
+                .default_fractal_func_for_size(
+                    min(self.width, self.height),
+                    min(self.width, self.height),
+                )
+                .with_size(self.width, self.height),
         )
     }
     pub fn set_fractal_options(&mut self, new_options: RHashMap<RString, RString>) {
         let mut fractal_func = self.fractal_func.clone();
         for Tuple2(name, value) in new_options.into_iter() {
-            fractal_func = match fractal_func.with_option(name.as_rstr(), value.as_rstr()) {
-                ROk(cell_func) => cell_func,
-                RErr(msg) => {
-                    println!("failed to set option {}={}: {}", name, value, msg);
+            fractal_func = match fractal_func.with_option(name.as_str(), value.as_str()) {
+                Ok(cell_func) => cell_func,
+                Err(msg) => {
+                    self.option_failed(&name, &value, &msg);
                     return;
                 }
             }
         }
         self.reset();
-        self.start_worker(fractal_func, None, None);
+        self.start_worker(fractal_func);
     }
     pub fn set_color_options(&mut self, new_options: RHashMap<RString, RString>) {
         let mut color_func = self.color_func.clone();
         for Tuple2(name, value) in new_options.into_iter() {
-            color_func = match color_func.with_option(name.as_rstr(), value.as_rstr()) {
-                ROk(cell_func) => cell_func,
-                RErr(msg) => {
-                    println!("failed to set option {}={}: {}", name, value, msg);
+            color_func = match color_func.with_option(name.as_str(), value.as_str()) {
+                Ok(cell_func) => cell_func,
+                Err(msg) => {
+                    self.option_failed(&name, &value, &msg);
                     return;
                 }
             }
         }
-        self.reset();
-        self.start_worker(None, color_func, None);
+        self.set_color_func(color_func);
+        self.color_stats.clear_applied();
+        self.continue_render(None);
+    }
+
+    fn option_failed(&mut self, name: &str, value: &str, msg: &str) {
+        let msg = format!("failed to set option {}={}: {}", name, value, msg);
+        warn!("{}", msg);
+        self.state = WorkerState::Error(msg);
+    }
+
+    fn set_color_func(&mut self, color_func: ColorFunc) {
+        self.color_generation = self.shared_color_func.set(color_func.clone());
+        self.color_func = color_func;
+        // what it refines would be drawn over by the recolor
+        self.cancel_refinement();
     }
 
     pub fn get_state(&self) -> WorkerState {
-        self.state
+        self.state.clone()
+    }
+    /// whether every cell was rendered, refined or not
+    fn is_finished(&self) -> bool {
+        matches!(
+            self.state,
+            WorkerState::Finished | WorkerState::Refining { .. }
+        )
     }
 
     /// range: 0 to 1
@@ -219,30 +906,118 @@ impl FractalWorker {
             WorkerState::Init => 0.0,
             WorkerState::Interrupted => 0.0,
             WorkerState::Started => 0.0,
-            WorkerState::Working { total, completed } => completed as f32 / total as f32,
+            WorkerState::Working { .. } => self.progress.fraction(),
             WorkerState::Finished => 1.0,
+            WorkerState::Refining { total, completed } => completed as f32 / total.max(1) as f32,
+            WorkerState::Error(_) => 0.0,
+        }
+    }
+
+    /// the counts are of what the render has to do, the retained chunks of a pan aren't part of it
+    pub fn get_render_progress(&self) -> RenderProgress {
+        let mut progress = self.progress.clone();
+        if !self.is_finished() {
+            let now = self.paused_at.unwrap_or_else(Instant::now);
+            progress.elapsed = now.saturating_duration_since(self.render_started);
+        }
+        progress
+    }
+
+    pub fn get_tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        if tone_mapping != self.tone_mapping {
+            self.tone_mapping = tone_mapping;
+            self.should_redraw_screen = true;
         }
     }
 
+    /// the current image as linear rgba floats, not tone mapped (see `ColorBuffer::to_linear_rgba`)
+    pub fn get_linear_image(&self) -> (u32, u32, Vec<f32>) {
+        let (width, height) = self.color_buffer.size();
+        (width, height, self.color_buffer.to_linear_rgba())
+    }
+
+    /// the current image as rgba with 16 bits per channel, tone mapped but not dithered
+    pub fn get_rgba16_image(&self) -> (u32, u32, Vec<u16>) {
+        let (width, height) = self.color_buffer.size();
+        (
+            width,
+            height,
+            self.color_buffer.to_rgba16(&self.tone_mapping),
+        )
+    }
+
     pub fn draw_new_chunks(&mut self, width: u32, height: u32, screen: &mut [u8]) {
+        self.poll_rebuild();
+        if self.color_buffer.size() != (width, height) {
+            self.color_buffer = ColorBuffer::new(width, height);
+            self.cell_costs = CellCosts::new(width, height);
+            self.should_clear_screen = true;
+        }
         if self.should_clear_screen {
             for rgba in screen.chunks_exact_mut(4) {
                 rgba.copy_from_slice(&[0, 0, 0, 0]);
             }
+            self.color_buffer.clear();
             self.should_clear_screen = false;
+            self.should_redraw_screen = false;
+            self.screen_offset = None;
+        }
+        if self.should_redraw_screen {
+            self.color_buffer.redraw(&self.tone_mapping, screen);
+            self.should_redraw_screen = false;
+            self.screen_offset = None;
+        }
+        if let Some(offset) = self.screen_offset.take() {
+            self.color_buffer.translate_screen(offset, screen);
         }
 
         self.width = width;
         self.height = height;
+        let mut stale_chunks = vec![];
+        let mut new_stats = None;
         if let Some(receiver) = &self.receiver {
             for message in receiver.try_iter() {
                 match message {
                     WorkerMessage::Init => self.state = WorkerState::Init,
-                    // TODO: what if it's finished but there's still messages in the buffer?
-                    WorkerMessage::Finished => self.state = WorkerState::Finished,
-                    WorkerMessage::Chunk(rchunk, rcolors, epoch) if epoch == self.epoch => {
-                        draw_chunk_colors(rcolors, width, screen);
-                        self.chunks.push(rchunk);
+                    WorkerMessage::Scheduled { chunks, cells } => {
+                        self.progress.total_chunks = chunks;
+                        self.progress.total_cells = cells;
+                        self.state = WorkerState::Working {
+                            total: chunks,
+                            completed: 0,
+                        };
+                    }
+                    // every chunk was sent before this, so they are all drawn by now
+                    WorkerMessage::Finished => {
+                        self.progress.elapsed = self.render_started.elapsed();
+                        self.state = WorkerState::Finished;
+                    }
+                    WorkerMessage::Error(msg) => {
+                        error!("render failed: {}", msg);
+                        self.state = WorkerState::Error(msg);
+                    }
+                    WorkerMessage::Chunk(rendered, epoch) if epoch == self.epoch => {
+                        self.color_buffer.draw_colors(
+                            &rendered.colors,
+                            self.render_block_size,
+                            &self.tone_mapping,
+                            screen,
+                        );
+                        if let Some(elapsed) = rendered.elapsed {
+                            self.cell_costs.record(&rendered.chunk, elapsed);
+                        }
+                        self.progress.completed_chunks += 1;
+                        self.progress.completed_cells += rendered.chunk.len();
+                        if rendered.color_generation != self.color_generation {
+                            // colored by a color func that was replaced while this chunk was in flight
+                            stale_chunks.push(self.chunks.len());
+                        }
+                        self.chunks.push(Arc::new(rendered.chunk));
+                        self.chunk_values.push(Arc::new(rendered.values));
+                        self.chunk_color_generations.push(rendered.color_generation);
                         if let WorkerState::Working { total, completed } = self.state {
                             self.state = WorkerState::Working {
                                 total,
@@ -250,21 +1025,212 @@ impl FractalWorker {
                             };
                         }
                     }
+                    WorkerMessage::Recolored {
+                        index,
+                        colors,
+                        values,
+                        color_generation,
+                    } => {
+                        self.pending_recolors = self.pending_recolors.saturating_sub(1);
+                        if color_generation != self.color_generation {
+                            continue;
+                        }
+                        self.color_buffer
+                            .draw_colors(&colors, 1, &self.tone_mapping, screen);
+                        if let Some(values) = values {
+                            self.chunk_values[index] = Arc::new(values);
+                        }
+                        self.chunk_color_generations[index] = color_generation;
+                    }
+                    WorkerMessage::Refining {
+                        pixels,
+                        color_generation,
+                    } if color_generation == self.color_generation => {
+                        self.color_buffer.settle(&pixels);
+                        if let WorkerState::Refining { .. } = self.state {
+                            let total = pixels.len();
+                            self.state = if total > 0 {
+                                WorkerState::Refining {
+                                    total,
+                                    completed: 0,
+                                }
+                            } else {
+                                WorkerState::Finished
+                            };
+                        }
+                    }
+                    WorkerMessage::Refined {
+                        pixels,
+                        color_generation,
+                    } if color_generation == self.color_generation => {
+                        self.color_buffer
+                            .draw_pixels(&pixels, &self.tone_mapping, screen);
+                        if let WorkerState::Refining { total, completed } = self.state {
+                            let completed = completed + pixels.len();
+                            self.state = if completed < total {
+                                WorkerState::Refining { total, completed }
+                            } else {
+                                WorkerState::Finished
+                            };
+                        }
+                    }
+                    WorkerMessage::Statistics(stats, epoch) if epoch == self.epoch => {
+                        self.computing_stats = false;
+                        new_stats = self.color_stats.finish(stats);
+                    }
                     _ => (),
                 }
             }
         }
+
+        if !stale_chunks.is_empty() {
+            self.recolor_chunks(stale_chunks, self.color_func.wants_statistics());
+        }
+        if let Some(stats) = new_stats {
+            self.apply_color_statistics(&stats);
+        }
+        self.update_color_statistics();
+        if self.refine_edges
+            && self.state == WorkerState::Finished
+            && self.pending_recolors == 0
+            && !self.computing_stats
+            && self.refined_render != Some((self.epoch, self.color_generation))
+        {
+            self.start_refinement();
+        }
+    }
+
+    /// gives the high contrast pixels that weren't refined yet more samples, see
+    /// `SubpixelSampler`. after a pan, those are the ones of the newly exposed strips.
+    fn start_refinement(&mut self) {
+        self.refined_render = Some((self.epoch, self.color_generation));
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => return,
+        };
+        if !self.fractal_func.can_resample() {
+            info!("the fractal func can't sample between pixels, not refining");
+            return;
+        }
+        // the total is known once the pool has looked for the pixels
+        self.state = WorkerState::Refining {
+            total: 0,
+            completed: 0,
+        };
+        self.refinement_cancelled = Default::default();
+        refine_pixels(
+            self.color_buffer.clone(),
+            self.fractal_func.clone(),
+            self.color_func.clone(),
+            self.color_generation,
+            self.tone_mapping,
+            self.refinement_cancelled.clone(),
+            &self.pool,
+            self.render_gate.clone(),
+            sender,
+        );
+    }
+
+    fn cancel_refinement(&mut self) {
+        self.refinement_cancelled.store(true, Ordering::Relaxed);
+        if let WorkerState::Refining { .. } = self.state {
+            self.state = WorkerState::Finished;
+        }
+    }
+
+    /// computes statistics over the cell values on a thread of their own whenever they are due,
+    /// see `ColorStatsCollector`
+    fn update_color_statistics(&mut self) {
+        if !self.color_func.wants_statistics() || self.computing_stats {
+            return;
+        }
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => return,
+        };
+        let value_count = self.chunk_values.iter().map(|values| values.len()).sum();
+        if !self.color_stats.start(value_count, self.is_finished()) {
+            return;
+        }
+        self.computing_stats = true;
+        let chunk_values = self.chunk_values.clone();
+        let epoch = self.epoch;
+        std::thread::spawn(move || {
+            let stats = compute_stats(&chunk_values);
+            // fails if a new render started, then these are outdated anyway
+            let _ = sender.send(WorkerMessage::Statistics(stats, epoch));
+        });
+    }
+
+    /// hands new statistics to the color func, and recolors everything that was already drawn
+    fn apply_color_statistics(&mut self, stats: &RColorStats) {
+        match self.color_func.with_statistics(stats) {
+            Ok(color_func) => {
+                info!("color statistics changed, recoloring");
+                self.set_color_func(color_func);
+                // the values of chunks whose recoloring was outdated by these statistics are missing
+                let (complete, missing): (Vec<_>, Vec<_>) = (0..self.chunks.len())
+                    .partition(|&index| self.chunk_values[index].len() == self.chunks[index].len());
+                self.recolor_chunks(complete, false);
+                if !missing.is_empty() {
+                    self.recolor_chunks(missing, true);
+                }
+            }
+            Err(msg) => error!("failed to apply color statistics: {}", msg),
+        }
+    }
+
+    fn recolor_chunks(&mut self, indexes: Vec<usize>, compute_values: bool) {
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => return,
+        };
+        self.pending_recolors += indexes.len();
+        let chunks = indexes
+            .into_iter()
+            .map(|index| (index, self.chunks[index].clone()))
+            .collect_vec();
+        let neighbors = NeighborSource {
+            chunks: self.chunks.clone(),
+            fractal_func: self.fractal_func.clone(),
+            width: self.width,
+            height: self.height,
+            block_size: self.render_block_size,
+        };
+        recolor_chunks(
+            chunks,
+            self.color_func.clone(),
+            self.color_generation,
+            compute_values,
+            neighbors,
+            &self.pool,
+            self.render_gate.clone(),
+            sender,
+        );
     }
 
     fn reset(&mut self) {
         // self.receiver = None;
         self.chunks = vec![];
+        self.chunk_values = vec![];
+        self.chunk_color_generations = vec![];
+        self.color_stats.reset();
         self.should_clear_screen = true;
     }
 
     pub fn apply_offset_and_zoom_factor(&mut self, dx: i32, dy: i32, zoom_factor: f64) {
         info!("apply_offset_and_zoom_factor");
-        self.reset();
+        let zoomed = (zoom_factor - 1.0).abs() > 0.0001;
+        if zoomed {
+            self.reset();
+        } else {
+            // the cells that stay on screen just move, only the exposed strips are rendered
+            self.translate_chunks([dx, dy]);
+            self.translate_screen([dx, dy]);
+        }
+        self.cell_costs = self
+            .cell_costs
+            .moved(self.width, self.height, [dx, dy], zoom_factor);
         let dx = -dx;
         let dy = -dy;
 
@@ -272,222 +1238,822 @@ impl FractalWorker {
         if dx != 0 || dy != 0 {
             new_func = new_func.with_offset(dx, dy);
         }
-        if (zoom_factor - 1.0).abs() > 0.0001 {
+        if zoomed {
             new_func = new_func.add_zoom(zoom_factor);
         }
-        self.start_worker(new_func, None, None);
+        self.continue_render(new_func);
+    }
+
+    /// moves the retained chunks by `offset` cells, dropping what falls off the screen
+    fn translate_chunks(&mut self, offset: [i32; 2]) {
+        let (width, height) = (self.width, self.height);
+        let ((chunks, chunk_values), chunk_color_generations) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .zip(std::mem::take(&mut self.chunk_values))
+            .zip(std::mem::take(&mut self.chunk_color_generations))
+            .filter_map(|((chunk, values), color_generation)| {
+                Some((
+                    translate_chunk(chunk, values, offset, width, height)?,
+                    color_generation,
+                ))
+            })
+            .unzip();
+        self.chunks = chunks;
+        self.chunk_values = chunk_values;
+        self.chunk_color_generations = chunk_color_generations;
+        self.color_stats.reset();
+    }
+
+    /// moves the colors on screen along with the chunks
+    fn translate_screen(&mut self, offset: [i32; 2]) {
+        self.color_buffer.translate(offset);
+        if self.screen_offset.is_some() {
+            // panned twice without a draw in between
+            self.should_redraw_screen = true;
+        } else {
+            self.screen_offset = Some(offset);
+        }
+    }
+
+    /// renders what the retained chunks don't cover, and recolors the ones that were colored by
+    /// an older color func
+    fn continue_render(&mut self, fractal_func: impl Into<Option<FractalFunc>>) {
+        self.start_worker(fractal_func);
+        self.recolor_stale_chunks();
+    }
+
+    /// recolors the retained chunks that an older color func colored, because it was replaced or
+    /// because their recoloring went to a previous render and was lost
+    fn recolor_stale_chunks(&mut self) {
+        let stale_chunks = (0..self.chunks.len())
+            .filter(|&index| self.chunk_color_generations[index] != self.color_generation)
+            .collect_vec();
+        if !stale_chunks.is_empty() {
+            self.recolor_chunks(stale_chunks, self.color_func.wants_statistics());
+        }
     }
 
     pub fn apply_resize(&mut self, new_size: (u32, u32)) {
         info!("apply_resize");
-        self.start_worker(
-            self.fractal_func.with_size(new_size.0, new_size.1),
-            None,
-            new_size,
-        );
-        // TODO: move around the stuff in pixels.frame so that it's in the right place, instead of clearing it
-        self.reset();
+        let (width, height) = new_size;
+        // `with_size` keeps the middle cell where it is, everything else moves with it
+        let offset = [
+            (width / 2) as i32 - (self.width / 2) as i32,
+            (height / 2) as i32 - (self.height / 2) as i32,
+        ];
+        let fractal_func = self.fractal_func.with_size(width, height);
+        self.width = width;
+        self.height = height;
+        self.translate_chunks(offset);
+        self.color_buffer.resize(width, height, offset);
+        self.cell_costs = self.cell_costs.moved(width, height, offset, 1.0);
+        // the screen was resized as well, so there is nothing to move
+        self.should_redraw_screen = true;
+        self.screen_offset = None;
+        self.continue_render(fractal_func);
     }
 
-    fn start_worker(
-        &mut self,
-        fractal_func: impl Into<Option<RFractalFuncBox>>,
-        color_func: impl Into<Option<RColorFuncBox>>,
-        new_size: impl Into<Option<(u32, u32)>>,
-    ) {
+    fn start_worker(&mut self, fractal_func: impl Into<Option<FractalFunc>>) {
         if let Some(fractal_func) = fractal_func.into() {
             self.fractal_func = fractal_func;
         }
-        if let Some(color_func) = color_func.into() {
-            self.color_func = color_func;
-        }
-        let existing_chunks_offset = if let Some((width, height)) = new_size.into() {
-            let dx = (width as i32 - self.width as i32) / 2;
-            let dy = (height as i32 - self.height as i32) / 2;
-            self.width = width;
-            self.height = height;
-            [dx, dy]
+        self.cancel_refinement();
+        // lets the threads of the previous render go, if it was paused
+        self.render_gate.close();
+        // a new render is a good time to replace threads that the background mode lowered
+        self.update_pool();
+        self.render_gate = Arc::new(RenderGate::new(self.is_paused(), self.current_priority()));
+        let (sender, receiver) = channel();
+        self.epoch = self.epoch.wrapping_add(1);
+        // the recolors and statistics of the previous render went to its channel
+        self.pending_recolors = 0;
+        self.computing_stats = false;
+        self.render_block_size = if self.progressive {
+            PROGRESSIVE_BLOCK_SIZE
+        } else {
+            1
+        };
+        let remote_render = if self.remote_workers.is_empty() {
+            self.remote_stats = Default::default();
+            None
+        } else {
+            // a fresh vec, so that the threads of the previous render can't touch it
+            self.remote_stats = Arc::new(Mutex::new(
+                self.remote_workers
+                    .iter()
+                    .map(|addr| RemoteWorkerStats {
+                        addr: addr.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ));
+            Some(RemoteRender {
+                addrs: self.remote_workers.clone(),
+                fractal: self.fractal_func.remote_state(&self.fractal_lib_path),
+                color_lib: self.color_lib_path.clone(),
+                block_size: self.render_block_size,
+                stats: self.remote_stats.clone(),
+            })
+        };
+        self.slow_chunks = Default::default();
+        let fill_safe_fields = if self.boundary_fill {
+            self.fractal_func.fill_safe_fields()
         } else {
-            [0, 0]
+            vec![]
         };
-        self.receiver = start_worker(
+        let watchdog = ChunkWatchdog::start(
+            self.chunk_budget,
+            RenderPlugins {
+                fractal_lib: self.fractal_lib_path.clone(),
+                fractal_options: wire_options(&self.fractal_func.get_options()),
+                color_lib: self.color_lib_path.clone(),
+                color_options: wire_options(&self.color_func.get_options()),
+            },
+            self.plugin_process.clone(),
+            self.slow_chunks.clone(),
+        );
+        start_worker(
             self.width,
             self.height,
             &self.fractal_func,
-            &self.color_func,
+            &self.shared_color_func,
             self.epoch,
             self.chunk_size,
-            std::mem::take(&mut self.chunks),
-            existing_chunks_offset,
-        )
-        .into();
+            self.adaptive_chunk_size.then(|| self.cell_costs.clone()),
+            self.render_block_size,
+            self.chunk_schedule,
+            fill_safe_fields,
+            self.chunks.clone(),
+            remote_render,
+            watchdog,
+            &self.pool,
+            self.render_gate.clone(),
+            sender.clone(),
+        );
+        self.receiver = Some(receiver);
+        self.sender = Some(sender);
         self.state = WorkerState::Started;
+        self.progress = Default::default();
+        self.render_started = Instant::now();
+        if self.is_paused() {
+            self.paused_at = Some(self.render_started);
+        }
     }
 }
 
-fn draw_chunk_colors(rcolors: RVec<RColor>, width: u32, screen: &mut [u8]) {
-    let width = width as usize;
-    for rcolor in rcolors {
-        let x = rcolor.pos[0] as usize;
-        let y = rcolor.pos[1] as usize;
-        let idx = (x + y * width) * 4;
-        // if (idx + 3) >= screen.len() {
-        //     dbg!(width, x, y, idx, screen.len());
-        // }
-        screen[idx..idx + 3].copy_from_slice(&rcolor.rgb);
-        screen[idx + 3] = 0xff;
+fn load_plugin<M: BuiltinPlugins>(
+    path: &Path,
+    plugin_process: Option<&PluginProcess>,
+) -> anyhow::Result<Plugin<M>> {
+    match plugin_process {
+        Some(plugin_process) => Plugin::load_remote(path, plugin_process),
+        None => Plugin::load(path),
     }
 }
 
-fn get_all_pixel_positions(width: u32, height: u32, chunk_size: usize) -> Vec<Vec<[u32; 2]>> {
-    let num_chunks = (0..width).step_by(chunk_size).len() * (0..height).step_by(chunk_size).len();
-    info!(
-        "chunk_size={chunk_size}, num_chunks={num_chunks}, per thread: {}",
-        num_chunks / current_num_threads()
-    );
-    let mut chunks = Vec::with_capacity(num_chunks);
-    for xmin in (0..width).step_by(chunk_size) {
-        for ymin in (0..height).step_by(chunk_size) {
-            chunks.push(
-                (xmin..min(width, xmin + chunk_size as u32))
-                    .cartesian_product(ymin..min(height, ymin + chunk_size as u32))
-                    .map(|(x, y)| [x, y])
-                    .collect_vec(),
-            );
+/// applies `options` to `func` one by one, skipping (and returning) the ones it rejects
+fn replay_options<F>(
+    mut func: F,
+    options: &ROptionsMap,
+    with_option: impl Fn(&F, &str, &str) -> Result<F, String>,
+) -> (F, Vec<String>) {
+    let mut dropped = vec![];
+    let options = options
+        .iter()
+        .map(|Tuple2(name, value)| (name.as_str(), value.as_str()))
+        .sorted();
+    for (name, value) in options {
+        match with_option(&func, name, value) {
+            Ok(new_func) => func = new_func,
+            Err(msg) => dropped.push(format!("{}={}: {}", name, value, msg)),
         }
     }
-    chunks
+    (func, dropped)
 }
 
-fn _get_incomplete_pixel_positions(
+/// the cells that none of `existing_chunks` covers, in chunks of up to `chunk_size` squared
+/// cells, or sized from `cell_costs` if there are any. in the order that the queue hands them
+/// out (from the end). with a `block_size` above 1
+/// the render is progressive: every `block_size`th cell comes first, then passes that halve the
+/// spacing and skip the cells of the coarser ones. the chunks of a pass are in the order of
+/// `chunk_schedule`.
+fn get_pixel_positions(
     width: u32,
     height: u32,
     chunk_size: usize,
-    existing_chunks: &[RChunk],
+    cell_costs: Option<&CellCosts>,
+    block_size: u32,
+    chunk_schedule: &ChunkSchedule,
+    existing_chunks: &[Arc<RChunk>],
 ) -> Vec<Vec<[u32; 2]>> {
-    let mut coordinates: HashSet<_> = (0..height)
-        .cartesian_product(0..width)
-        .map(|(y, x)| [x, y])
-        .collect();
+    let mut covered = vec![false; (width * height) as usize];
+    for [x, y] in existing_chunks.iter().flat_map(|chunk| chunk.positions()) {
+        if x < width && y < height {
+            covered[(x + y * width) as usize] = true;
+        }
+    }
+    let mut passes = vec![];
+    let mut spacing = block_size;
+    while spacing >= 1 {
+        // about as many cells per chunk in every pass
+        let tile_size = match cell_costs {
+            Some(_) => MAX_CHUNK_SIZE.max(chunk_size as u32) * spacing,
+            None => chunk_size as u32 * spacing,
+        };
+        let mut chunks = vec![];
+        for xmin in (0..width).step_by(tile_size as usize) {
+            for ymin in (0..height).step_by(tile_size as usize) {
+                let positions = (xmin..min(width, xmin + tile_size))
+                    .step_by(spacing as usize)
+                    .cartesian_product(
+                        (ymin..min(height, ymin + tile_size)).step_by(spacing as usize),
+                    )
+                    .map(|(x, y)| [x, y])
+                    .filter(|&pos| cell_block_size(pos, block_size) == spacing)
+                    .filter(|&[x, y]| !covered[(x + y * width) as usize])
+                    .collect_vec();
+                match cell_costs {
+                    Some(cell_costs) => cell_costs.split_tile(
+                        positions,
+                        [xmin, ymin],
+                        tile_size,
+                        spacing,
+                        chunk_size as u32,
+                        &mut chunks,
+                    ),
+                    None if !positions.is_empty() => chunks.push(positions),
+                    None => (),
+                }
+            }
+        }
+        chunk_schedule.sort(&mut chunks, width, height);
+        // the queue hands them out from the end
+        chunks.reverse();
+        passes.push(chunks);
+        spacing /= 2;
+    }
+    let chunks = passes.into_iter().rev().flatten().collect_vec();
+    info!(
+        "chunk_size={chunk_size}, adaptive={}, block_size={block_size}, {} chunks were retained, {} chunks to render, per thread: {}",
+        cell_costs.is_some(),
+        existing_chunks.len(),
+        chunks.len(),
+        chunks.len() / current_num_threads()
+    );
+    chunks
+}
 
-    for chunk in existing_chunks {
-        for (pos, _) in chunk.iter() {
-            coordinates.remove(&pos);
+/// `chunk` and its cell values moved by `offset`, without the cells that fall off the screen
+fn translate_chunk(
+    mut chunk: Arc<RChunk>,
+    values: Arc<RVec<f64>>,
+    offset: [i32; 2],
+    width: u32,
+    height: u32,
+) -> Option<(Arc<RChunk>, Arc<RVec<f64>>)> {
+    let translate = |[x, y]: [u32; 2]| {
+        let x = x as i64 + offset[0] as i64;
+        let y = y as i64 + offset[1] as i64;
+        ((0..width as i64).contains(&x) && (0..height as i64).contains(&y))
+            .then_some([x as u32, y as u32])
+    };
+    if chunk.positions().all(|pos| translate(pos).is_some()) {
+        // the common case, the cell data stays where it is
+        for Tuple2(pos, _) in Arc::make_mut(&mut chunk).pos_indexes.iter_mut() {
+            *pos = translate(*pos)?;
         }
+        return Some((chunk, values));
     }
+    let mut translated = RChunk::default();
+    let mut translated_values = RVec::new();
+    for (index, (pos, data)) in chunk.iter().enumerate() {
+        if let Some(pos) = translate(pos) {
+            translated.push(pos, data);
+            translated_values.extend(values.get(index).copied());
+        }
+    }
+    (!translated.is_empty()).then(|| (Arc::new(translated), Arc::new(translated_values)))
+}
 
-    coordinates
-        .into_iter()
-        .sorted()
-        .chunks(chunk_size)
-        .into_iter()
-        .map(|vs| vs.collect_vec())
-        .collect_vec()
+enum RenderError {
+    Interrupted,
+    PluginFailed,
 }
 
+/// the chunks of a render that nobody has picked up yet
+struct ChunkQueue {
+    chunks: Mutex<Vec<Vec<[u32; 2]>>>,
+    closed: AtomicBool,
+    gate: Arc<RenderGate>,
+}
+
+impl ChunkQueue {
+    fn new(chunks: Vec<Vec<[u32; 2]>>, gate: Arc<RenderGate>) -> Self {
+        Self {
+            chunks: Mutex::new(chunks),
+            closed: AtomicBool::new(false),
+            gate,
+        }
+    }
+
+    /// waits while the render is paused
+    fn pop(&self) -> Option<Vec<[u32; 2]>> {
+        if !self.gate.pass() || self.closed.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.chunks.lock().unwrap().pop()
+    }
+
+    /// for chunks that a render server failed to render
+    fn push(&self, positions: Vec<[u32; 2]>) {
+        self.chunks.lock().unwrap().push(positions);
+    }
+
+    /// stops handing out chunks, once the render failed
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+/// what the render servers need to help with a render
+struct RemoteRender {
+    addrs: Vec<String>,
+    fractal: RemoteFractalState,
+    color_lib: PathBuf,
+    block_size: u32,
+    stats: Arc<Mutex<Vec<RemoteWorkerStats>>>,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn start_worker(
     width: u32,
     height: u32,
-    fractal_func: &RFractalFuncBox,
-    color_func: &RColorFuncBox,
+    fractal_func: &FractalFunc,
+    color_func: &SharedColorFunc,
     epoch: u32,
     chunk_size: usize,
-    _existing_chunks: Vec<RChunk>,
-    existing_chunks_offset: [i32; 2],
-) -> Receiver<WorkerMessage> {
+    cell_costs: Option<CellCosts>,
+    block_size: u32,
+    chunk_schedule: ChunkSchedule,
+    fill_safe_fields: Vec<String>,
+    existing_chunks: Vec<Arc<RChunk>>,
+    remote_render: Option<RemoteRender>,
+    watchdog: ChunkWatchdog,
+    pool: &RenderPool,
+    gate: Arc<RenderGate>,
+    sender: Sender<WorkerMessage>,
+) {
     // println!("starting worker");
     info!("starting worker");
     let fractal_func = fractal_func.clone();
     let color_func = color_func.clone();
-    let (sender, receiver) = channel();
 
-    rayon::spawn(move || {
+    pool.spawn(move || {
         info!("worker thread started");
-        sender
-            .send(WorkerMessage::Init)
-            .expect("interrupted before beginning render");
-
-        if existing_chunks_offset != [0, 0] {
-            dbg!(existing_chunks_offset);
-        }
-
-        // TODO: fix the existing chunk recoloring thingy to work with new chunk type
-        // let existing_chunks: Vec<RChunk> = existing_chunks
-        //     .into_par_iter()
-        //     .map(|chunk| {
-        //         chunk
-        //             .into_iter()
-        //             .filter_map(|mut cell| {
-        //                 let x = cell.pos[0] as i32 + existing_chunks_offset[0];
-        //                 let y = cell.pos[1] as i32 + existing_chunks_offset[1];
-        //                 if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
-        //                     None
-        //                 } else {
-        //                     cell.pos[0] = x as u32;
-        //                     cell.pos[1] = y as u32;
-        //                     Some(cell)
-        //                 }
-        //             })
-        //             .collect()
-        //     })
-        //     .collect();
-
-        // for chunk in existing_chunks.iter_mut() {
-        //     for cell in chunk.iter_mut() {
-        //         cell.pos[0] += existing_chunks_offset[0];
-        //         cell.pos[1] += existing_chunks_offset[1];
-        //     }
-        // }
-
-        // let pixel_positions = if existing_chunks.len() > 0 {
-        //     get_incomplete_pixel_positions(width, height, chunk_size, &existing_chunks)
-        // } else {
-        //     get_all_pixel_positions(width, height, chunk_size)
-        // };
-
-        // if existing_chunks.len() > 0 {
-        //     info!("recolor existing chunks");
-        //     // recolor existing chunks
-        //     let res = existing_chunks
-        //         .into_par_iter()
-        //         .map(|rchunk| {
-        //             let rcolors = color_func.compute_colors(&rchunk);
-        //             (rchunk, rcolors)
-        //         })
-        //         .try_for_each_with(sender.clone(), |sender, (rchunk, rcolors)| {
-        //             sender.send(WorkerMessage::Chunk(rchunk, rcolors, epoch))
-        //         });
-        //     match res {
-        //         Ok(_) => info!("existing chunks complete"),
-        //         Err(_) => info!("existing chunks interrupted"),
-        //     }
-        // } else {
-        //     info!("existing chunks empty");
-        // }
-
-        let pixel_positions = {
-            let mut pixel_positions = get_all_pixel_positions(width, height, chunk_size);
-            let mut rng = thread_rng();
-            pixel_positions.as_mut_slice().shuffle(&mut rng);
-            pixel_positions
+        if sender.send(WorkerMessage::Init).is_err() {
+            info!("render interrupted before it began");
+            return;
+        }
+
+        let pixel_positions = get_pixel_positions(
+            width,
+            height,
+            chunk_size,
+            cell_costs.as_ref(),
+            block_size,
+            &chunk_schedule,
+            &existing_chunks,
+        );
+        // so that the next pan can move them without copying
+        drop(existing_chunks);
+        let scheduled = WorkerMessage::Scheduled {
+            chunks: pixel_positions.len(),
+            cells: pixel_positions.iter().map(Vec::len).sum(),
+        };
+        if sender.send(scheduled).is_err() {
+            info!("render interrupted before it began");
+            return;
+        }
+
+        let queue = Arc::new(ChunkQueue::new(pixel_positions, gate));
+        let remote_threads = match remote_render {
+            Some(remote_render) => {
+                start_remote_renders(remote_render, &queue, &color_func, epoch, &sender)
+            }
+            None => vec![],
         };
 
-        let res = pixel_positions
-            .into_par_iter()
-            .map(|positions| {
-                let rchunk = fractal_func.compute_cells(RSlice::from(positions.as_slice()));
-                let rcolors = color_func.compute_colors(&rchunk);
-                (rchunk, rcolors)
+        let render_chunk = |positions: &[[u32; 2]]| {
+            let _guard = watchdog.watch(positions);
+            let start = Instant::now();
+            let rchunk = if fill_safe_fields.is_empty() {
+                fractal_func
+                    .compute_cells(positions)
+                    .map_err(|e| format!("fractal func: {}", e))?
+            } else {
+                compute_cells_filled(&fractal_func, positions, &fill_safe_fields)?
+            };
+            let (color_generation, color_func) = color_func.get();
+            let spacing = chunk_spacing(positions.iter().copied(), block_size);
+            let colors = compute_chunk_colors(&color_func, &rchunk, spacing, |radius| {
+                let apron = apron_positions(positions, radius, spacing, width, height);
+                fractal_func
+                    .compute_cells(&apron)
+                    .map_err(|e| format!("fractal func: {}", e))
+            })?;
+            let values = if color_func.wants_statistics() {
+                color_func
+                    .cell_values(&rchunk)
+                    .map_err(|e| format!("color func: {}", e))?
+            } else {
+                RVec::new()
+            };
+            Ok(RenderedChunk {
+                chunk: rchunk,
+                colors,
+                values,
+                color_generation,
+                elapsed: Some(start.elapsed()),
             })
-            .try_for_each_with(sender.clone(), |sender, (rchunk, rcolors)| {
-                sender.send(WorkerMessage::Chunk(rchunk, rcolors, epoch))
+        };
+        let render_local = || {
+            (0..current_num_threads())
+                .into_par_iter()
+                .try_for_each_with(sender.clone(), |sender, _| {
+                    while let Some(positions) = queue.pop() {
+                        match render_chunk(&positions) {
+                            Ok(rendered) => sender
+                                .send(WorkerMessage::Chunk(rendered, epoch))
+                                .map_err(|_| RenderError::Interrupted)?,
+                            Err(msg) => {
+                                sender.send(WorkerMessage::Error(msg)).ok();
+                                return Err(RenderError::PluginFailed);
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+        };
+
+        let res = render_local()
+            .and_then(|_| {
+                // the render servers may have handed chunks back
+                for thread in remote_threads {
+                    thread.join().ok();
+                }
+                render_local()
             })
-            .map(|_| sender.send(WorkerMessage::Finished));
+            .and_then(|_| {
+                sender
+                    .send(WorkerMessage::Finished)
+                    .map_err(|_| RenderError::Interrupted)
+            });
+        queue.close();
         match res {
             Ok(_) => info!("render complete"),
-            Err(_) => info!("render interrupted"),
+            Err(RenderError::Interrupted) => info!("render interrupted"),
+            Err(RenderError::PluginFailed) => info!("render failed"),
         }
     });
+}
 
-    receiver
+/// starts a thread per render server, which opens as many connections as the server has threads.
+/// a server that fails hands its chunk back and drops out of the render.
+fn start_remote_renders(
+    remote_render: RemoteRender,
+    queue: &Arc<ChunkQueue>,
+    color_func: &SharedColorFunc,
+    epoch: u32,
+    sender: &Sender<WorkerMessage>,
+) -> Vec<JoinHandle<()>> {
+    let remote_render = Arc::new(remote_render);
+    (0..remote_render.addrs.len())
+        .map(|index| {
+            let remote_render = remote_render.clone();
+            let queue = queue.clone();
+            let color_func = color_func.clone();
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                let addr = &remote_render.addrs[index];
+                let mut connection = match RemoteConnection::connect(addr) {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        remote_render.set_error(index, format!("{:#}", e));
+                        return;
+                    }
+                };
+                let threads = match connection.threads() {
+                    Ok(threads) => threads.max(1),
+                    Err(msg) => {
+                        remote_render.set_error(index, msg);
+                        return;
+                    }
+                };
+                info!("rendering on {} with {} connections", addr, threads);
+                let others = (1..threads)
+                    .filter_map(|_| {
+                        let connection = RemoteConnection::connect(addr).ok()?;
+                        let remote_render = remote_render.clone();
+                        let queue = queue.clone();
+                        let color_func = color_func.clone();
+                        let sender = sender.clone();
+                        Some(std::thread::spawn(move || {
+                            remote_render.render(
+                                index,
+                                connection,
+                                &queue,
+                                &color_func,
+                                epoch,
+                                &sender,
+                            )
+                        }))
+                    })
+                    .collect_vec();
+                remote_render.render(index, connection, &queue, &color_func, epoch, &sender);
+                for thread in others {
+                    thread.join().ok();
+                }
+            })
+        })
+        .collect()
+}
+
+impl RemoteRender {
+    fn set_error(&self, index: usize, error: String) {
+        warn!("render server {}: {}", self.addrs[index], error);
+        self.stats.lock().unwrap()[index].error = Some(error);
+    }
+
+    fn render(
+        &self,
+        index: usize,
+        mut connection: RemoteConnection,
+        queue: &ChunkQueue,
+        color_func: &SharedColorFunc,
+        epoch: u32,
+        sender: &Sender<WorkerMessage>,
+    ) {
+        while let Some(positions) = queue.pop() {
+            let (color_generation, color_func) = color_func.get();
+            let color = color_func.remote_state(&self.color_lib);
+            let spacing = chunk_spacing(positions.iter().copied(), self.block_size);
+            match connection.render_chunk(&self.fractal, &color, &positions, spacing) {
+                Ok((chunk, colors, values)) => {
+                    self.stats.lock().unwrap()[index].chunks += 1;
+                    let rendered = RenderedChunk {
+                        chunk,
+                        colors,
+                        values,
+                        color_generation,
+                        elapsed: None,
+                    };
+                    if sender.send(WorkerMessage::Chunk(rendered, epoch)).is_err() {
+                        return;
+                    }
+                }
+                Err(msg) => {
+                    queue.push(positions);
+                    self.set_error(index, msg);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// computes the colors of `chunk`, going through `compute_colors_tile` with the cells from
+/// `neighbors` if the color func wants to see the neighborhood
+pub(crate) fn compute_chunk_colors(
+    color_func: &ColorFunc,
+    chunk: &RChunk,
+    spacing: u32,
+    neighbors: impl FnOnce(u32) -> Result<RChunk, String>,
+) -> Result<RVec<RColor>, String> {
+    let radius = color_func.neighborhood_radius();
+    let colors = if radius == 0 {
+        color_func.compute_colors(chunk)
+    } else {
+        let mut tile_chunk = chunk.clone();
+        tile_chunk.extend(&neighbors(radius)?);
+        color_func.compute_colors_tile(&RTile {
+            chunk: tile_chunk,
+            center_count: chunk.len(),
+            radius,
+            spacing,
+        })
+    };
+    let colors = colors.map_err(|e| format!("color func: {}", e))?;
+    check_color_positions(&colors, chunk)?;
+    Ok(colors)
+}
+
+/// fails if a color is for a position that isn't a cell of `chunk`, eg. one outside the screen
+pub(crate) fn check_color_positions(colors: &[RColor], chunk: &RChunk) -> Result<(), String> {
+    let positions: HashSet<[u32; 2]> = chunk.positions().collect();
+    match colors.iter().find(|color| !positions.contains(&color.pos)) {
+        Some(color) => Err(format!(
+            "color func: color for {:?}, which isn't in the chunk",
+            color.pos
+        )),
+        None => Ok(()),
+    }
+}
+
+/// fails if `chunk` has a cell that isn't one of the requested `positions`
+pub(crate) fn check_chunk_positions(chunk: &RChunk, positions: &[[u32; 2]]) -> Result<(), String> {
+    let positions: HashSet<[u32; 2]> = positions.iter().copied().collect();
+    match chunk.positions().find(|pos| !positions.contains(pos)) {
+        Some(pos) => Err(format!(
+            "fractal func: cell at {:?}, which wasn't requested",
+            pos
+        )),
+        None => Ok(()),
+    }
+}
+
+/// where a recolor job gets the neighbors of a chunk from: the retained chunks where possible,
+/// the fractal func for whatever is missing
+struct NeighborSource {
+    chunks: Vec<Arc<RChunk>>,
+    fractal_func: FractalFunc,
+    width: u32,
+    height: u32,
+    block_size: u32,
+}
+
+impl NeighborSource {
+    fn apron(
+        &self,
+        index: &CellIndex,
+        chunk: &RChunk,
+        radius: u32,
+        spacing: u32,
+    ) -> Result<RChunk, String> {
+        let positions = chunk.positions().collect_vec();
+        let mut apron = RChunk::default();
+        let mut missing = vec![];
+        for pos in apron_positions(&positions, radius, spacing, self.width, self.height) {
+            match index.get(&self.chunks, pos) {
+                Some(data) => apron.push(pos, data),
+                None => missing.push(pos),
+            }
+        }
+        if !missing.is_empty() {
+            let computed = self
+                .fractal_func
+                .compute_cells(&missing)
+                .map_err(|e| format!("fractal func: {}", e))?;
+            apron.extend(&computed);
+        }
+        Ok(apron)
+    }
+}
+
+/// recomputes the colors of already-rendered chunks, without touching the fractal func
+#[allow(clippy::too_many_arguments)]
+fn recolor_chunks(
+    chunks: Vec<(usize, Arc<RChunk>)>,
+    color_func: ColorFunc,
+    color_generation: u32,
+    compute_values: bool,
+    neighbors: NeighborSource,
+    pool: &RenderPool,
+    gate: Arc<RenderGate>,
+    sender: Sender<WorkerMessage>,
+) {
+    pool.spawn(move || {
+        let index = if color_func.neighborhood_radius() > 0 {
+            Some(CellIndex::new(
+                &neighbors.chunks,
+                neighbors.width,
+                neighbors.height,
+            ))
+        } else {
+            None
+        };
+        let res = chunks
+            .into_par_iter()
+            .map(|(index_in_chunks, rchunk)| {
+                if !gate.pass() {
+                    return None;
+                }
+                let recolored = recolor_chunk(
+                    &color_func,
+                    &rchunk,
+                    &neighbors,
+                    index.as_ref(),
+                    compute_values,
+                );
+                Some(recolored.map(|(colors, values)| WorkerMessage::Recolored {
+                    index: index_in_chunks,
+                    colors,
+                    values,
+                    color_generation,
+                }))
+            })
+            .try_for_each_with(sender, |sender, res| match res {
+                Some(Ok(message)) => sender.send(message).map_err(|_| RenderError::Interrupted),
+                Some(Err(msg)) => {
+                    sender.send(WorkerMessage::Error(msg)).ok();
+                    Err(RenderError::PluginFailed)
+                }
+                None => Err(RenderError::Interrupted),
+            });
+        match res {
+            Ok(_) => info!("recolor complete"),
+            Err(RenderError::Interrupted) => info!("recolor interrupted"),
+            Err(RenderError::PluginFailed) => info!("recolor failed"),
+        }
+    });
+}
+
+/// the colors of a retained chunk, and its values if `compute_values`
+fn recolor_chunk(
+    color_func: &ColorFunc,
+    rchunk: &RChunk,
+    neighbors: &NeighborSource,
+    index: Option<&CellIndex>,
+    compute_values: bool,
+) -> Result<(RVec<RColor>, Option<RVec<f64>>), String> {
+    let spacing = chunk_spacing(rchunk.positions(), neighbors.block_size);
+    let colors = compute_chunk_colors(color_func, rchunk, spacing, |radius| match index {
+        Some(index) => neighbors.apron(index, rchunk, radius, spacing),
+        None => Err("color func changed its neighborhood radius".to_owned()),
+    })?;
+    let values = if compute_values {
+        Some(
+            color_func
+                .cell_values(rchunk)
+                .map_err(|e| format!("color func: {}", e))?,
+        )
+    } else {
+        None
+    };
+    Ok((colors, values))
+}
+
+/// the pixels in batches of this many go to the threads of the pool
+const REFINE_BATCH_SIZE: usize = 256;
+
+/// looks for the high contrast pixels of `buffer` and refines them in batches, until done or
+/// cancelled
+#[allow(clippy::too_many_arguments)]
+fn refine_pixels(
+    buffer: ColorBuffer,
+    fractal_func: FractalFunc,
+    color_func: ColorFunc,
+    color_generation: u32,
+    tone_mapping: ToneMapping,
+    cancelled: Arc<AtomicBool>,
+    pool: &RenderPool,
+    gate: Arc<RenderGate>,
+    sender: Sender<WorkerMessage>,
+) {
+    pool.spawn(move || {
+        if !gate.pass() {
+            return;
+        }
+        let pixels = buffer
+            .high_contrast_pixels(&tone_mapping, CONTRAST_THRESHOLD)
+            .into_iter()
+            .filter_map(|pos| Some((pos, buffer.get_pixel(pos)?)))
+            .collect_vec();
+        drop(buffer);
+        info!("refining {} pixels", pixels.len());
+        let found = WorkerMessage::Refining {
+            pixels: pixels.iter().map(|(pos, _)| *pos).collect(),
+            color_generation,
+        };
+        if sender.send(found).is_err() || pixels.is_empty() {
+            return;
+        }
+        let sampler = SubpixelSampler::new(&fractal_func);
+        let res = pixels
+            .par_chunks(REFINE_BATCH_SIZE)
+            .map(|batch| {
+                if !gate.pass() || cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let positions = batch.iter().map(|(pos, _)| *pos).collect_vec();
+                let colors = batch.iter().map(|(_, rgba)| *rgba).collect_vec();
+                let refined = sampler.refine(&color_func, &tone_mapping, &positions, &colors);
+                Some(refined.map(|colors| WorkerMessage::Refined {
+                    pixels: positions.into_iter().zip(colors).collect(),
+                    color_generation,
+                }))
+            })
+            .try_for_each_with(sender, |sender, res| match res {
+                Some(Ok(message)) => sender.send(message).map_err(|_| RenderError::Interrupted),
+                Some(Err(msg)) => {
+                    sender.send(WorkerMessage::Error(msg)).ok();
+                    Err(RenderError::PluginFailed)
+                }
+                None => Err(RenderError::Interrupted),
+            });
+        match res {
+            Ok(_) => info!("refinement complete"),
+            Err(RenderError::Interrupted) => info!("refinement interrupted"),
+            Err(RenderError::PluginFailed) => info!("refinement failed"),
+        }
+    });
 }
@@ -1,3 +1,21 @@
-pub mod util;
+pub mod boundary_fill;
+pub mod builtin_plugins;
+pub mod chunk_order;
+pub mod chunk_sizing;
+pub mod color_buffer;
+pub mod color_stats;
 pub mod config_manager;
 pub mod fractal_worker2;
+pub mod plugin_dir;
+pub mod plugin_host;
+pub mod plugin_log;
+pub mod plugin_process;
+pub mod rebuild;
+pub mod refinement;
+pub mod render_pool;
+pub mod render_server;
+pub mod tiles;
+pub mod util;
+pub mod wasm_plugin;
+pub mod watchdog;
+pub mod wire;
@@ -0,0 +1,195 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::SystemTime,
+};
+
+use abi_stable::library::{lib_header_from_raw_library, RawLibrary, RootModule};
+use color_func::prelude::ColorLib_Ref;
+use fractal_func::prelude::FractalLib_Ref;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::wasm_plugin::{inspect_wasm_plugin, is_wasm_plugin, WASM_ABI_VERSION};
+
+/// where plugins are looked for if nothing else is configured
+pub const DEFAULT_PLUGIN_DIR: &str = "target/release";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PluginKind {
+    Fractal,
+    Color,
+}
+
+impl fmt::Display for PluginKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginKind::Fractal => write!(f, "fractal"),
+            PluginKind::Color => write!(f, "color"),
+        }
+    }
+}
+
+/// a library found in the plugin directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginInfo {
+    pub path: PathBuf,
+    /// the file name without the platform's prefix and extension
+    pub name: String,
+    /// none for a native library that wasn't loaded yet, see `PluginInfo::inspected`
+    pub kind: Option<PluginKind>,
+    /// the version of the interface crate the plugin was built against, like `kind`
+    pub interface_version: Option<String>,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl PluginInfo {
+    /// with the kind and interface version filled in, which loads a native library. none if it
+    /// doesn't export a fractal or color root module.
+    pub fn inspected(&self) -> anyhow::Result<Option<PluginInfo>> {
+        match self.kind {
+            Some(_) => Ok(Some(self.clone())),
+            None => inspect_library(&self.path),
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/rust-mandelbrot/plugins` if it exists, `target/release` otherwise
+pub fn default_plugin_dir() -> PathBuf {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    match data_home.map(|dir| dir.join("rust-mandelbrot/plugins")) {
+        Some(dir) if dir.is_dir() => dir,
+        _ => PathBuf::from(DEFAULT_PLUGIN_DIR),
+    }
+}
+
+/// lists the native libraries in `dir` and the wasm plugins, sorted by kind and name. the native
+/// libraries aren't loaded, so their kind is only known once they are inspected.
+pub fn scan_plugin_dir(dir: &Path) -> anyhow::Result<Vec<PluginInfo>> {
+    let mut plugins = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let info = if is_wasm_plugin(&path) {
+            inspect_wasm(&path)
+        } else if path.extension() == Some(std::env::consts::DLL_EXTENSION.as_ref()) {
+            plugin_info(&path, None, None)
+        } else {
+            continue;
+        };
+        match info {
+            Ok(Some(info)) => plugins.push(info),
+            Ok(None) => {}
+            Err(e) => debug!("skipping {}: {}", path.display(), e),
+        }
+    }
+    plugins.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    Ok(plugins)
+}
+
+/// `scan_plugin_dir` on another thread
+pub fn scan_plugin_dir_in_background(dir: PathBuf) -> Receiver<anyhow::Result<Vec<PluginInfo>>> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        sender.send(scan_plugin_dir(&dir)).ok();
+    });
+    receiver
+}
+
+/// the first plugin of `kind` in `dir`. native libraries are inspected in order until one is
+/// found, so the ones after it aren't loaded.
+pub fn find_plugin(dir: &Path, kind: PluginKind) -> anyhow::Result<Option<PluginInfo>> {
+    for plugin in scan_plugin_dir(dir)? {
+        match plugin.inspected() {
+            Ok(Some(plugin)) if plugin.kind == Some(kind) => return Ok(Some(plugin)),
+            Ok(_) => {}
+            Err(e) => debug!("skipping {}: {}", plugin.path.display(), e),
+        }
+    }
+    Ok(None)
+}
+
+fn inspect_library(path: &Path) -> anyhow::Result<Option<PluginInfo>> {
+    let raw_library = RawLibrary::load_at(path)?;
+    // safety: only the header is read, the root module isn't initialized
+    let header = unsafe { lib_header_from_raw_library(&raw_library)? };
+    let consts = header.root_mod_consts();
+    let base_name = consts.base_name().as_str();
+    let kind = if base_name == FractalLib_Ref::BASE_NAME {
+        PluginKind::Fractal
+    } else if base_name == ColorLib_Ref::BASE_NAME {
+        PluginKind::Color
+    } else {
+        return Ok(None);
+    };
+    let interface_version = consts.version_strings().version.as_str().to_owned();
+    // everything from the header is copied before the library is unloaded again
+    drop(raw_library);
+
+    plugin_info(path, Some(kind), Some(interface_version))
+}
+
+fn inspect_wasm(path: &Path) -> anyhow::Result<Option<PluginInfo>> {
+    match inspect_wasm_plugin(path)? {
+        Some(kind) => plugin_info(path, Some(kind), Some(WASM_ABI_VERSION.to_owned())),
+        None => Ok(None),
+    }
+}
+
+fn plugin_info(
+    path: &Path,
+    kind: Option<PluginKind>,
+    interface_version: Option<String>,
+) -> anyhow::Result<Option<PluginInfo>> {
+    let metadata = std::fs::metadata(path)?;
+    let file_stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = file_stem
+        .strip_prefix(std::env::consts::DLL_PREFIX)
+        .unwrap_or(&file_stem)
+        .to_owned();
+    Ok(Some(PluginInfo {
+        path: path.to_owned(),
+        name,
+        kind,
+        interface_version,
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_does_not_load_libraries() {
+        let dir = std::env::temp_dir().join(format!("plugin-dir-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = dir.join(format!(
+            "{}broken.{}",
+            std::env::consts::DLL_PREFIX,
+            std::env::consts::DLL_EXTENSION
+        ));
+        // loading this would fail
+        std::fs::write(&library, "not a library").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let plugins = scan_plugin_dir_in_background(dir.clone())
+            .recv()
+            .unwrap()
+            .unwrap();
+        let inspected = plugins.first().map(PluginInfo::inspected);
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(plugins.len(), 1);
+        assert_eq!(plugins[0].name, "broken");
+        assert_eq!(plugins[0].kind, None);
+        assert!(inspected.unwrap().is_err());
+    }
+}
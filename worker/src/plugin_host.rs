@@ -0,0 +1,557 @@
+//! loads plugin libraries so that they can be reloaded and unloaded again.
+//!
+//! every function object from a library holds a reference to it, and the library is unloaded
+//! once the last one is dropped. for that to be sound, nothing allocated by a plugin may outlive
+//! its library (abi_stable containers carry a vtable pointing into the library that created
+//! them), so the wrappers here copy everything a plugin returns into memory owned by the host.
+//!
+//! built-in plugins (see `builtin_plugins`) go through the same wrappers, without a library,
+//! and so do plugins running in the plugin process (see `plugin_process`) and WebAssembly
+//! plugins (see `wasm_plugin`).
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use abi_stable::{
+    abi_stability::abi_checking::check_layout_compatibility,
+    library::{lib_header_from_raw_library, RawLibrary, RootModule},
+    std_types::{RSlice, RStr, RVec, Tuple2},
+};
+use anyhow::Context;
+use log::{error, info};
+
+use color_func::{
+    prelude::{ColorLib_Ref, RColorFuncBox},
+    RColor, RColorStats, RTile,
+};
+use fractal_func::prelude::*;
+
+use crate::builtin_plugins::{builtin_name, BuiltinPlugins};
+use crate::plugin_process::{
+    ColorOp, FractalOp, PluginProcess, RemoteColorState, RemoteFractalState,
+};
+use crate::wasm_plugin::{is_wasm_plugin, WasmColorFunc, WasmFractalFunc, WasmModule};
+use crate::wire::{wire_options, WireColorStats};
+
+static LOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// a loaded copy of a plugin library, unloaded when dropped
+struct LoadedLibrary {
+    path: PathBuf,
+    raw_library: RawLibrary,
+}
+
+impl Drop for LoadedLibrary {
+    fn drop(&mut self) {
+        info!("unloading {}", self.path.display());
+    }
+}
+
+impl fmt::Debug for LoadedLibrary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedLibrary")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// the root module of a loaded library, keeping the library loaded
+#[derive(Debug, Clone)]
+pub struct Plugin<M> {
+    source: PluginSource<M>,
+}
+
+#[derive(Debug, Clone)]
+enum PluginSource<M> {
+    Local {
+        module: M,
+        /// `None` for built-in plugins
+        library: Option<Arc<LoadedLibrary>>,
+    },
+    /// loaded in the plugin process
+    Remote {
+        path: PathBuf,
+        process: PluginProcess,
+    },
+    Wasm(Arc<WasmModule>),
+}
+
+impl<M: BuiltinPlugins> Plugin<M> {
+    /// similar to `RootModule::load_from_file()`, except that it loads a fresh copy of the
+    /// library every time, so that a rebuilt library actually gets reloaded.
+    /// paths like `builtin:name` refer to the built-in plugins instead, and `.wasm` files to
+    /// WebAssembly plugins.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if let Some(name) = builtin_name(path) {
+            let module = M::builtin(name)
+                .ok_or_else(|| anyhow::anyhow!("there is no built-in plugin named {:?}", name))?;
+            info!("using built-in plugin {}", name);
+            return Ok(Self::local(module, None));
+        }
+        if is_wasm_plugin(path) {
+            let module = WasmModule::load(path, M::KIND)?;
+            info!("loaded wasm plugin {}", path.display());
+            return Ok(Self {
+                source: PluginSource::Wasm(Arc::new(module)),
+            });
+        }
+
+        // copy the library to a unique path, to make sure it gets reloaded even if it was already loaded
+        let unique_path = {
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| anyhow::anyhow!("invalid library path: {}", path.display()))?;
+            path.with_file_name(format!(
+                "{}-{}-{}",
+                file_name.to_string_lossy(),
+                std::process::id(),
+                LOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ))
+        };
+        std::fs::copy(path, &unique_path)
+            .with_context(|| format!("failed to copy {} to unique path", path.display()))?;
+        let raw_library = RawLibrary::load_at(&unique_path);
+        // the copy isn't needed anymore once it's mapped
+        std::fs::remove_file(&unique_path)?;
+        let library = Arc::new(LoadedLibrary {
+            path: path.to_owned(),
+            raw_library: raw_library?,
+        });
+
+        register_host_layout::<M>();
+        let module = unsafe {
+            let header = lib_header_from_raw_library(&library.raw_library)?;
+            header.ensure_layout::<M>()?;
+            // safety: the layout was checked above
+            header
+                .init_root_module_with_unchecked_layout::<M>()?
+                .initialization()
+        }
+        .context("loading library")?;
+
+        info!("loaded {}", path.display());
+        Ok(Self::local(module, Some(library)))
+    }
+
+    /// loads the library in the plugin process instead of this one
+    pub fn load_remote(path: &Path, process: &PluginProcess) -> anyhow::Result<Self> {
+        process
+            .load(M::KIND, path)
+            .map_err(|msg| anyhow::anyhow!(msg))?;
+        info!("loaded {} in the plugin process", path.display());
+        Ok(Self {
+            source: PluginSource::Remote {
+                path: path.to_owned(),
+                process: process.clone(),
+            },
+        })
+    }
+}
+
+impl<M> Plugin<M> {
+    fn local(module: M, library: Option<Arc<LoadedLibrary>>) -> Self {
+        Self {
+            source: PluginSource::Local { module, library },
+        }
+    }
+
+    /// the root module, unless the library was loaded in the plugin process or isn't native
+    pub fn module(&self) -> Option<&M> {
+        match &self.source {
+            PluginSource::Local { module, .. } => Some(module),
+            PluginSource::Remote { .. } | PluginSource::Wasm(_) => None,
+        }
+    }
+}
+
+/// the layout checker remembers the layouts of prefix types (root modules, vtables) it has
+/// seen, and would otherwise keep pointing at the layouts of the first library that was loaded.
+/// checking the host's own layout first makes that the one it remembers, so it stays valid
+/// after libraries are unloaded.
+fn register_host_layout<M: RootModule>() {
+    if let Err(e) = check_layout_compatibility(M::LAYOUT, M::LAYOUT) {
+        log::error!("checking the host layout failed: {}", e);
+    }
+}
+
+fn host_chunk(chunk: RChunk) -> RChunk {
+    RChunk {
+        pos_indexes: RVec::from(chunk.pos_indexes.into_vec()),
+        data: RVec::from(chunk.data.into_vec()),
+    }
+}
+
+fn host_vec<T>(vec: RVec<T>) -> RVec<T> {
+    RVec::from(vec.into_vec())
+}
+
+fn host_options(options: ROptionsMap) -> ROptionsMap {
+    options
+        .iter()
+        .map(|Tuple2(name, value)| (RString::from(name.as_str()), RString::from(value.as_str())))
+        .collect()
+}
+
+fn host_result<T>(res: RResult<T, RString>) -> Result<T, String> {
+    match res {
+        ROk(value) => Ok(value),
+        RErr(msg) => Err(msg.as_str().to_owned()),
+    }
+}
+
+impl Plugin<FractalLib_Ref> {
+    pub fn default_fractal_func_for_size(&self, width: u32, height: u32) -> FractalFunc {
+        match &self.source {
+            PluginSource::Local { module, library } => {
+                let func = module.default_fractal_func_for_size()(width, height);
+                FractalFunc::new(func, library)
+            }
+            PluginSource::Remote { path, process } => {
+                let state = process
+                    .default_fractal_func(path, width, height)
+                    .unwrap_or_else(|msg| {
+                        error!("failed to create the default fractal func: {}", msg);
+                        RemoteFractalState {
+                            lib: path.clone(),
+                            width,
+                            height,
+                            options: vec![],
+                            fill_safe_fields: vec![],
+                            can_resample: false,
+                        }
+                    });
+                FractalFunc(FractalFuncImpl::Remote {
+                    state,
+                    process: process.clone(),
+                })
+            }
+            PluginSource::Wasm(module) => {
+                let func = WasmFractalFunc::new_default(module.clone(), width, height);
+                FractalFunc::new(func, &None)
+            }
+        }
+    }
+}
+
+impl Plugin<ColorLib_Ref> {
+    pub fn default_color_func(&self) -> ColorFunc {
+        match &self.source {
+            PluginSource::Local { module, library } => {
+                let func = module.default_color_func()();
+                ColorFunc::new(func, library)
+            }
+            PluginSource::Remote { path, process } => {
+                let state = process.default_color_func(path).unwrap_or_else(|msg| {
+                    error!("failed to create the default color func: {}", msg);
+                    RemoteColorState {
+                        lib: path.clone(),
+                        options: vec![],
+                        stats: None,
+                        wants_statistics: false,
+                        neighborhood_radius: 0,
+                    }
+                });
+                ColorFunc(ColorFuncImpl::Remote {
+                    state,
+                    process: process.clone(),
+                })
+            }
+            PluginSource::Wasm(module) => {
+                ColorFunc::new(WasmColorFunc::new_default(module.clone()), &None)
+            }
+        }
+    }
+}
+
+/// a fractal func that keeps its library loaded
+#[derive(Debug, Clone)]
+pub struct FractalFunc(FractalFuncImpl);
+
+#[derive(Debug, Clone)]
+enum FractalFuncImpl {
+    Local {
+        // declared before the library, so that it is dropped first
+        func: RFractalFuncBox,
+        library: Option<Arc<LoadedLibrary>>,
+    },
+    Remote {
+        state: RemoteFractalState,
+        process: PluginProcess,
+    },
+}
+
+impl FractalFunc {
+    fn new(func: RFractalFuncBox, library: &Option<Arc<LoadedLibrary>>) -> Self {
+        Self(FractalFuncImpl::Local {
+            func,
+            library: library.clone(),
+        })
+    }
+
+    /// a fractal func that no library has to stay loaded for, eg. one made up by a test
+    #[cfg(test)]
+    pub(crate) fn from_box(func: RFractalFuncBox) -> Self {
+        Self::new(func, &None)
+    }
+
+    /// applies `op` in the plugin process. the function objects there can't fail these, so an
+    /// error means the process is gone, and the view stays as it is.
+    fn transform(
+        &self,
+        op: FractalOp,
+        local: impl FnOnce(&RFractalFuncBox) -> RFractalFuncBox,
+    ) -> Self {
+        match &self.0 {
+            FractalFuncImpl::Local { func, library } => Self::new(local(func), library),
+            FractalFuncImpl::Remote { state, process } => {
+                match process.transform_fractal_func(state, op) {
+                    Ok(state) => Self(FractalFuncImpl::Remote {
+                        state,
+                        process: process.clone(),
+                    }),
+                    Err(msg) => {
+                        error!("fractal func: {}", msg);
+                        self.clone()
+                    }
+                }
+            }
+        }
+    }
+
+    /// how to recreate this fractal func in another process
+    pub fn remote_state(&self, lib: &Path) -> RemoteFractalState {
+        match &self.0 {
+            FractalFuncImpl::Local { .. } => {
+                let (width, height) = self.get_size();
+                RemoteFractalState {
+                    lib: lib.to_owned(),
+                    width,
+                    height,
+                    options: wire_options(&self.get_options()),
+                    fill_safe_fields: self.fill_safe_fields(),
+                    can_resample: self.can_resample(),
+                }
+            }
+            FractalFuncImpl::Remote { state, .. } => state.clone(),
+        }
+    }
+
+    pub fn get_size(&self) -> (u32, u32) {
+        match &self.0 {
+            FractalFuncImpl::Local { func, .. } => func.get_size().into_tuple(),
+            FractalFuncImpl::Remote { state, .. } => (state.width, state.height),
+        }
+    }
+
+    pub fn compute_cells(&self, positions: &[[u32; 2]]) -> Result<RChunk, String> {
+        match &self.0 {
+            FractalFuncImpl::Local { func, .. } => {
+                host_result(func.compute_cells(RSlice::from(positions))).map(host_chunk)
+            }
+            FractalFuncImpl::Remote { state, process } => process.compute_cells(state, positions),
+        }
+    }
+
+    pub fn with_size(&self, width: u32, height: u32) -> Self {
+        self.transform(FractalOp::WithSize(width, height), |func| {
+            func.with_size(width, height)
+        })
+    }
+
+    pub fn with_offset(&self, dx: i32, dy: i32) -> Self {
+        self.transform(FractalOp::WithOffset(dx, dy), |func| {
+            func.with_offset(dx, dy)
+        })
+    }
+
+    pub fn add_zoom(&self, zoom_factor: f64) -> Self {
+        self.transform(FractalOp::AddZoom(zoom_factor), |func| {
+            func.add_zoom(zoom_factor)
+        })
+    }
+
+    pub fn with_option(&self, name: &str, value: &str) -> Result<Self, String> {
+        match &self.0 {
+            FractalFuncImpl::Local { func, library } => {
+                host_result(func.with_option(RStr::from(name), RStr::from(value)))
+                    .map(|func| Self::new(func, library))
+            }
+            FractalFuncImpl::Remote { state, process } => process
+                .transform_fractal_func(state, FractalOp::WithOption(name.into(), value.into()))
+                .map(|state| {
+                    Self(FractalFuncImpl::Remote {
+                        state,
+                        process: process.clone(),
+                    })
+                }),
+        }
+    }
+
+    pub fn get_options(&self) -> ROptionsMap {
+        match &self.0 {
+            FractalFuncImpl::Local { func, .. } => host_options(func.get_options()),
+            FractalFuncImpl::Remote { state, .. } => state.options_map(),
+        }
+    }
+
+    /// see `RFractalFunc::fill_safe_fields`
+    pub fn fill_safe_fields(&self) -> Vec<String> {
+        match &self.0 {
+            FractalFuncImpl::Local { func, .. } => func
+                .fill_safe_fields()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            FractalFuncImpl::Remote { state, .. } => state.fill_safe_fields.clone(),
+        }
+    }
+
+    /// see `RFractalFunc::can_resample`
+    pub fn can_resample(&self) -> bool {
+        match &self.0 {
+            FractalFuncImpl::Local { func, .. } => func.can_resample(),
+            FractalFuncImpl::Remote { state, .. } => state.can_resample,
+        }
+    }
+}
+
+/// a color func that keeps its library loaded
+#[derive(Debug, Clone)]
+pub struct ColorFunc(ColorFuncImpl);
+
+#[derive(Debug, Clone)]
+enum ColorFuncImpl {
+    Local {
+        // declared before the library, so that it is dropped first
+        func: RColorFuncBox,
+        library: Option<Arc<LoadedLibrary>>,
+        /// the statistics that were applied, needed to recreate it in another process
+        stats: Option<WireColorStats>,
+    },
+    Remote {
+        state: RemoteColorState,
+        process: PluginProcess,
+    },
+}
+
+impl ColorFunc {
+    fn new(func: RColorFuncBox, library: &Option<Arc<LoadedLibrary>>) -> Self {
+        Self(ColorFuncImpl::Local {
+            func,
+            library: library.clone(),
+            stats: None,
+        })
+    }
+
+    fn transform(
+        &self,
+        op: ColorOp,
+        local: impl FnOnce(&RColorFuncBox) -> RResult<RColorFuncBox, RString>,
+    ) -> Result<Self, String> {
+        match &self.0 {
+            ColorFuncImpl::Local {
+                func,
+                library,
+                stats,
+            } => {
+                let stats = match &op {
+                    ColorOp::WithStatistics(new_stats) => Some(new_stats.clone()),
+                    ColorOp::WithOption(..) => stats.clone(),
+                };
+                host_result(local(func)).map(|func| {
+                    Self(ColorFuncImpl::Local {
+                        func,
+                        library: library.clone(),
+                        stats,
+                    })
+                })
+            }
+            ColorFuncImpl::Remote { state, process } => {
+                process.transform_color_func(state, op).map(|state| {
+                    Self(ColorFuncImpl::Remote {
+                        state,
+                        process: process.clone(),
+                    })
+                })
+            }
+        }
+    }
+
+    /// how to recreate this color func in another process
+    pub fn remote_state(&self, lib: &Path) -> RemoteColorState {
+        match &self.0 {
+            ColorFuncImpl::Local { stats, .. } => RemoteColorState {
+                lib: lib.to_owned(),
+                options: wire_options(&self.get_options()),
+                stats: stats.clone(),
+                wants_statistics: self.wants_statistics(),
+                neighborhood_radius: self.neighborhood_radius(),
+            },
+            ColorFuncImpl::Remote { state, .. } => state.clone(),
+        }
+    }
+
+    pub fn compute_colors(&self, chunk: &RChunk) -> Result<RVec<RColor>, String> {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => {
+                host_result(func.compute_colors(chunk)).map(host_vec)
+            }
+            ColorFuncImpl::Remote { state, process } => process.compute_colors(state, chunk),
+        }
+    }
+
+    pub fn with_option(&self, name: &str, value: &str) -> Result<Self, String> {
+        self.transform(ColorOp::WithOption(name.into(), value.into()), |func| {
+            func.with_option(RStr::from(name), RStr::from(value))
+        })
+    }
+
+    pub fn get_options(&self) -> ROptionsMap {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => host_options(func.get_options()),
+            ColorFuncImpl::Remote { state, .. } => state.options_map(),
+        }
+    }
+
+    pub fn wants_statistics(&self) -> bool {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => func.wants_statistics(),
+            ColorFuncImpl::Remote { state, .. } => state.wants_statistics,
+        }
+    }
+
+    pub fn cell_values(&self, chunk: &RChunk) -> Result<RVec<f64>, String> {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => host_result(func.cell_values(chunk)).map(host_vec),
+            ColorFuncImpl::Remote { state, process } => process.cell_values(state, chunk),
+        }
+    }
+
+    pub fn with_statistics(&self, stats: &RColorStats) -> Result<Self, String> {
+        self.transform(ColorOp::WithStatistics(stats.into()), |func| {
+            func.with_statistics(stats)
+        })
+    }
+
+    pub fn neighborhood_radius(&self) -> u32 {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => func.neighborhood_radius(),
+            ColorFuncImpl::Remote { state, .. } => state.neighborhood_radius,
+        }
+    }
+
+    pub fn compute_colors_tile(&self, tile: &RTile) -> Result<RVec<RColor>, String> {
+        match &self.0 {
+            ColorFuncImpl::Local { func, .. } => {
+                host_result(func.compute_colors_tile(tile)).map(host_vec)
+            }
+            ColorFuncImpl::Remote { state, process } => process.compute_colors_tile(state, tile),
+        }
+    }
+}
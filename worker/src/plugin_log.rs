@@ -0,0 +1,92 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Instant};
+
+use abi_stable::std_types::RStr;
+use color_func::prelude::ColorLib_Ref;
+use fractal_func::prelude::{FractalLib_Ref, RHostServices};
+use log::{Level, Metadata, Record};
+
+use crate::plugin_host::Plugin;
+
+static METRICS: Mutex<BTreeMap<String, (f64, Instant)>> = Mutex::new(BTreeMap::new());
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        0 | 1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+extern "C" fn log(level: u8, target: RStr<'_>, message: RStr<'_>) {
+    log::logger().log(
+        &Record::builder()
+            .level(level_from_u8(level))
+            .target(target.as_str())
+            .module_path(Some(target.as_str()))
+            .args(format_args!("{}", message))
+            .build(),
+    );
+}
+
+extern "C" fn log_enabled(level: u8, target: RStr<'_>) -> bool {
+    let level = level_from_u8(level);
+    level <= log::max_level()
+        && log::logger().enabled(
+            &Metadata::builder()
+                .level(level)
+                .target(target.as_str())
+                .build(),
+        )
+}
+
+extern "C" fn metric(name: RStr<'_>, value: f64) {
+    if let Ok(mut metrics) = METRICS.lock() {
+        metrics.insert(name.to_string(), (value, Instant::now()));
+    }
+}
+
+pub fn host_services() -> RHostServices {
+    RHostServices {
+        log,
+        log_enabled,
+        metric,
+    }
+}
+
+/// a root module that may take the host services
+pub trait HostServicesLib {
+    fn host_services_setter(&self) -> Option<extern "C" fn(services: RHostServices)>;
+}
+
+impl HostServicesLib for FractalLib_Ref {
+    fn host_services_setter(&self) -> Option<extern "C" fn(services: RHostServices)> {
+        self.set_host_services()
+    }
+}
+
+impl HostServicesLib for ColorLib_Ref {
+    fn host_services_setter(&self) -> Option<extern "C" fn(services: RHostServices)> {
+        self.set_host_services()
+    }
+}
+
+/// hands the host services to a freshly loaded library (older libraries may not take them).
+/// libraries in the plugin process get them from the plugin process instead.
+pub fn init_lib<M: HostServicesLib>(lib: &Plugin<M>) {
+    if let Some(set_host_services) = lib.module().and_then(M::host_services_setter) {
+        set_host_services(host_services());
+    }
+}
+
+/// the latest value of every metric reported by plugins, with the time since it was reported
+pub fn plugin_metrics() -> Vec<(String, f64, f64)> {
+    match METRICS.lock() {
+        Ok(metrics) => metrics
+            .iter()
+            .map(|(name, (value, at))| (name.clone(), *value, at.elapsed().as_secs_f64()))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
@@ -0,0 +1,786 @@
+//! runs the plugins in a child process, so that a plugin that crashes takes down the child
+//! instead of the whole app.
+//!
+//! the child is the host executable itself, started with `CHILD_ARG`, which is expected to call
+//! `run_child()`. requests and responses go over the child's stdin and stdout, so plugins running
+//! in the child must not print to stdout (`log` goes to stderr, which is forwarded to the host's
+//! log). the function objects only exist in the child: the host describes them by their library,
+//! size and options, and the child recreates them from that the same way options are carried
+//! over to reloaded libraries.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, BufWriter},
+    path::{Component, Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+use anyhow::Context;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use color_func::{prelude::ColorLib_Ref, RColor, RColorStats, RTile};
+use fractal_func::prelude::*;
+
+use crate::builtin_plugins::builtin_name;
+use crate::fractal_worker2::{check_chunk_positions, compute_chunk_colors};
+use crate::plugin_dir::PluginKind;
+use crate::plugin_host::{ColorFunc, FractalFunc, Plugin};
+use crate::plugin_log;
+use crate::tiles::apron_positions;
+use crate::wire::{
+    colors_from_wire, options_map, read_message, wire_colors, write_message, WireChunk, WireColor,
+    WireColorStats, WireOptions, WireTile,
+};
+
+/// the argument that makes the host executable act as the plugin process
+pub const CHILD_ARG: &str = "--plugin-process";
+/// how many lines of the child's stderr are kept for the failure message
+const STDERR_TAIL_LINES: usize = 20;
+/// how many recreated function objects the child keeps around
+const FUNC_CACHE_SIZE: usize = 64;
+
+/// a fractal func living in the plugin process.
+/// no options means the library's default, which is what is used if asking the child failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteFractalState {
+    pub lib: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub options: WireOptions,
+    pub fill_safe_fields: Vec<String>,
+    pub can_resample: bool,
+}
+
+/// a color func living in the plugin process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteColorState {
+    pub lib: PathBuf,
+    pub options: WireOptions,
+    pub stats: Option<WireColorStats>,
+    pub wants_statistics: bool,
+    pub neighborhood_radius: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FractalOp {
+    WithSize(u32, u32),
+    WithOffset(i32, i32),
+    AddZoom(f64),
+    WithOption(String, String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ColorOp {
+    WithOption(String, String),
+    WithStatistics(WireColorStats),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Request {
+    /// asks how many requests the other side can work on at once
+    Hello,
+    Load(PluginKind, PathBuf),
+    DefaultFractalFunc {
+        lib: PathBuf,
+        width: u32,
+        height: u32,
+    },
+    TransformFractalFunc(RemoteFractalState, FractalOp),
+    ComputeCells(RemoteFractalState, Vec<[u32; 2]>),
+    DefaultColorFunc(PathBuf),
+    TransformColorFunc(RemoteColorState, ColorOp),
+    ComputeColors(RemoteColorState, WireChunk),
+    CellValues(RemoteColorState, WireChunk),
+    ComputeColorsTile(RemoteColorState, WireTile),
+    /// the cells of a chunk and their colors in one go, see `render_server`
+    RenderChunk {
+        fractal: RemoteFractalState,
+        color: RemoteColorState,
+        positions: Vec<[u32; 2]>,
+        /// the spacing of the pass the chunk is from, see `RTile::spacing`
+        spacing: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Response {
+    Hello {
+        threads: usize,
+    },
+    Loaded,
+    FractalFunc(RemoteFractalState),
+    ColorFunc(RemoteColorState),
+    Chunk(WireChunk),
+    Colors(Vec<WireColor>),
+    Values(Vec<f64>),
+    RenderedChunk {
+        chunk: WireChunk,
+        colors: Vec<WireColor>,
+        values: Vec<f64>,
+    },
+    Error(String),
+}
+
+pub(crate) fn unexpected(response: Response) -> String {
+    format!(
+        "unexpected response from the plugin process: {:?}",
+        response
+    )
+}
+
+/// what the gui shows about the plugin process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessStatus {
+    /// `None` if no child is running, it is started with the first request
+    pub pid: Option<u32>,
+    /// how often a child died on its own
+    pub crashes: u32,
+    /// why the last child died
+    pub last_failure: Option<String>,
+}
+
+/// a handle to the plugin process, which is started on demand and restarted after it dies
+#[derive(Debug, Clone)]
+pub struct PluginProcess(Arc<ProcessShared>);
+
+#[derive(Debug)]
+struct ProcessShared {
+    program: PathBuf,
+    connection: Mutex<Option<Arc<Connection>>>,
+    status: Arc<Mutex<ProcessStatus>>,
+}
+
+impl Drop for ProcessShared {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            connection.kill("the plugin process was shut down");
+        }
+    }
+}
+
+/// one running child
+#[derive(Debug)]
+struct Connection {
+    child: Mutex<Child>,
+    pid: u32,
+    writer: Mutex<BufWriter<ChildStdin>>,
+    pending: Mutex<HashMap<u64, Sender<Response>>>,
+    next_id: AtomicU64,
+    /// why the child is gone, once it is
+    exit: Mutex<Option<String>>,
+    /// why the host killed it on purpose, which isn't a failure
+    killed: Mutex<Option<String>>,
+}
+
+impl Connection {
+    fn kill(&self, reason: &str) {
+        *self.killed.lock().unwrap() = Some(reason.to_owned());
+        if let Err(e) = self.child.lock().unwrap().kill() {
+            warn!("failed to kill plugin process {}: {}", self.pid, e);
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.exit.lock().unwrap().is_none()
+    }
+}
+
+impl PluginProcess {
+    /// uses the current executable as the plugin process, see `CHILD_ARG`
+    pub fn new() -> anyhow::Result<Self> {
+        let program = std::env::current_exe().context("failed to find the current executable")?;
+        Ok(Self::with_program(program))
+    }
+
+    pub fn with_program(program: PathBuf) -> Self {
+        Self(Arc::new(ProcessShared {
+            program,
+            connection: Mutex::new(None),
+            status: Default::default(),
+        }))
+    }
+
+    pub fn status(&self) -> ProcessStatus {
+        let mut status = self.0.status.lock().unwrap().clone();
+        status.pid = match &*self.0.connection.lock().unwrap() {
+            Some(connection) if connection.is_alive() => Some(connection.pid),
+            _ => None,
+        };
+        status
+    }
+
+    /// kills the child, the next request starts a new one (with freshly loaded libraries)
+    pub fn restart(&self) {
+        self.kill("the plugin process was restarted");
+    }
+
+    /// like `restart`, with `reason` as the error of the requests that were still running
+    pub fn kill(&self, reason: &str) {
+        if let Some(connection) = self.0.connection.lock().unwrap().take() {
+            info!("killing plugin process {}: {}", connection.pid, reason);
+            connection.kill(reason);
+        }
+    }
+
+    fn connection(&self) -> Result<Arc<Connection>, String> {
+        let mut guard = self.0.connection.lock().unwrap();
+        if let Some(connection) = guard.as_ref() {
+            if connection.is_alive() {
+                return Ok(connection.clone());
+            }
+        }
+        let connection = spawn_child(&self.0.program, self.0.status.clone())
+            .map_err(|e| format!("failed to start the plugin process: {:#}", e))?;
+        *guard = Some(connection.clone());
+        Ok(connection)
+    }
+
+    fn request(&self, request: &Request) -> Result<Response, String> {
+        let connection = self.connection()?;
+        let (sender, receiver) = channel();
+        let id = connection.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut pending = connection.pending.lock().unwrap();
+            if let Some(exit) = connection.exit.lock().unwrap().clone() {
+                return Err(exit);
+            }
+            pending.insert(id, sender);
+        }
+        let res = write_message(&mut *connection.writer.lock().unwrap(), &(id, request));
+        if let Err(e) = res {
+            // the reader notices that the child is gone and fails the request
+            warn!("failed to send a request to the plugin process: {}", e);
+        }
+        match receiver.recv() {
+            Ok(Response::Error(msg)) => Err(msg),
+            Ok(response) => Ok(response),
+            Err(_) => Err(connection
+                .exit
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap_or_else(|| "the plugin process went away".to_owned())),
+        }
+    }
+
+    /// makes the child load the library, which also checks that it is one
+    pub fn load(&self, kind: PluginKind, lib: &Path) -> Result<(), String> {
+        match self.request(&Request::Load(kind, lib.to_owned()))? {
+            Response::Loaded => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn default_fractal_func(
+        &self,
+        lib: &Path,
+        width: u32,
+        height: u32,
+    ) -> Result<RemoteFractalState, String> {
+        let request = Request::DefaultFractalFunc {
+            lib: lib.to_owned(),
+            width,
+            height,
+        };
+        match self.request(&request)? {
+            Response::FractalFunc(state) => Ok(state),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn transform_fractal_func(
+        &self,
+        state: &RemoteFractalState,
+        op: FractalOp,
+    ) -> Result<RemoteFractalState, String> {
+        match self.request(&Request::TransformFractalFunc(state.clone(), op))? {
+            Response::FractalFunc(state) => Ok(state),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn compute_cells(
+        &self,
+        state: &RemoteFractalState,
+        positions: &[[u32; 2]],
+    ) -> Result<RChunk, String> {
+        match self.request(&Request::ComputeCells(state.clone(), positions.to_vec()))? {
+            Response::Chunk(chunk) => {
+                let chunk = RChunk::try_from(chunk)?;
+                check_chunk_positions(&chunk, positions)?;
+                Ok(chunk)
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn default_color_func(&self, lib: &Path) -> Result<RemoteColorState, String> {
+        match self.request(&Request::DefaultColorFunc(lib.to_owned()))? {
+            Response::ColorFunc(state) => Ok(state),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn transform_color_func(
+        &self,
+        state: &RemoteColorState,
+        op: ColorOp,
+    ) -> Result<RemoteColorState, String> {
+        match self.request(&Request::TransformColorFunc(state.clone(), op))? {
+            Response::ColorFunc(state) => Ok(state),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn compute_colors(
+        &self,
+        state: &RemoteColorState,
+        chunk: &RChunk,
+    ) -> Result<RVec<RColor>, String> {
+        match self.request(&Request::ComputeColors(state.clone(), chunk.into()))? {
+            Response::Colors(colors) => Ok(colors_from_wire(colors)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn cell_values(
+        &self,
+        state: &RemoteColorState,
+        chunk: &RChunk,
+    ) -> Result<RVec<f64>, String> {
+        match self.request(&Request::CellValues(state.clone(), chunk.into()))? {
+            Response::Values(values) => Ok(RVec::from(values)),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn compute_colors_tile(
+        &self,
+        state: &RemoteColorState,
+        tile: &RTile,
+    ) -> Result<RVec<RColor>, String> {
+        match self.request(&Request::ComputeColorsTile(state.clone(), tile.into()))? {
+            Response::Colors(colors) => Ok(colors_from_wire(colors)),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+fn spawn_child(
+    program: &Path,
+    status: Arc<Mutex<ProcessStatus>>,
+) -> anyhow::Result<Arc<Connection>> {
+    let mut child = Command::new(program)
+        .arg(CHILD_ARG)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run {}", program.display()))?;
+    let pid = child.id();
+    info!("started plugin process {}", pid);
+    let stdin = child.stdin.take().context("no stdin")?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    let stderr = child.stderr.take().context("no stderr")?;
+
+    let connection = Arc::new(Connection {
+        child: Mutex::new(child),
+        pid,
+        writer: Mutex::new(BufWriter::new(stdin)),
+        pending: Default::default(),
+        next_id: AtomicU64::new(0),
+        exit: Mutex::new(None),
+        killed: Mutex::new(None),
+    });
+
+    let stderr_tail = Arc::new(Mutex::new(VecDeque::new()));
+    let stderr_thread = {
+        let stderr_tail = stderr_tail.clone();
+        std::thread::spawn(move || forward_stderr(pid, stderr, &stderr_tail))
+    };
+
+    let reader_connection = connection.clone();
+    std::thread::spawn(move || {
+        let connection = reader_connection;
+        let mut reader = BufReader::new(stdout);
+        while let Ok((id, response)) = read_message::<(u64, Response)>(&mut reader) {
+            if let Some(sender) = connection.pending.lock().unwrap().remove(&id) {
+                sender.send(response).ok();
+            }
+        }
+        on_child_exit(&connection, stderr_thread, &stderr_tail, &status);
+    });
+    Ok(connection)
+}
+
+fn forward_stderr(pid: u32, stderr: impl std::io::Read, tail: &Mutex<VecDeque<String>>) {
+    for line in BufReader::new(stderr).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        info!(target: "plugin_process", "[{}] {}", pid, line);
+        let mut tail = tail.lock().unwrap();
+        if tail.len() == STDERR_TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+}
+
+/// fails every request still waiting for the child, and records why it died
+fn on_child_exit(
+    connection: &Connection,
+    stderr_thread: JoinHandle<()>,
+    stderr_tail: &Mutex<VecDeque<String>>,
+    status: &Mutex<ProcessStatus>,
+) {
+    let killed = connection.killed.lock().unwrap().take();
+    let exit_status = {
+        let mut child = connection.child.lock().unwrap();
+        // the output can also break while the child is still running
+        child.kill().ok();
+        child.wait()
+    };
+    stderr_thread.join().ok();
+
+    let reason = if let Some(reason) = killed {
+        reason
+    } else {
+        let exit_status = match exit_status {
+            Ok(exit_status) => exit_status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        let mut reason = format!("the plugin process died ({})", exit_status);
+        for line in stderr_tail.lock().unwrap().iter() {
+            reason.push('\n');
+            reason.push_str(line);
+        }
+        error!("{}", reason);
+        let mut status = status.lock().unwrap();
+        status.crashes += 1;
+        status.last_failure = Some(reason.clone());
+        reason
+    };
+
+    let mut pending = connection.pending.lock().unwrap();
+    *connection.exit.lock().unwrap() = Some(reason);
+    // dropping the senders wakes up the requests, which then return the reason
+    pending.clear();
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// the child
+
+/// serves requests from the host on stdin and stdout, until the host goes away
+pub fn run_child() -> anyhow::Result<()> {
+    env_logger::try_init().ok();
+    info!("plugin process started");
+    let server = Arc::new(PluginServer::new(None));
+    let writer = Arc::new(Mutex::new(BufWriter::new(std::io::stdout())));
+    let mut reader = BufReader::new(std::io::stdin());
+    loop {
+        let (id, request): (u64, Request) = match read_message(&mut reader) {
+            Ok(message) => message,
+            Err(_) => {
+                info!("host went away, plugin process exiting");
+                return Ok(());
+            }
+        };
+        let server = server.clone();
+        let writer = writer.clone();
+        rayon::spawn(move || {
+            let response = server.handle(request);
+            let mut writer = writer.lock().unwrap();
+            if write_message(&mut *writer, &(id, response)).is_err() {
+                std::process::exit(0);
+            }
+        });
+    }
+}
+
+/// answers requests with plugins loaded in this process
+pub(crate) struct PluginServer {
+    /// for a render server, the only place that libraries are loaded from, by their file name.
+    /// none for the plugin process, which loads the paths its host sends.
+    plugin_dir: Option<PathBuf>,
+    fractal_libs: Mutex<HashMap<PathBuf, Plugin<FractalLib_Ref>>>,
+    color_libs: Mutex<HashMap<PathBuf, Plugin<ColorLib_Ref>>>,
+    /// recreated function objects, by their serialized state
+    fractal_funcs: Mutex<HashMap<Vec<u8>, FractalFunc>>,
+    color_funcs: Mutex<HashMap<Vec<u8>, ColorFunc>>,
+}
+
+impl PluginServer {
+    pub(crate) fn new(plugin_dir: Option<PathBuf>) -> Self {
+        Self {
+            plugin_dir,
+            fractal_libs: Default::default(),
+            color_libs: Default::default(),
+            fractal_funcs: Default::default(),
+            color_funcs: Default::default(),
+        }
+    }
+
+    pub(crate) fn handle(&self, request: Request) -> Response {
+        self.try_handle(request).unwrap_or_else(Response::Error)
+    }
+
+    fn try_handle(&self, request: Request) -> Result<Response, String> {
+        Ok(match request {
+            Request::Hello => Response::Hello {
+                threads: rayon::current_num_threads(),
+            },
+            Request::Load(PluginKind::Fractal, lib) => {
+                self.fractal_lib(&lib)?;
+                Response::Loaded
+            }
+            Request::Load(PluginKind::Color, lib) => {
+                self.color_lib(&lib)?;
+                Response::Loaded
+            }
+            Request::DefaultFractalFunc { lib, width, height } => {
+                let func = self
+                    .fractal_lib(&lib)?
+                    .default_fractal_func_for_size(width, height);
+                Response::FractalFunc(func.remote_state(&lib))
+            }
+            Request::TransformFractalFunc(state, op) => {
+                let func = self.fractal_func(&state)?;
+                let func = match op {
+                    FractalOp::WithSize(width, height) => func.with_size(width, height),
+                    FractalOp::WithOffset(dx, dy) => func.with_offset(dx, dy),
+                    FractalOp::AddZoom(zoom_factor) => func.add_zoom(zoom_factor),
+                    FractalOp::WithOption(name, value) => func.with_option(&name, &value)?,
+                };
+                Response::FractalFunc(func.remote_state(&state.lib))
+            }
+            Request::ComputeCells(state, positions) => {
+                let chunk = self.fractal_func(&state)?.compute_cells(&positions)?;
+                Response::Chunk(WireChunk::from(&chunk))
+            }
+            Request::DefaultColorFunc(lib) => {
+                let func = self.color_lib(&lib)?.default_color_func();
+                Response::ColorFunc(func.remote_state(&lib))
+            }
+            Request::TransformColorFunc(state, op) => {
+                let func = self.color_func(&state)?;
+                let func = match op {
+                    ColorOp::WithOption(name, value) => func.with_option(&name, &value)?,
+                    ColorOp::WithStatistics(stats) => {
+                        func.with_statistics(&RColorStats::from(&stats))?
+                    }
+                };
+                Response::ColorFunc(func.remote_state(&state.lib))
+            }
+            Request::ComputeColors(state, chunk) => {
+                let colors = self
+                    .color_func(&state)?
+                    .compute_colors(&chunk.try_into()?)?;
+                Response::Colors(wire_colors(&colors))
+            }
+            Request::CellValues(state, chunk) => {
+                let values = self.color_func(&state)?.cell_values(&chunk.try_into()?)?;
+                Response::Values(values.into_vec())
+            }
+            Request::ComputeColorsTile(state, tile) => {
+                let colors = self
+                    .color_func(&state)?
+                    .compute_colors_tile(&tile.try_into()?)?;
+                Response::Colors(wire_colors(&colors))
+            }
+            Request::RenderChunk {
+                fractal,
+                color,
+                positions,
+                spacing,
+            } => {
+                let fractal_func = self.fractal_func(&fractal)?;
+                let color_func = self.color_func(&color)?;
+                let chunk = fractal_func.compute_cells(&positions)?;
+                let colors = compute_chunk_colors(&color_func, &chunk, spacing, |radius| {
+                    let apron =
+                        apron_positions(&positions, radius, spacing, fractal.width, fractal.height);
+                    fractal_func.compute_cells(&apron)
+                })?;
+                let values = if color_func.wants_statistics() {
+                    color_func.cell_values(&chunk)?.into_vec()
+                } else {
+                    vec![]
+                };
+                Response::RenderedChunk {
+                    chunk: WireChunk::from(&chunk),
+                    colors: wire_colors(&colors),
+                    values,
+                }
+            }
+        })
+    }
+
+    /// the host's path, or with a plugin dir, the library in it that `lib` names. anything else
+    /// than the bare file name of a library in the plugin dir is refused then.
+    fn resolve(&self, lib: &Path) -> Result<PathBuf, String> {
+        let plugin_dir = match &self.plugin_dir {
+            Some(plugin_dir) if builtin_name(lib).is_none() => plugin_dir,
+            _ => return Ok(lib.to_owned()),
+        };
+        let mut components = lib.components();
+        let path = match (components.next(), components.next()) {
+            (Some(Component::Normal(file_name)), None) => plugin_dir.join(file_name),
+            _ => return Err(format!("{} is not a library file name", lib.display())),
+        };
+        if !path.is_file() {
+            return Err(format!("no library {} in the plugin dir", lib.display()));
+        }
+        Ok(path)
+    }
+
+    fn fractal_lib(&self, lib: &Path) -> Result<Plugin<FractalLib_Ref>, String> {
+        let mut libs = self.fractal_libs.lock().unwrap();
+        if let Some(plugin) = libs.get(lib) {
+            return Ok(plugin.clone());
+        }
+        let plugin: Plugin<FractalLib_Ref> = Plugin::load(&self.resolve(lib)?)
+            .map_err(|e| format!("failed to load fractal library: {:#}", e))?;
+        plugin_log::init_lib(&plugin);
+        libs.insert(lib.to_owned(), plugin.clone());
+        Ok(plugin)
+    }
+
+    fn color_lib(&self, lib: &Path) -> Result<Plugin<ColorLib_Ref>, String> {
+        let mut libs = self.color_libs.lock().unwrap();
+        if let Some(plugin) = libs.get(lib) {
+            return Ok(plugin.clone());
+        }
+        let plugin: Plugin<ColorLib_Ref> = Plugin::load(&self.resolve(lib)?)
+            .map_err(|e| format!("failed to load color library: {:#}", e))?;
+        plugin_log::init_lib(&plugin);
+        libs.insert(lib.to_owned(), plugin.clone());
+        Ok(plugin)
+    }
+
+    fn fractal_func(&self, state: &RemoteFractalState) -> Result<FractalFunc, String> {
+        let key = bincode::serialize(state).map_err(|e| e.to_string())?;
+        if let Some(func) = self.fractal_funcs.lock().unwrap().get(&key) {
+            return Ok(func.clone());
+        }
+        let mut func = self
+            .fractal_lib(&state.lib)?
+            .default_fractal_func_for_size(state.width, state.height);
+        for (name, value) in state.options.iter() {
+            func = func.with_option(name, value)?;
+        }
+        cache_insert(&self.fractal_funcs, key, func.clone());
+        Ok(func)
+    }
+
+    fn color_func(&self, state: &RemoteColorState) -> Result<ColorFunc, String> {
+        let key = bincode::serialize(state).map_err(|e| e.to_string())?;
+        if let Some(func) = self.color_funcs.lock().unwrap().get(&key) {
+            return Ok(func.clone());
+        }
+        let mut func = self.color_lib(&state.lib)?.default_color_func();
+        for (name, value) in state.options.iter() {
+            func = func.with_option(name, value)?;
+        }
+        if let Some(stats) = &state.stats {
+            func = func.with_statistics(&RColorStats::from(stats))?;
+        }
+        cache_insert(&self.color_funcs, key, func.clone());
+        Ok(func)
+    }
+}
+
+fn cache_insert<T>(cache: &Mutex<HashMap<Vec<u8>, T>>, key: Vec<u8>, value: T) {
+    let mut cache = cache.lock().unwrap();
+    if cache.len() >= FUNC_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(key, value);
+}
+
+impl RemoteFractalState {
+    pub fn options_map(&self) -> ROptionsMap {
+        options_map(&self.options)
+    }
+}
+
+impl RemoteColorState {
+    pub fn options_map(&self) -> ROptionsMap {
+        options_map(&self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_server_only_resolves_libraries_in_its_plugin_dir() {
+        let dir = std::env::temp_dir().join(format!("plugin-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("libplugin.so"), "").unwrap();
+        let server = PluginServer::new(Some(dir.clone()));
+
+        let resolved = server.resolve(Path::new("libplugin.so"));
+        let refused = [
+            "libmissing.so",
+            "../libplugin.so",
+            "/tmp/libplugin.so",
+            "sub/libplugin.so",
+        ]
+        .map(|lib| server.resolve(Path::new(lib)));
+        let builtin = server.resolve(Path::new("builtin:mandelbrot_f64"));
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(resolved, Ok(dir.join("libplugin.so")));
+        assert!(refused.iter().all(Result::is_err), "{:?}", refused);
+        assert_eq!(builtin, Ok(PathBuf::from("builtin:mandelbrot_f64")));
+    }
+
+    /// a plugin process that dies as soon as the first request arrives
+    #[cfg(unix)]
+    fn crashing_program(dir: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let program = dir.join("crashing-plugin-process");
+        std::fs::write(
+            &program,
+            "#!/bin/sh\nhead -c 8 > /dev/null\necho \"child $$ crashed on purpose\" >&2\nexit 3\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        program
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crashed_child_fails_its_request_and_is_replaced() {
+        let dir = std::env::temp_dir().join(format!("plugin-process-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let process = PluginProcess::with_program(crashing_program(&dir));
+        let lib = Path::new("builtin:mandelbrot_f64");
+
+        let first = process.load(PluginKind::Fractal, lib).unwrap_err();
+        let status = process.status();
+        let second = process.load(PluginKind::Fractal, lib).unwrap_err();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert!(first.contains("died"), "{}", first);
+        assert!(first.contains("crashed on purpose"), "{}", first);
+        assert_eq!(status.crashes, 1);
+        assert_eq!(status.last_failure.as_ref(), Some(&first));
+        assert_eq!(status.pid, None);
+        // a new child, which died in turn
+        assert_eq!(process.status().crashes, 2);
+        assert!(second.contains("crashed on purpose"), "{}", second);
+        assert_ne!(first, second);
+    }
+}
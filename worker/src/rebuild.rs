@@ -0,0 +1,315 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+
+use crate::config_manager::FuncConfig;
+
+/// how often the watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// only the end of the compiler output is kept
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+/// how to rebuild a plugin library
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildConfig {
+    /// run through the shell, eg. `cargo build -p mandelbrot_f64`
+    pub command: Option<String>,
+    /// files or directories that trigger a rebuild when they change
+    pub watch_paths: Vec<PathBuf>,
+}
+
+impl From<&FuncConfig> for BuildConfig {
+    fn from(config: &FuncConfig) -> Self {
+        Self {
+            command: config.build_command.clone(),
+            watch_paths: config.watch_paths.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildStatus {
+    Idle,
+    Building,
+    Succeeded,
+    /// the build (or loading its result) failed, the old libraries are still running
+    Failed(String),
+}
+
+struct BuildResult {
+    output: String,
+    error: Option<String>,
+}
+
+/// runs the build commands of the plugin libraries, either on request or when their sources change
+#[derive(Debug)]
+pub struct Rebuilder {
+    configs: Vec<BuildConfig>,
+    watcher: Option<Watcher>,
+    status: BuildStatus,
+    output: String,
+    receiver: Option<Receiver<BuildResult>>,
+    /// the sources changed again while a build was running
+    rebuild_pending: bool,
+}
+
+impl Default for Rebuilder {
+    fn default() -> Self {
+        Self {
+            configs: vec![],
+            watcher: None,
+            status: BuildStatus::Idle,
+            output: String::new(),
+            receiver: None,
+            rebuild_pending: false,
+        }
+    }
+}
+
+impl Rebuilder {
+    pub fn set_configs(&mut self, configs: Vec<BuildConfig>) {
+        self.configs = configs;
+        if self.watcher.is_some() {
+            self.watcher = Some(Watcher::start(self.watch_paths()));
+        }
+    }
+
+    pub fn configs(&self) -> &[BuildConfig] {
+        &self.configs
+    }
+
+    pub fn set_watching(&mut self, watching: bool) {
+        if watching != self.watcher.is_some() {
+            self.watcher = watching.then(|| Watcher::start(self.watch_paths()));
+        }
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    pub fn status(&self) -> &BuildStatus {
+        &self.status
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn has_build_command(&self) -> bool {
+        self.configs.iter().any(|config| config.command.is_some())
+    }
+
+    /// starts the build commands in the background, see `poll()` for the result
+    pub fn start_build(&mut self) {
+        if self.status == BuildStatus::Building {
+            self.rebuild_pending = true;
+            return;
+        }
+        let mut commands: Vec<String> = vec![];
+        for command in self.configs.iter().filter_map(|c| c.command.clone()) {
+            if !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            sender.send(run_commands(&commands)).ok();
+        });
+        self.receiver = Some(receiver);
+        self.status = BuildStatus::Building;
+        self.output.clear();
+    }
+
+    /// loading the freshly built libraries failed
+    pub fn set_reload_failed(&mut self, error: String) {
+        self.status = BuildStatus::Failed(error);
+    }
+
+    /// returns true once a build succeeded and the libraries should be reloaded
+    pub fn poll(&mut self) -> bool {
+        let changed = self
+            .watcher
+            .as_ref()
+            .is_some_and(|watcher| watcher.changes.try_iter().count() > 0);
+        if changed {
+            info!("watched sources changed, rebuilding");
+            self.start_build();
+        }
+
+        let result = match self.receiver.as_ref().map(|r| r.try_recv()) {
+            Some(Ok(result)) => result,
+            _ => return false,
+        };
+        self.receiver = None;
+        self.output = result.output;
+        self.status = match result.error {
+            None => BuildStatus::Succeeded,
+            Some(error) => {
+                warn!("rebuild failed: {}", error);
+                BuildStatus::Failed(error)
+            }
+        };
+        if self.rebuild_pending {
+            self.rebuild_pending = false;
+            self.start_build();
+            return false;
+        }
+        self.status == BuildStatus::Succeeded
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        self.configs
+            .iter()
+            .flat_map(|c| c.watch_paths.iter().cloned())
+            .collect()
+    }
+}
+
+/// polls the modification times of the watched files on its own thread, and reports changes
+#[derive(Debug)]
+struct Watcher {
+    changes: Receiver<()>,
+    /// never sent on, the thread stops once it's dropped
+    _stop: Sender<()>,
+}
+
+impl Watcher {
+    fn start(paths: Vec<PathBuf>) -> Self {
+        let (changes_sender, changes) = channel();
+        let (stop, stop_receiver) = channel::<()>();
+        std::thread::spawn(move || {
+            let mut mtimes = scan_files(&paths);
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(POLL_INTERVAL) {
+                let new_mtimes = scan_files(&paths);
+                if new_mtimes != mtimes {
+                    mtimes = new_mtimes;
+                    if changes_sender.send(()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            changes,
+            _stop: stop,
+        }
+    }
+}
+
+fn scan_files(paths: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut mtimes = HashMap::new();
+    for path in paths {
+        collect_mtimes(path, &mut mtimes);
+    }
+    mtimes
+}
+
+fn collect_mtimes(path: &Path, mtimes: &mut HashMap<PathBuf, SystemTime>) {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    if metadata.is_dir() {
+        let is_ignored = path
+            .file_name()
+            .map(|name| name == "target" || name.to_string_lossy().starts_with('.'))
+            .unwrap_or(false);
+        if is_ignored {
+            return;
+        }
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                collect_mtimes(&entry.path(), mtimes);
+            }
+        }
+    } else if let Ok(mtime) = metadata.modified() {
+        mtimes.insert(path.to_owned(), mtime);
+    }
+}
+
+fn shell_command(command: &str) -> Command {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut shell_command = Command::new(shell);
+    shell_command.args([flag, command]);
+    shell_command
+}
+
+fn run_commands(commands: &[String]) -> BuildResult {
+    let mut output = String::new();
+    for command in commands {
+        info!("running build command: {}", command);
+        output.push_str(&format!("$ {}\n", command));
+        let res = match shell_command(command).output() {
+            Ok(res) => res,
+            Err(e) => {
+                return BuildResult {
+                    output,
+                    error: Some(format!("failed to run {:?}: {}", command, e)),
+                }
+            }
+        };
+        output.push_str(&String::from_utf8_lossy(&res.stdout));
+        output.push_str(&String::from_utf8_lossy(&res.stderr));
+        truncate_front(&mut output, MAX_OUTPUT_LEN);
+        if !res.status.success() {
+            return BuildResult {
+                output,
+                error: Some(format!("{:?} failed with {}", command, res.status)),
+            };
+        }
+    }
+    BuildResult {
+        output,
+        error: None,
+    }
+}
+
+fn truncate_front(s: &mut String, max_len: usize) {
+    if s.len() > max_len {
+        let mut start = s.len() - max_len;
+        while !s.is_char_boundary(start) {
+            start += 1;
+        }
+        s.drain(..start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn watcher_reports_changed_files() {
+        let dir = std::env::temp_dir().join(format!("rebuild-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.rs");
+        std::fs::write(&path, "").unwrap();
+
+        let watcher = Watcher::start(vec![dir.clone()]);
+        // let it take the first snapshot
+        std::thread::sleep(POLL_INTERVAL / 2);
+        assert!(watcher.changes.try_recv().is_err());
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let changed = watcher.changes.recv_timeout(POLL_INTERVAL * 4);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(changed.is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use crate::color_buffer::ToneMapping;
+use crate::fractal_worker2::compute_chunk_colors;
+use crate::plugin_host::{ColorFunc, FractalFunc};
+use crate::tiles::apron_positions;
+
+/// the samples of a pixel are on a grid of this many per side, so at most its square per pixel
+pub const SUBDIVISIONS: u32 = 4;
+/// pixels whose tone mapped color differs from a neighbor's by more than this get refined
+pub const CONTRAST_THRESHOLD: f32 = 0.1;
+/// a pixel is done when a round of samples moves its tone mapped color by less than this
+const CONVERGED_CHANGE: f32 = 1.0 / 255.0;
+/// the fine grid has twice the resolution of the sample grid, so that the samples sit between its
+/// cells, centered on the pixel's own cell
+const FINE_SCALE: u32 = 2 * SUBDIVISIONS;
+/// where on the sample grid the samples of each round are. every round is symmetric around the
+/// middle of the pixel, so that stopping after any of them doesn't shift the average.
+const ROUNDS: [&[[u32; 2]]; 4] = [
+    &[[1, 1], [2, 1], [1, 2], [2, 2]],
+    &[[0, 0], [3, 0], [0, 3], [3, 3]],
+    &[[1, 0], [2, 3], [3, 1], [0, 2]],
+    &[[2, 0], [1, 3], [0, 1], [3, 2]],
+];
+
+/// computes the cells of a fractal func at sub-pixel positions, through a copy of it with
+/// `FINE_SCALE` times the resolution
+#[derive(Debug, Clone)]
+pub struct SubpixelSampler {
+    fractal_func: FractalFunc,
+    width: u32,
+    height: u32,
+    /// where the cell of pixel 0 is on the fine grid
+    origin: [i64; 2],
+}
+
+impl SubpixelSampler {
+    pub fn new(fractal_func: &FractalFunc) -> Self {
+        let (width, height) = fractal_func.get_size();
+        let (fine_width, fine_height) = (width * FINE_SCALE, height * FINE_SCALE);
+        // both keep the middle cell where it is, see `RFractalFunc::with_size`
+        let fine_func = fractal_func
+            .with_size(fine_width, fine_height)
+            .add_zoom(FINE_SCALE as f64);
+        let origin = [(fine_width, width), (fine_height, height)]
+            .map(|(fine, coarse)| (fine / 2) as i64 - (coarse / 2 * FINE_SCALE) as i64);
+        Self {
+            fractal_func: fine_func,
+            width: fine_width,
+            height: fine_height,
+            origin,
+        }
+    }
+
+    /// the cell on the fine grid of the sample at `offset` on the sample grid of `pixel`
+    fn sample_pos(&self, pixel: [u32; 2], offset: [u32; 2]) -> Option<[u32; 2]> {
+        let [x, y] = [0, 1]
+            .map(|i| self.origin[i] + (pixel[i] * FINE_SCALE) as i64 + sample_offset(offset[i]));
+        let inside = (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y);
+        inside.then_some([x as u32, y as u32])
+    }
+
+    /// the pixel that the sample at the fine grid cell `pos` belongs to
+    fn pixel_of(&self, pos: [u32; 2]) -> [u32; 2] {
+        [0, 1].map(|i| {
+            ((pos[i] as i64 - self.origin[i] + (FINE_SCALE / 2) as i64) / FINE_SCALE as i64) as u32
+        })
+    }
+
+    /// the averages of more samples for `pixels`, whose current linear colors are `colors`.
+    /// samples are added in rounds until a pixel's color stops changing or it runs out of them.
+    pub fn refine(
+        &self,
+        color_func: &ColorFunc,
+        tone_mapping: &ToneMapping,
+        pixels: &[[u32; 2]],
+        colors: &[[f32; 4]],
+    ) -> Result<Vec<[f32; 4]>, String> {
+        let indexes: HashMap<[u32; 2], usize> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &pos)| (pos, i))
+            .collect();
+        let mut sums = colors.to_vec();
+        let mut counts = vec![1u32; pixels.len()];
+        let mut active = (0..pixels.len()).collect::<Vec<_>>();
+        for round in ROUNDS {
+            if active.is_empty() {
+                break;
+            }
+            let positions = active
+                .iter()
+                .flat_map(|&i| {
+                    round
+                        .iter()
+                        .filter_map(move |&offset| self.sample_pos(pixels[i], offset))
+                })
+                .collect::<Vec<_>>();
+            let chunk = self
+                .fractal_func
+                .compute_cells(&positions)
+                .map_err(|e| format!("fractal func: {}", e))?;
+            // a color func that looks at the neighborhood sees the one on the sample grid
+            let samples = compute_chunk_colors(color_func, &chunk, 2, |radius| {
+                let apron = apron_positions(&positions, radius, 2, self.width, self.height);
+                self.fractal_func
+                    .compute_cells(&apron)
+                    .map_err(|e| format!("fractal func: {}", e))
+            })?;
+
+            let before = active
+                .iter()
+                .map(|&i| average(sums[i], counts[i]))
+                .collect::<Vec<_>>();
+            for sample in samples.iter() {
+                if let Some(&i) = indexes.get(&self.pixel_of(sample.pos)) {
+                    let rgba = sample.value.to_linear();
+                    sums[i] = [0, 1, 2, 3].map(|c| sums[i][c] + rgba[c]);
+                    counts[i] += 1;
+                }
+            }
+            active = active
+                .into_iter()
+                .zip(before)
+                .filter(|&(i, before)| {
+                    let before = tone_mapping.map(before);
+                    let after = tone_mapping.map(average(sums[i], counts[i]));
+                    before
+                        .iter()
+                        .zip(after)
+                        .any(|(a, b)| (a - b).abs() >= CONVERGED_CHANGE)
+                })
+                .map(|(i, _)| i)
+                .collect();
+        }
+        Ok(sums
+            .into_iter()
+            .zip(counts)
+            .map(|(sum, count)| average(sum, count))
+            .collect())
+    }
+}
+
+/// how far the sample at `offset` on the sample grid is from the pixel's own cell, on the fine grid
+fn sample_offset(offset: u32) -> i64 {
+    (2 * offset + 1) as i64 - SUBDIVISIONS as i64
+}
+
+fn average(sum: [f32; 4], count: u32) -> [f32; 4] {
+    sum.map(|v| v / count as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn rounds_are_centered_on_the_pixel() {
+        for round in ROUNDS {
+            let sum = round.iter().fold([0, 0], |[x, y], offset| {
+                [x + sample_offset(offset[0]), y + sample_offset(offset[1])]
+            });
+            assert_eq!(sum, [0, 0], "{:?}", round);
+        }
+        let offsets = ROUNDS
+            .iter()
+            .flat_map(|round| round.iter())
+            .collect::<HashSet<_>>();
+        assert_eq!(offsets.len(), (SUBDIVISIONS * SUBDIVISIONS) as usize);
+    }
+
+    #[test]
+    fn samples_stay_inside_their_pixel() {
+        let half = (FINE_SCALE / 2) as i64;
+        for offset in 0..SUBDIVISIONS {
+            assert!((-half..half).contains(&sample_offset(offset)));
+        }
+    }
+}
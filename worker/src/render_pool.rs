@@ -0,0 +1,159 @@
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+
+/// the nice value of the render threads at `RenderPriority::Low`
+#[cfg(target_os = "linux")]
+const LOW_PRIORITY_NICE: libc::c_int = 10;
+
+/// how the OS schedules the render threads against everything else
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderPriority {
+    #[default]
+    Normal,
+    /// everything else goes first, eg. the gui. only on linux, elsewhere it's the same as normal.
+    Low,
+}
+
+impl RenderPriority {
+    pub const ALL: [RenderPriority; 2] = [RenderPriority::Normal, RenderPriority::Low];
+}
+
+thread_local! {
+    /// the priority that the calling thread was last set to
+    static THREAD_PRIORITY: Cell<RenderPriority> = const { Cell::new(RenderPriority::Normal) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// 0 is one per CPU
+    pub threads: usize,
+    pub priority: RenderPriority,
+}
+
+/// the threads that the worker renders, recolors and refines on, so that it doesn't compete with
+/// whatever else uses the global rayon pool
+#[derive(Clone)]
+pub struct RenderPool {
+    pool: Arc<ThreadPool>,
+    config: PoolConfig,
+}
+
+impl RenderPool {
+    pub fn new(config: PoolConfig) -> anyhow::Result<Self> {
+        let priority = config.priority;
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(config.threads)
+            .thread_name(|index| format!("render-{}", index))
+            .start_handler(move |_| set_thread_priority(priority))
+            .build()?;
+        Ok(Self {
+            pool: Arc::new(pool),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> PoolConfig {
+        self.config
+    }
+
+    pub fn current_num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// the parallel iterators of `job` run on this pool as well
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        self.pool.spawn(job);
+    }
+}
+
+/// for the calling thread only. it's only tried once per change, and going back up to normal
+/// fails without the privilege to raise priorities.
+fn set_thread_priority(priority: RenderPriority) {
+    if THREAD_PRIORITY.replace(priority) == priority {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let nice = match priority {
+            RenderPriority::Normal => 0,
+            RenderPriority::Low => LOW_PRIORITY_NICE,
+        };
+        // on linux, the nice value of PRIO_PROCESS 0 is the calling thread's own
+        let res = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+        if res != 0 {
+            let error = std::io::Error::last_os_error();
+            match priority {
+                // expected without the privilege, the worker replaces the pool for the next render
+                RenderPriority::Normal => {
+                    log::debug!("can't raise the render thread priority: {}", error)
+                }
+                RenderPriority::Low => {
+                    log::warn!("failed to lower the render thread priority: {}", error)
+                }
+            }
+        }
+    }
+}
+
+/// holds the threads of a render while it's paused, and lets them go for good once it's closed.
+/// the threads that pass also take on its priority.
+#[derive(Debug, Default)]
+pub(crate) struct RenderGate {
+    state: Mutex<GateState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    paused: bool,
+    closed: bool,
+    priority: RenderPriority,
+}
+
+impl RenderGate {
+    pub(crate) fn new(paused: bool, priority: RenderPriority) -> Self {
+        Self {
+            state: Mutex::new(GateState {
+                paused,
+                closed: false,
+                priority,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// for the threads that pass from now on, without interrupting them
+    pub(crate) fn set_priority(&self, priority: RenderPriority) {
+        self.state.lock().unwrap().priority = priority;
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = paused;
+        self.changed.notify_all();
+    }
+
+    /// for a render that was replaced
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// blocks while paused, false once closed
+    pub(crate) fn pass(&self) -> bool {
+        let state = self
+            .changed
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.paused && !state.closed
+            })
+            .unwrap();
+        let (open, priority) = (!state.closed, state.priority);
+        drop(state);
+        if open {
+            set_thread_priority(priority);
+        }
+        open
+    }
+}
@@ -0,0 +1,267 @@
+//! renders chunks for `FractalWorker`s on other machines, see the `worker_server` binary.
+//!
+//! this is the plugin process protocol (see `plugin_process`) over tcp, answered one request at a
+//! time per connection. a render server only loads the libraries in its own plugin dir, by the
+//! file names of the ones the host uses, so they have to be built from the same sources.
+
+use std::{
+    io::{BufReader, BufWriter},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use abi_stable::std_types::RVec;
+use anyhow::Context;
+use color_func::RColor;
+use fractal_func::prelude::RChunk;
+use log::{info, warn};
+
+use crate::fractal_worker2::{check_chunk_positions, check_color_positions};
+use crate::plugin_process::{
+    unexpected, PluginServer, RemoteColorState, RemoteFractalState, Request, Response,
+};
+use crate::wire::{colors_from_wire, read_message, write_message};
+
+/// only reachable from this machine, listening anywhere else is up to the user
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// a server that doesn't answer for this long is given up on, and its chunk is rendered locally
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub struct RenderServer {
+    listener: TcpListener,
+    server: Arc<PluginServer>,
+}
+
+impl RenderServer {
+    /// `plugin_dir`: the libraries that clients may ask for, by their file names
+    pub fn bind(addr: impl ToSocketAddrs, plugin_dir: PathBuf) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(addr).context("failed to bind the render server")?;
+        Ok(Self {
+            listener,
+            server: Arc::new(PluginServer::new(Some(plugin_dir))),
+        })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// serves connections until the listener fails
+    pub fn run(self) -> anyhow::Result<()> {
+        info!("render server listening on {}", self.local_addr()?);
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let server = self.server.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve_connection(stream, &server) {
+                    warn!("render server connection failed: {:#}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// serves connections in the background, eg. for a server on localhost
+    pub fn spawn(self) {
+        std::thread::spawn(move || {
+            if let Err(e) = self.run() {
+                warn!("render server stopped: {:#}", e);
+            }
+        });
+    }
+}
+
+fn serve_connection(stream: TcpStream, server: &PluginServer) -> anyhow::Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_nodelay(true)?;
+    info!("{} connected", peer);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let (id, request): (u64, Request) = match read_message(&mut reader) {
+            Ok(message) => message,
+            Err(_) => {
+                info!("{} disconnected", peer);
+                return Ok(());
+            }
+        };
+        let response = server.handle(request);
+        write_message(&mut writer, &(id, response))?;
+    }
+}
+
+/// a connection to a render server, for one request at a time
+pub struct RemoteConnection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    next_id: u64,
+}
+
+impl RemoteConnection {
+    pub fn connect(addr: &str) -> anyhow::Result<Self> {
+        let socket_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .with_context(|| format!("{} didn't resolve to anything", addr))?;
+        let stream = TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT)
+            .with_context(|| format!("failed to connect to {}", addr))?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            next_id: 0,
+        })
+    }
+
+    fn request(&mut self, request: &Request) -> Result<Response, String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        write_message(&mut self.writer, &(id, request)).map_err(|e| e.to_string())?;
+        match read_message::<(u64, Response)>(&mut self.reader) {
+            Ok((_, Response::Error(msg))) => Err(msg),
+            Ok((response_id, response)) if response_id == id => Ok(response),
+            Ok((response_id, _)) => Err(format!(
+                "got the response to request {} instead of {}",
+                response_id, id
+            )),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// how many chunks the server can render at once
+    pub fn threads(&mut self) -> Result<usize, String> {
+        match self.request(&Request::Hello)? {
+            Response::Hello { threads } => Ok(threads),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// the cells of a chunk, their colors, and their values if the color func wants statistics
+    pub fn render_chunk(
+        &mut self,
+        fractal: &RemoteFractalState,
+        color: &RemoteColorState,
+        positions: &[[u32; 2]],
+        spacing: u32,
+    ) -> Result<(RChunk, RVec<RColor>, RVec<f64>), String> {
+        let request = Request::RenderChunk {
+            fractal: RemoteFractalState {
+                lib: server_lib(&fractal.lib),
+                ..fractal.clone()
+            },
+            color: RemoteColorState {
+                lib: server_lib(&color.lib),
+                ..color.clone()
+            },
+            positions: positions.to_vec(),
+            spacing,
+        };
+        match self.request(&request)? {
+            Response::RenderedChunk {
+                chunk,
+                colors,
+                values,
+            } => {
+                // the server may be broken or hostile, nothing it sends may reach the screen unchecked
+                let chunk = RChunk::try_from(chunk)?;
+                check_chunk_positions(&chunk, positions)?;
+                let colors = colors_from_wire(colors);
+                check_color_positions(&colors, &chunk)?;
+                if !values.is_empty() && values.len() != chunk.len() {
+                    return Err(format!("{} values for {} cells", values.len(), chunk.len()));
+                }
+                Ok((chunk, colors, RVec::from(values)))
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// the name that a render server knows a library by, see `RenderServer::bind`
+fn server_lib(lib: &Path) -> PathBuf {
+    lib.file_name()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| lib.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wire::{WireChunk, WireColor, WireColorValue};
+
+    use super::*;
+
+    /// a server that answers the requests on one connection with `responses`, in order
+    fn fake_server(responses: Vec<Response>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            for response in responses {
+                let (id, _) = read_message::<(u64, Request)>(&mut reader).unwrap();
+                write_message(&mut writer, &(id, response)).unwrap();
+            }
+        });
+        addr
+    }
+
+    fn rendered_chunk(pos_indexes: Vec<([u32; 2], usize)>, colors: &[[u32; 2]]) -> Response {
+        Response::RenderedChunk {
+            chunk: WireChunk {
+                pos_indexes,
+                data: vec![0; 2],
+            },
+            colors: colors
+                .iter()
+                .map(|&pos| WireColor {
+                    pos,
+                    value: WireColorValue::Rgb8([0; 3]),
+                })
+                .collect(),
+            values: vec![],
+        }
+    }
+
+    #[test]
+    fn malformed_chunks_are_refused() {
+        let addr = fake_server(vec![
+            rendered_chunk(vec![([0, 0], 0), ([1, 0], 1)], &[[0, 0], [1, 0]]),
+            rendered_chunk(vec![([0, 0], 0), ([1, 0], 3)], &[[0, 0], [1, 0]]),
+            rendered_chunk(vec![([0, 0], 0), ([9999, 0], 1)], &[[0, 0], [9999, 0]]),
+            rendered_chunk(vec![([0, 0], 0), ([1, 0], 1)], &[[0, 0], [0, 9999]]),
+        ]);
+        let fractal = RemoteFractalState {
+            lib: PathBuf::from("libfractal.so"),
+            width: 2,
+            height: 1,
+            options: vec![],
+            fill_safe_fields: vec![],
+            can_resample: true,
+        };
+        let color = RemoteColorState {
+            lib: PathBuf::from("libcolor.so"),
+            options: vec![],
+            stats: None,
+            wants_statistics: false,
+            neighborhood_radius: 0,
+        };
+        let mut connection = RemoteConnection::connect(&addr).unwrap();
+        let mut render = || connection.render_chunk(&fractal, &color, &[[0, 0], [1, 0]], 1);
+
+        assert!(render().is_ok());
+        assert!(render().unwrap_err().contains("offset"));
+        assert!(render().unwrap_err().contains("[9999, 0]"));
+        assert!(render().unwrap_err().contains("[0, 9999]"));
+    }
+}
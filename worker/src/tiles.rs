@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use fractal_func::RChunk;
+
+use crate::color_buffer::cell_block_size;
+
+/// the spacing of the cells in a chunk of a render with `block_size`: all cells of a chunk are
+/// from the same progressive pass, see `cell_block_size`
+pub fn chunk_spacing(positions: impl IntoIterator<Item = [u32; 2]>, block_size: u32) -> u32 {
+    positions
+        .into_iter()
+        .next()
+        .map_or(1, |pos| cell_block_size(pos, block_size))
+}
+
+/// positions up to `radius` steps of `spacing` away from `positions` that are not in
+/// `positions` themselves, clipped to the canvas
+pub fn apron_positions(
+    positions: &[[u32; 2]],
+    radius: u32,
+    spacing: u32,
+    width: u32,
+    height: u32,
+) -> Vec<[u32; 2]> {
+    let mut seen: HashSet<[u32; 2]> = positions.iter().copied().collect();
+    let mut apron = vec![];
+    let radius = radius as i64;
+    for &[x, y] in positions {
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let ax = x as i64 + dx * spacing as i64;
+                let ay = y as i64 + dy * spacing as i64;
+                if ax < 0 || ay < 0 || ax >= width as i64 || ay >= height as i64 {
+                    continue;
+                }
+                let pos = [ax as u32, ay as u32];
+                if seen.insert(pos) {
+                    apron.push(pos);
+                }
+            }
+        }
+    }
+    apron
+}
+
+/// finds the cell at a given position among all the retained chunks
+pub struct CellIndex {
+    width: u32,
+    height: u32,
+    /// (chunk index + 1, cell index), 0 for positions without a cell
+    entries: Vec<(u32, u32)>,
+}
+
+impl CellIndex {
+    pub fn new(chunks: &[Arc<RChunk>], width: u32, height: u32) -> Self {
+        let mut entries = vec![(0, 0); (width * height) as usize];
+        for (chunk_index, chunk) in chunks.iter().enumerate() {
+            for (cell_index, [x, y]) in chunk.positions().enumerate() {
+                if x < width && y < height {
+                    entries[(x + y * width) as usize] = (chunk_index as u32 + 1, cell_index as u32);
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            entries,
+        }
+    }
+
+    pub fn get<'a>(&self, chunks: &'a [Arc<RChunk>], pos: [u32; 2]) -> Option<&'a [u8]> {
+        let [x, y] = pos;
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        match self.entries[(x + y * self.width) as usize] {
+            (0, _) => None,
+            (chunk_index, cell_index) => chunks[chunk_index as usize - 1]
+                .get(cell_index as usize)
+                .map(|(_, data)| data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apron_surrounds_the_cells_at_their_spacing() {
+        let positions = [[8, 8], [16, 8]];
+        let mut apron = apron_positions(&positions, 1, 8, 64, 64);
+        apron.sort_by_key(|&[x, y]| (y, x));
+        assert_eq!(
+            apron,
+            [
+                [0, 0],
+                [8, 0],
+                [16, 0],
+                [24, 0],
+                [0, 8],
+                [24, 8],
+                [0, 16],
+                [8, 16],
+                [16, 16],
+                [24, 16]
+            ]
+        );
+    }
+
+    #[test]
+    fn apron_of_sparse_cells_stays_near_them() {
+        let positions = [[0, 0], [99, 99]];
+        let apron = apron_positions(&positions, 2, 1, 100, 100);
+        // two corners of 5x5 blocks, clipped to 3x3
+        assert_eq!(apron.len(), 2 * 8);
+        assert!(apron
+            .iter()
+            .all(|&[x, y]| (x <= 2 && y <= 2) || (x >= 97 && y >= 97)));
+    }
+
+    #[test]
+    fn chunks_have_the_spacing_of_their_pass() {
+        assert_eq!(chunk_spacing([[0, 0], [16, 0]], 16), 16);
+        assert_eq!(chunk_spacing([[8, 0], [24, 0]], 16), 8);
+        assert_eq!(chunk_spacing([[3, 2]], 16), 1);
+        assert_eq!(chunk_spacing([[0, 0]], 1), 1);
+        assert_eq!(chunk_spacing([], 16), 1);
+    }
+}
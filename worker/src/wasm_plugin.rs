@@ -0,0 +1,788 @@
+//! fractal and color funcs compiled to WebAssembly (`.wasm`, or `.wat` text), run in the wasmi
+//! interpreter with limited fuel and memory, so plugins from elsewhere can be used without trusting
+//! them. the interface they implement is described in `interface/wasm.md`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use abi_stable::{
+    sabi_trait::TD_Opaque,
+    std_types::{RResult, RSlice, RStr, RString, RVec, Tuple2},
+};
+use anyhow::Context;
+use log::{error, Level};
+use wasmi::{
+    core::F64, Caller, Config, Engine, Extern, ExternType, Instance, Linker, Memory, Module, Store,
+    StoreLimits, StoreLimitsBuilder, TypedFunc, WasmParams, WasmResults,
+};
+
+use color_func::{
+    prelude::{RColorFunc, RColorFuncBox},
+    RColor, RColorValue,
+};
+use fractal_func::prelude::{RChunk, RFractalFunc, RFractalFuncBox, ROptionsMap};
+
+use crate::plugin_dir::PluginKind;
+
+/// the version of the interface in `interface/wasm.md`
+pub const WASM_ABI_VERSION: &str = "wasm 1";
+pub const WASM_EXTENSIONS: &[&str] = &["wasm", "wat"];
+
+/// host options, next to the ones of the plugin
+const FUEL_OPTION: &str = "wasm_fuel";
+const MEMORY_OPTION: &str = "wasm_max_memory_mb";
+/// the most bytes per cell that a fractal func may ask for
+const MAX_CELL_SIZE: i32 = 4096;
+
+pub fn is_wasm_plugin(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| WASM_EXTENSIONS.iter().any(|e| extension == *e))
+}
+
+/// limits for every call into a plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmLimits {
+    pub fuel: u64,
+    pub max_memory_mb: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 10_000_000_000,
+            max_memory_mb: 64,
+        }
+    }
+}
+
+impl WasmLimits {
+    fn options(&self) -> impl Iterator<Item = (String, String)> {
+        [
+            (FUEL_OPTION.to_owned(), self.fuel.to_string()),
+            (MEMORY_OPTION.to_owned(), self.max_memory_mb.to_string()),
+        ]
+        .into_iter()
+    }
+
+    /// `None` if `name` isn't a host option
+    fn with_option(&self, name: &str, value: &str) -> Option<Result<Self, String>> {
+        let mut limits = *self;
+        let res = match name {
+            FUEL_OPTION => value.parse().map(|fuel| limits.fuel = fuel),
+            MEMORY_OPTION => value.parse().map(|mb| limits.max_memory_mb = mb),
+            _ => return None,
+        };
+        Some(
+            res.map(|_| limits)
+                .map_err(|e| format!("error parsing option {}: {}", name, e)),
+        )
+    }
+}
+
+/// a compiled wasm plugin
+pub struct WasmModule {
+    path: PathBuf,
+    /// the file name, as the log target
+    name: String,
+    engine: Engine,
+    module: Module,
+    param_names: Vec<String>,
+    /// for fractal funcs
+    cell_size: usize,
+}
+
+impl fmt::Debug for WasmModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmModule")
+            .field("path", &self.path)
+            .field("param_names", &self.param_names)
+            .finish_non_exhaustive()
+    }
+}
+
+/// the state of one call
+struct CallState {
+    limits: StoreLimits,
+    log_target: String,
+}
+
+/// a fresh instance of the module, for one call
+struct Call<'a> {
+    module: &'a WasmModule,
+    store: Store<CallState>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl WasmModule {
+    pub fn load(path: &Path, kind: PluginKind) -> anyhow::Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let bytes = wat::parse_bytes(&bytes).context("failed to parse the wasm text")?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module =
+            Module::new(&engine, &bytes[..]).context("failed to compile the wasm module")?;
+        let found_kind = module_kind(&module);
+        if found_kind != Some(kind) {
+            anyhow::bail!(
+                "{} is not a {} plugin, it doesn't export {}",
+                path.display(),
+                kind,
+                required_export(kind)
+            );
+        }
+        let mut wasm_module = Self {
+            path: path.to_owned(),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            engine,
+            module,
+            param_names: vec![],
+            cell_size: 0,
+        };
+
+        let (param_names, cell_size) = wasm_module.describe(kind).map_err(anyhow::Error::msg)?;
+        wasm_module.param_names = param_names;
+        wasm_module.cell_size = cell_size;
+        Ok(wasm_module)
+    }
+
+    /// the names of the params, and the cell size of a fractal func
+    fn describe(&self, kind: PluginKind) -> Result<(Vec<String>, usize), String> {
+        let mut call = self.call(&WasmLimits::default())?;
+        let param_count: i32 = call.invoke("param_count", ())?;
+        let param_names = (0..param_count)
+            .map(|index| {
+                let packed: i64 = call.invoke("param_name", index)?;
+                call.read_string(packed)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let cell_size = match kind {
+            PluginKind::Fractal => match call.invoke::<(), i32>("cell_size", ())? {
+                cell_size @ 1..=MAX_CELL_SIZE => cell_size as usize,
+                cell_size => {
+                    return Err(format!(
+                        "cell_size {} is outside of 1 to {}",
+                        cell_size, MAX_CELL_SIZE
+                    ))
+                }
+            },
+            PluginKind::Color => 0,
+        };
+        Ok((param_names, cell_size))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn call(&self, limits: &WasmLimits) -> Result<Call<'_>, String> {
+        let state = CallState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.max_memory_mb.saturating_mul(1 << 20))
+                .build(),
+            log_target: self.name.clone(),
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.add_fuel(limits.fuel).map_err(|e| e.to_string())?;
+
+        let mut linker = <Linker<CallState>>::new(&self.engine);
+        linker
+            .func_wrap("host", "log", host_log)
+            .map_err(|e| e.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| format!("failed to instantiate {}: {}", self.name, e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| format!("{} doesn't export its memory", self.name))?;
+        Ok(Call {
+            module: self,
+            store,
+            instance,
+            memory,
+        })
+    }
+
+    /// the params as options, along with the host options
+    fn options(&self, params: &[f64], limits: &WasmLimits) -> ROptionsMap {
+        self.param_names
+            .iter()
+            .zip(params)
+            .map(|(name, value)| (name.clone(), value.to_string()))
+            .chain(limits.options())
+            .map(|(name, value)| (RString::from(name), RString::from(value)))
+            .collect()
+    }
+
+    fn param_index(&self, name: &str, value: &str) -> Result<(usize, f64), String> {
+        let index = self
+            .param_names
+            .iter()
+            .position(|param| param == name)
+            .ok_or_else(|| format!("unknown option {}", name))?;
+        let value = value
+            .parse()
+            .map_err(|e| format!("error parsing option {}: {}", name, e))?;
+        Ok((index, value))
+    }
+}
+
+impl Call<'_> {
+    fn invoke<P: WasmParams, R: WasmResults>(
+        &mut self,
+        name: &str,
+        params: P,
+    ) -> Result<R, String> {
+        let func: TypedFunc<P, R> = self
+            .instance
+            .get_typed_func(&self.store, name)
+            .map_err(|e| format!("{}: {}", name, e))?;
+        func.call(&mut self.store, params)
+            .map_err(|e| format!("{} failed: {}", name, e))
+    }
+
+    fn has_export(&self, name: &str) -> bool {
+        self.instance.get_export(&self.store, name).is_some()
+    }
+
+    /// copies `bytes` into the instance's memory
+    fn write(&mut self, bytes: &[u8]) -> Result<i32, String> {
+        let len = i32::try_from(bytes.len()).map_err(|_| "too much data for a wasm plugin")?;
+        let ptr: i32 = self.invoke("alloc", len)?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|e| format!("alloc returned bad memory: {}", e))?;
+        Ok(ptr)
+    }
+
+    fn read(&self, ptr: i32, len: usize) -> Result<Vec<u8>, String> {
+        memory_slice(self.memory.data(&self.store), ptr, len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| format!("{} returned memory out of bounds", self.module.name))
+    }
+
+    fn read_string(&self, packed: i64) -> Result<String, String> {
+        let bytes = self.read((packed >> 32) as i32, packed as u32 as usize)?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+
+    fn write_params(&mut self, params: &[f64]) -> Result<i32, String> {
+        let bytes = params
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.write(&bytes)
+    }
+
+    fn alloc_params(&mut self) -> Result<i32, String> {
+        self.write_params(&vec![0.0; self.module.param_names.len()])
+    }
+
+    fn read_params(&self, ptr: i32) -> Result<Vec<f64>, String> {
+        let bytes = self.read(ptr, self.module.param_names.len() * 8)?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+}
+
+fn host_log(caller: Caller<'_, CallState>, level: i32, ptr: i32, len: i32) {
+    let memory = match caller.get_export("memory").and_then(Extern::into_memory) {
+        Some(memory) => memory,
+        None => return,
+    };
+    let bytes = match memory_slice(memory.data(&caller), ptr, len.max(0) as usize) {
+        Some(bytes) => bytes,
+        None => return,
+    };
+    let level = match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    };
+    log::log!(
+        target: &caller.data().log_target,
+        level,
+        "{}",
+        String::from_utf8_lossy(bytes)
+    );
+}
+
+/// the `len` bytes at `ptr` in `memory`, checked before anything is allocated for them
+fn memory_slice(memory: &[u8], ptr: i32, len: usize) -> Option<&[u8]> {
+    let start = ptr as u32 as usize;
+    memory.get(start..start.checked_add(len)?)
+}
+
+fn required_export(kind: PluginKind) -> &'static str {
+    match kind {
+        PluginKind::Fractal => "compute_cells",
+        PluginKind::Color => "compute_colors",
+    }
+}
+
+fn module_kind(module: &Module) -> Option<PluginKind> {
+    [PluginKind::Fractal, PluginKind::Color]
+        .into_iter()
+        .find(|kind| exports_func(module, required_export(*kind)))
+}
+
+fn exports_func(module: &Module, name: &str) -> bool {
+    module
+        .exports()
+        .any(|export| export.name() == name && matches!(export.ty(), ExternType::Func(_)))
+}
+
+/// the kind of a wasm plugin, for listing it in the plugin dir
+pub fn inspect_wasm_plugin(path: &Path) -> anyhow::Result<Option<PluginKind>> {
+    let bytes = wat::parse_file(path)?;
+    let engine = Engine::default();
+    let module = Module::new(&engine, &bytes[..])?;
+    // a lone function is no plugin without its memory
+    let exports_memory = module
+        .exports()
+        .any(|export| export.name() == "memory" && matches!(export.ty(), ExternType::Memory(_)));
+    Ok(module_kind(&module).filter(|_| exports_memory))
+}
+
+fn check_code(name: &str, code: i32) -> Result<(), String> {
+    match code {
+        0 => Ok(()),
+        code => Err(format!("{} failed with error code {}", name, code)),
+    }
+}
+
+fn plugin_result<T>(res: Result<T, String>) -> RResult<T, RString> {
+    res.map_err(RString::from).into()
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone)]
+pub struct WasmFractalFunc {
+    module: Arc<WasmModule>,
+    width: u32,
+    height: u32,
+    params: Vec<f64>,
+    limits: WasmLimits,
+}
+
+impl WasmFractalFunc {
+    pub fn new_default(module: Arc<WasmModule>, width: u32, height: u32) -> RFractalFuncBox {
+        let limits = WasmLimits::default();
+        let params = module
+            .call(&limits)
+            .and_then(|mut call| {
+                let params = call.alloc_params()?;
+                call.invoke::<_, ()>("default_params", (width as i32, height as i32, params))?;
+                call.read_params(params)
+            })
+            .unwrap_or_else(|msg| {
+                error!(
+                    "failed to get the default params of {}: {}",
+                    module.name, msg
+                );
+                vec![0.0; module.param_names.len()]
+            });
+        Self {
+            module,
+            width,
+            height,
+            params,
+            limits,
+        }
+        .boxed()
+    }
+
+    fn boxed(self) -> RFractalFuncBox {
+        RFractalFuncBox::from_value(self, TD_Opaque)
+    }
+
+    /// calls `name` to update the params, if the plugin exports it
+    fn transform<P: WasmParams>(
+        &self,
+        name: &str,
+        args: impl FnOnce(i32) -> P,
+        width: u32,
+        height: u32,
+    ) -> RFractalFuncBox {
+        let params = self
+            .module
+            .call(&self.limits)
+            .and_then(|mut call| {
+                if !call.has_export(name) {
+                    return Ok(self.params.clone());
+                }
+                let params = call.write_params(&self.params)?;
+                call.invoke::<P, ()>(name, args(params))?;
+                call.read_params(params)
+            })
+            .unwrap_or_else(|msg| {
+                error!("{}", msg);
+                self.params.clone()
+            });
+        Self {
+            width,
+            height,
+            params,
+            ..self.clone()
+        }
+        .boxed()
+    }
+
+    fn compute(&self, positions: &[[u32; 2]]) -> Result<RChunk, String> {
+        let mut call = self.module.call(&self.limits)?;
+        let params = call.write_params(&self.params)?;
+        let position_bytes = positions
+            .iter()
+            .flat_map(|[x, y]| x.to_le_bytes().into_iter().chain(y.to_le_bytes()))
+            .collect::<Vec<_>>();
+        let positions_ptr = call.write(&position_bytes)?;
+        let cell_size = self.module.cell_size;
+        let out_len = positions
+            .len()
+            .checked_mul(cell_size)
+            .ok_or("too many cells for a wasm plugin")?;
+        let out = call.write(&vec![0; out_len])?;
+        let code = call.invoke(
+            "compute_cells",
+            (
+                params,
+                self.width as i32,
+                self.height as i32,
+                positions_ptr,
+                positions.len() as i32,
+                out,
+            ),
+        )?;
+        check_code("compute_cells", code)?;
+        let data = call.read(out, out_len)?;
+        let mut chunk = RChunk::default();
+        for (pos, cell) in positions.iter().zip(data.chunks_exact(cell_size)) {
+            chunk.push(*pos, cell);
+        }
+        Ok(chunk)
+    }
+}
+
+impl RFractalFunc for WasmFractalFunc {
+    fn get_size(&self) -> Tuple2<u32, u32> {
+        Tuple2(self.width, self.height)
+    }
+
+    fn compute_cells(&self, positions: RSlice<[u32; 2]>) -> RResult<RChunk, RString> {
+        plugin_result(self.compute(&positions))
+    }
+
+    fn with_size(&self, width: u32, height: u32) -> RFractalFuncBox {
+        let (old_width, old_height) = (self.width as i32, self.height as i32);
+        self.transform(
+            "with_size",
+            |params| (params, old_width, old_height, width as i32, height as i32),
+            width,
+            height,
+        )
+    }
+
+    fn with_offset(&self, dx: i32, dy: i32) -> RFractalFuncBox {
+        let (width, height) = (self.width as i32, self.height as i32);
+        self.transform(
+            "with_offset",
+            |params| (params, width, height, dx, dy),
+            self.width,
+            self.height,
+        )
+    }
+
+    fn add_zoom(&self, zoom_factor: f64) -> RFractalFuncBox {
+        let (width, height) = (self.width as i32, self.height as i32);
+        self.transform(
+            "add_zoom",
+            |params| (params, width, height, F64::from(zoom_factor)),
+            self.width,
+            self.height,
+        )
+    }
+
+    fn with_option(&self, name: RStr, value: RStr) -> RResult<RFractalFuncBox, RString> {
+        if let Some(limits) = self.limits.with_option(name.as_str(), value.as_str()) {
+            return plugin_result(limits.map(|limits| {
+                Self {
+                    limits,
+                    ..self.clone()
+                }
+                .boxed()
+            }));
+        }
+        plugin_result(self.module.param_index(name.as_str(), value.as_str()).map(
+            |(index, value)| {
+                let mut func = self.clone();
+                func.params[index] = value;
+                func.boxed()
+            },
+        ))
+    }
+
+    fn get_options(&self) -> ROptionsMap {
+        self.module.options(&self.params, &self.limits)
+    }
+
+    fn can_resample(&self) -> bool {
+        ["with_size", "add_zoom"]
+            .into_iter()
+            .all(|name| exports_func(&self.module.module, name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WasmColorFunc {
+    module: Arc<WasmModule>,
+    params: Vec<f64>,
+    limits: WasmLimits,
+}
+
+impl WasmColorFunc {
+    pub fn new_default(module: Arc<WasmModule>) -> RColorFuncBox {
+        let limits = WasmLimits::default();
+        let params = module
+            .call(&limits)
+            .and_then(|mut call| {
+                let params = call.alloc_params()?;
+                call.invoke::<_, ()>("default_params", params)?;
+                call.read_params(params)
+            })
+            .unwrap_or_else(|msg| {
+                error!(
+                    "failed to get the default params of {}: {}",
+                    module.name, msg
+                );
+                vec![0.0; module.param_names.len()]
+            });
+        Self {
+            module,
+            params,
+            limits,
+        }
+        .boxed()
+    }
+
+    fn boxed(self) -> RColorFuncBox {
+        RColorFuncBox::from_value(self, TD_Opaque)
+    }
+
+    fn compute(&self, chunk: &RChunk) -> Result<RVec<RColor>, String> {
+        let offsets = chunk
+            .pos_indexes
+            .iter()
+            .map(|Tuple2(_, index)| *index as u32)
+            .chain([chunk.data.len() as u32])
+            .flat_map(u32::to_le_bytes)
+            .collect::<Vec<_>>();
+        let mut call = self.module.call(&self.limits)?;
+        let params = call.write_params(&self.params)?;
+        let data = call.write(&chunk.data)?;
+        let offsets = call.write(&offsets)?;
+        let out = call.write(&vec![0; chunk.len() * 4])?;
+        let code = call.invoke(
+            "compute_colors",
+            (params, data, offsets, chunk.len() as i32, out),
+        )?;
+        check_code("compute_colors", code)?;
+        let colors = call.read(out, chunk.len() * 4)?;
+        Ok(chunk
+            .positions()
+            .zip(colors.chunks_exact(4))
+            .map(|(pos, rgba)| RColor {
+                pos,
+                value: RColorValue::Rgba8(rgba.try_into().unwrap()),
+            })
+            .collect())
+    }
+}
+
+impl RColorFunc for WasmColorFunc {
+    fn compute_colors(&self, chunk: &RChunk) -> RResult<RVec<RColor>, RString> {
+        plugin_result(self.compute(chunk))
+    }
+
+    fn with_option(&self, name: RStr, value: RStr) -> RResult<RColorFuncBox, RString> {
+        if let Some(limits) = self.limits.with_option(name.as_str(), value.as_str()) {
+            return plugin_result(limits.map(|limits| {
+                Self {
+                    limits,
+                    ..self.clone()
+                }
+                .boxed()
+            }));
+        }
+        plugin_result(self.module.param_index(name.as_str(), value.as_str()).map(
+            |(index, value)| {
+                let mut func = self.clone();
+                func.params[index] = value;
+                func.boxed()
+            },
+        ))
+    }
+
+    fn get_options(&self) -> ROptionsMap {
+        self.module.options(&self.params, &self.limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rmpv::Value;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../impls/wasm")
+            .join(name)
+    }
+
+    fn load(name: &str, kind: PluginKind) -> anyhow::Result<Arc<WasmModule>> {
+        WasmModule::load(&fixture(name), kind).map(Arc::new)
+    }
+
+    /// a fractal func with the given `cell_size` and `param_name`, whose `compute_cells` never
+    /// returns
+    fn load_endless(cell_size: i32, param_name: i64) -> anyhow::Result<Arc<WasmModule>> {
+        let path = std::env::temp_dir().join(format!(
+            "wasm-plugin-{}-{}-{}.wat",
+            std::process::id(),
+            cell_size,
+            param_name
+        ));
+        let wat = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "param_count") (result i32) (i32.const 1))
+                (func (export "param_name") (param i32) (result i64) (i64.const {param_name}))
+                (func (export "default_params") (param i32 i32 i32))
+                (func (export "cell_size") (result i32) (i32.const {cell_size}))
+                (func (export "compute_cells") (param i32 i32 i32 i32 i32 i32) (result i32)
+                    (loop $forever (br $forever))
+                    (i32.const 0)))"#
+        );
+        std::fs::write(&path, wat).unwrap();
+        let module = WasmModule::load(&path, PluginKind::Fractal).map(Arc::new);
+        std::fs::remove_file(&path).ok();
+        module
+    }
+
+    fn compute_cells(func: &RFractalFuncBox, positions: &[[u32; 2]]) -> Result<RChunk, RString> {
+        func.compute_cells(RSlice::from_slice(positions))
+            .into_result()
+    }
+
+    fn with_option(func: &RFractalFuncBox, name: &str, value: &str) -> RFractalFuncBox {
+        func.with_option(RStr::from(name), RStr::from(value))
+            .unwrap()
+    }
+
+    #[test]
+    fn plugins_load_with_their_params() {
+        let fractal = load("mandelbrot.wat", PluginKind::Fractal).unwrap();
+        let color = load("color_gray.wat", PluginKind::Color).unwrap();
+
+        assert_eq!(
+            fractal.param_names,
+            ["center_re", "center_im", "pixel_size", "max_iter"]
+        );
+        assert_eq!(fractal.cell_size, 20);
+        assert_eq!(color.param_names, ["period", "brightness"]);
+        let options = WasmFractalFunc::new_default(fractal, 16, 16).get_options();
+        assert_eq!(
+            options.get("wasm_fuel").map(RString::as_str),
+            Some(WasmLimits::default().fuel.to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn plugins_of_the_wrong_kind_are_refused() {
+        let err = load("mandelbrot.wat", PluginKind::Color).unwrap_err();
+        assert!(err.to_string().contains("is not a color plugin"), "{}", err);
+        assert!(load("color_gray.wat", PluginKind::Fractal).is_err());
+    }
+
+    #[test]
+    fn bad_cell_sizes_and_strings_are_refused() {
+        for cell_size in [0, -1, MAX_CELL_SIZE + 1] {
+            let err = load_endless(cell_size, 0).unwrap_err();
+            assert!(err.to_string().contains("cell_size"), "{}", err);
+        }
+        assert!(load_endless(8, 0).is_ok());
+        let err = load_endless(8, i32::MAX as i64).unwrap_err();
+        assert!(err.to_string().contains("out of bounds"), "{}", err);
+    }
+
+    #[test]
+    fn cells_and_colors_round_trip() {
+        let fractal = load("mandelbrot.wat", PluginKind::Fractal).unwrap();
+        let color = load("color_gray.wat", PluginKind::Color).unwrap();
+        let fractal_func = WasmFractalFunc::new_default(fractal, 16, 16);
+        let color_func = WasmColorFunc::new_default(color);
+
+        let positions = [[0, 0], [8, 8], [15, 3]];
+        let chunk = compute_cells(&fractal_func, &positions).unwrap();
+        assert_eq!(chunk.positions().collect::<Vec<_>>(), positions);
+        let outside = chunk
+            .iter()
+            .map(|(_, mut data)| {
+                assert_eq!(data.len(), 20);
+                let cell = rmpv::decode::read_value(&mut data).unwrap();
+                let fields = cell.as_map().unwrap();
+                assert_eq!(fields[1].0, Value::from("iter"));
+                fields[0].1.as_bool().unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(outside, [true, false, true]);
+
+        let colors = color_func.compute_colors(&chunk).unwrap();
+        assert_eq!(colors.len(), positions.len());
+        assert_eq!(colors[1].pos, [8, 8]);
+        assert_eq!(colors[1].value, RColorValue::Rgba8([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn fuel_limit_stops_an_endless_loop() {
+        let func = WasmFractalFunc::new_default(load_endless(8, 0).unwrap(), 16, 16);
+        let func = with_option(&func, "wasm_fuel", "100000");
+
+        let err = compute_cells(&func, &[[0, 0]]).unwrap_err();
+        assert!(err.contains("compute_cells failed"), "{}", err);
+    }
+
+    #[test]
+    fn memory_limit_refuses_growth() {
+        let fractal = load("mandelbrot.wat", PluginKind::Fractal).unwrap();
+        let func = WasmFractalFunc::new_default(fractal, 1024, 1024);
+        let func = with_option(
+            &with_option(&func, "max_iter", "1"),
+            "wasm_max_memory_mb",
+            "1",
+        );
+        // 28 bytes per cell for the position and the cell, 2.8 MB in all
+        let positions = (0..100_000)
+            .map(|i| [i % 1024, i / 1024])
+            .collect::<Vec<_>>();
+
+        assert!(compute_cells(&func, &positions[..1000]).is_ok());
+        assert!(compute_cells(&func, &positions).is_err());
+    }
+}
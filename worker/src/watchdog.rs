@@ -0,0 +1,207 @@
+//! notices chunks that take longer than their time budget, eg. because a plugin loops forever.
+//! a chunk that is still running over budget is flagged right away. a thread stuck in this
+//! process can't be stopped, but a stuck plugin process is killed, which fails its requests.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::plugin_process::PluginProcess;
+use crate::wire::WireOptions;
+
+pub const DEFAULT_CHUNK_BUDGET: Duration = Duration::from_secs(10);
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// the plugins and options a render was started with, to tell which ones were slow
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderPlugins {
+    pub fractal_lib: PathBuf,
+    pub fractal_options: WireOptions,
+    pub color_lib: PathBuf,
+    pub color_options: WireOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowChunk {
+    /// the first cell of the chunk
+    pub pos: [u32; 2],
+    pub cells: usize,
+    /// so far, if it is still running
+    pub elapsed: Duration,
+    /// it was rendered, or failed
+    pub finished: bool,
+    /// the plugin process was killed to get the chunk unstuck
+    pub killed_process: bool,
+    pub plugins: Arc<RenderPlugins>,
+}
+
+/// watches the chunks of one render
+#[derive(Debug, Clone)]
+pub struct ChunkWatchdog(Arc<WatchdogShared>);
+
+#[derive(Debug)]
+struct WatchdogShared {
+    budget: Duration,
+    plugins: Arc<RenderPlugins>,
+    /// killed when a chunk gets stuck in it
+    plugin_process: Option<PluginProcess>,
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, RunningChunk>>,
+    slow_chunks: Arc<Mutex<Vec<SlowChunk>>>,
+}
+
+#[derive(Debug)]
+struct RunningChunk {
+    started: Instant,
+    pos: [u32; 2],
+    cells: usize,
+    /// the index in `slow_chunks`, once it was flagged
+    flagged: Option<usize>,
+}
+
+impl ChunkWatchdog {
+    /// a budget of zero disables the watchdog. the slow chunks are reported to `slow_chunks`.
+    pub fn start(
+        budget: Duration,
+        plugins: RenderPlugins,
+        plugin_process: Option<PluginProcess>,
+        slow_chunks: Arc<Mutex<Vec<SlowChunk>>>,
+    ) -> Self {
+        let watchdog = Self(Arc::new(WatchdogShared {
+            budget,
+            plugins: Arc::new(plugins),
+            plugin_process,
+            next_id: AtomicU64::new(0),
+            running: Default::default(),
+            slow_chunks,
+        }));
+        if !budget.is_zero() {
+            let shared = Arc::downgrade(&watchdog.0);
+            std::thread::spawn(move || check_running(shared));
+        }
+        watchdog
+    }
+
+    /// keep the guard around while the chunk is rendered
+    pub fn watch(&self, positions: &[[u32; 2]]) -> ChunkGuard {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let chunk = RunningChunk {
+            started: Instant::now(),
+            pos: positions.first().copied().unwrap_or_default(),
+            cells: positions.len(),
+            flagged: None,
+        };
+        self.0.running.lock().unwrap().insert(id, chunk);
+        ChunkGuard {
+            watchdog: self.clone(),
+            id,
+        }
+    }
+}
+
+pub struct ChunkGuard {
+    watchdog: ChunkWatchdog,
+    id: u64,
+}
+
+impl Drop for ChunkGuard {
+    fn drop(&mut self) {
+        let shared = &self.watchdog.0;
+        let chunk = match shared.running.lock().unwrap().remove(&self.id) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let elapsed = chunk.started.elapsed();
+        if shared.budget.is_zero() || elapsed <= shared.budget {
+            return;
+        }
+        let mut slow_chunks = shared.slow_chunks.lock().unwrap();
+        match chunk.flagged {
+            Some(index) => {
+                let slow_chunk = &mut slow_chunks[index];
+                slow_chunk.elapsed = elapsed;
+                slow_chunk.finished = true;
+            }
+            None => {
+                warn!(
+                    "chunk at {:?} took {:.1?}, over the budget of {:.1?}",
+                    chunk.pos, elapsed, shared.budget
+                );
+                slow_chunks.push(shared.slow_chunk(&chunk, elapsed, true, false));
+            }
+        }
+    }
+}
+
+impl WatchdogShared {
+    fn slow_chunk(
+        &self,
+        chunk: &RunningChunk,
+        elapsed: Duration,
+        finished: bool,
+        killed_process: bool,
+    ) -> SlowChunk {
+        SlowChunk {
+            pos: chunk.pos,
+            cells: chunk.cells,
+            elapsed,
+            finished,
+            killed_process,
+            plugins: self.plugins.clone(),
+        }
+    }
+
+    /// flags the chunks that went over budget since the last check
+    fn check(&self) {
+        let mut kill = false;
+        {
+            let mut running = self.running.lock().unwrap();
+            let mut slow_chunks = self.slow_chunks.lock().unwrap();
+            for chunk in running.values_mut() {
+                let elapsed = chunk.started.elapsed();
+                if let Some(index) = chunk.flagged {
+                    slow_chunks[index].elapsed = elapsed;
+                    continue;
+                }
+                if elapsed <= self.budget {
+                    continue;
+                }
+                warn!(
+                    "chunk at {:?} is still running after {:.1?}, over the budget of {:.1?}",
+                    chunk.pos, elapsed, self.budget
+                );
+                let killed_process = self.plugin_process.is_some();
+                chunk.flagged = Some(slow_chunks.len());
+                slow_chunks.push(self.slow_chunk(chunk, elapsed, false, killed_process));
+                kill |= killed_process;
+            }
+        }
+        if kill {
+            if let Some(plugin_process) = &self.plugin_process {
+                plugin_process.kill(&format!(
+                    "the plugin process was killed, a chunk took longer than the budget of {:.1?}",
+                    self.budget
+                ));
+            }
+        }
+    }
+}
+
+/// runs until the render and all its chunks are gone
+fn check_running(shared: Weak<WatchdogShared>) {
+    loop {
+        std::thread::sleep(CHECK_INTERVAL);
+        match shared.upgrade() {
+            Some(shared) => shared.check(),
+            None => return,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! serializable copies of the plugin data types, for sending them to other processes,
+//! and the framing used on the wire
+
+use std::io::{Read, Write};
+
+use abi_stable::std_types::{RVec, Tuple2};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use color_func::{RColor, RColorStats, RColorValue, RTile};
+use fractal_func::prelude::{RChunk, ROptionsMap, RString};
+
+use crate::chunk_sizing::MAX_CHUNK_SIZE;
+
+/// generous for the position, data and color of a cell
+const MAX_BYTES_PER_CELL: u64 = 256;
+/// messages bigger than this are treated as a broken stream. the biggest ones carry a chunk.
+pub const MAX_MESSAGE_LEN: u64 = (MAX_CHUNK_SIZE * MAX_CHUNK_SIZE) as u64 * MAX_BYTES_PER_CELL;
+
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> anyhow::Result<()> {
+    let bytes = bincode::serialize(message)?;
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> anyhow::Result<T> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        anyhow::bail!("message too long ({} bytes)", len);
+    }
+    // grows with what actually arrives, rather than trusting the length up front
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        anyhow::bail!("message truncated ({} of {} bytes)", bytes.len(), len);
+    }
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+pub type WireOptions = Vec<(String, String)>;
+
+/// sorted, so that equal options always serialize the same
+pub fn wire_options(options: &ROptionsMap) -> WireOptions {
+    let mut options: WireOptions = options
+        .iter()
+        .map(|Tuple2(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    options.sort();
+    options
+}
+
+pub fn options_map(options: &[(String, String)]) -> ROptionsMap {
+    options
+        .iter()
+        .map(|(name, value)| (RString::from(name.as_str()), RString::from(value.as_str())))
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WireChunk {
+    pub pos_indexes: Vec<([u32; 2], usize)>,
+    pub data: Vec<u8>,
+}
+
+impl From<&RChunk> for WireChunk {
+    fn from(chunk: &RChunk) -> Self {
+        Self {
+            pos_indexes: chunk
+                .pos_indexes
+                .iter()
+                .map(|Tuple2(pos, index)| (*pos, *index))
+                .collect(),
+            data: chunk.data.to_vec(),
+        }
+    }
+}
+
+/// fails unless the data offsets rise and stay inside the data, which `RChunk::iter` relies on
+impl TryFrom<WireChunk> for RChunk {
+    type Error = String;
+
+    fn try_from(chunk: WireChunk) -> Result<Self, String> {
+        let mut end = chunk.data.len();
+        for &(pos, index) in chunk.pos_indexes.iter().rev() {
+            if index > end {
+                return Err(format!("chunk: bad data offset {} for {:?}", index, pos));
+            }
+            end = index;
+        }
+        Ok(RChunk {
+            pos_indexes: chunk
+                .pos_indexes
+                .into_iter()
+                .map(|(pos, index)| Tuple2(pos, index))
+                .collect(),
+            data: RVec::from(chunk.data),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WireColorValue {
+    Rgb8([u8; 3]),
+    Rgba8([u8; 4]),
+    Rgba16([u16; 4]),
+    LinearF32([f32; 4]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WireColor {
+    pub pos: [u32; 2],
+    pub value: WireColorValue,
+}
+
+impl From<&RColor> for WireColor {
+    fn from(color: &RColor) -> Self {
+        let value = match color.value {
+            RColorValue::Rgb8(v) => WireColorValue::Rgb8(v),
+            RColorValue::Rgba8(v) => WireColorValue::Rgba8(v),
+            RColorValue::Rgba16(v) => WireColorValue::Rgba16(v),
+            RColorValue::LinearF32(v) => WireColorValue::LinearF32(v),
+        };
+        Self {
+            pos: color.pos,
+            value,
+        }
+    }
+}
+
+impl From<WireColor> for RColor {
+    fn from(color: WireColor) -> Self {
+        let value = match color.value {
+            WireColorValue::Rgb8(v) => RColorValue::Rgb8(v),
+            WireColorValue::Rgba8(v) => RColorValue::Rgba8(v),
+            WireColorValue::Rgba16(v) => RColorValue::Rgba16(v),
+            WireColorValue::LinearF32(v) => RColorValue::LinearF32(v),
+        };
+        RColor {
+            pos: color.pos,
+            value,
+        }
+    }
+}
+
+pub fn wire_colors(colors: &[RColor]) -> Vec<WireColor> {
+    colors.iter().map(WireColor::from).collect()
+}
+
+pub fn colors_from_wire(colors: Vec<WireColor>) -> RVec<RColor> {
+    colors.into_iter().map(RColor::from).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireColorStats {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub histogram: Vec<u64>,
+    pub quantiles: Vec<(f64, f64)>,
+}
+
+impl From<&RColorStats> for WireColorStats {
+    fn from(stats: &RColorStats) -> Self {
+        Self {
+            count: stats.count,
+            min: stats.min,
+            max: stats.max,
+            histogram: stats.histogram.to_vec(),
+            quantiles: stats
+                .quantiles
+                .iter()
+                .map(|Tuple2(q, v)| (*q, *v))
+                .collect(),
+        }
+    }
+}
+
+impl From<&WireColorStats> for RColorStats {
+    fn from(stats: &WireColorStats) -> Self {
+        RColorStats {
+            count: stats.count,
+            min: stats.min,
+            max: stats.max,
+            histogram: RVec::from(stats.histogram.clone()),
+            quantiles: stats
+                .quantiles
+                .iter()
+                .map(|(q, v)| Tuple2(*q, *v))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireTile {
+    pub chunk: WireChunk,
+    pub center_count: usize,
+    pub radius: u32,
+    pub spacing: u32,
+}
+
+impl From<&RTile> for WireTile {
+    fn from(tile: &RTile) -> Self {
+        Self {
+            chunk: WireChunk::from(&tile.chunk),
+            center_count: tile.center_count,
+            radius: tile.radius,
+            spacing: tile.spacing,
+        }
+    }
+}
+
+impl TryFrom<WireTile> for RTile {
+    type Error = String;
+
+    fn try_from(tile: WireTile) -> Result<Self, String> {
+        let chunk = RChunk::try_from(tile.chunk)?;
+        if tile.center_count > chunk.len() {
+            return Err(format!(
+                "tile: {} center cells, but only {} cells",
+                tile.center_count,
+                chunk.len()
+            ));
+        }
+        Ok(RTile {
+            chunk,
+            center_count: tile.center_count,
+            radius: tile.radius,
+            spacing: tile.spacing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let chunk = WireChunk {
+            pos_indexes: vec![([1, 2], 0), ([3, 4], 5)],
+            data: vec![1, 2, 3, 4, 5, 6, 7],
+        };
+        let mut bytes = vec![];
+        write_message(&mut bytes, &(7u64, chunk.clone())).unwrap();
+        write_message(&mut bytes, &"second".to_owned()).unwrap();
+
+        let mut reader = Cursor::new(bytes);
+        let (id, read): (u64, WireChunk) = read_message(&mut reader).unwrap();
+        assert_eq!((id, read), (7, chunk));
+        assert_eq!(read_message::<String>(&mut reader).unwrap(), "second");
+        assert!(read_message::<String>(&mut reader).is_err());
+    }
+
+    #[test]
+    fn oversized_message_is_refused() {
+        let mut bytes = (MAX_MESSAGE_LEN + 1).to_le_bytes().to_vec();
+        bytes.extend([0; 16]);
+        let err = read_message::<Vec<u8>>(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(err.to_string().contains("too long"));
+    }
+
+    #[test]
+    fn truncated_message_is_refused() {
+        let mut bytes = vec![];
+        write_message(&mut bytes, &vec![0u8; 100]).unwrap();
+        bytes.truncate(50);
+        let err = read_message::<Vec<u8>>(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+    }
+
+    #[test]
+    fn chunks_with_bad_offsets_are_refused() {
+        let chunk = |pos_indexes: Vec<([u32; 2], usize)>| {
+            RChunk::try_from(WireChunk {
+                pos_indexes,
+                data: vec![0; 4],
+            })
+        };
+
+        assert!(chunk(vec![([0, 0], 0), ([1, 0], 2), ([2, 0], 4)]).is_ok());
+        assert!(chunk(vec![([0, 0], 0), ([1, 0], 5)]).is_err());
+        assert!(chunk(vec![([0, 0], 3), ([1, 0], 1)]).is_err());
+        let tile = WireTile {
+            chunk: WireChunk {
+                pos_indexes: vec![([0, 0], 0)],
+                data: vec![],
+            },
+            center_count: 2,
+            radius: 1,
+            spacing: 1,
+        };
+        assert!(RTile::try_from(tile).is_err());
+    }
+}
@@ -0,0 +1,31 @@
+use std::time::{Duration, Instant};
+
+use worker::fractal_worker2::{FractalWorker, WorkerState};
+
+pub const FRACTAL_LIB: &str = "builtin:mandelbrot_f64";
+pub const COLOR_LIB: &str = "builtin:color_luma_basic";
+
+/// draws until the render and everything after it (recolors, refinement) is done
+pub fn render(worker: &mut FractalWorker, width: u32, height: u32) -> Vec<u8> {
+    let mut screen = vec![0; (width * height * 4) as usize];
+    draw_until_idle(worker, width, height, &mut screen);
+    screen
+}
+
+/// like `render`, onto what is already on `screen`
+pub fn draw_until_idle(worker: &mut FractalWorker, width: u32, height: u32, screen: &mut [u8]) {
+    let started = Instant::now();
+    let mut idle_frames = 0;
+    while idle_frames < 20 {
+        worker.draw_new_chunks(width, height, screen);
+        idle_frames = match worker.get_state() {
+            WorkerState::Finished | WorkerState::Error(_) => idle_frames + 1,
+            _ => 0,
+        };
+        assert!(
+            started.elapsed() < Duration::from_secs(120),
+            "render timed out"
+        );
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use std::time::Duration;
+
+use abi_stable::std_types::{RHashMap, RString};
+use worker::chunk_order::ChunkOrder;
+use worker::fractal_worker2::{FractalWorker, RenderSettings, WorkerState};
+use worker::render_pool::RenderPriority;
+
+use common::{draw_until_idle, render, COLOR_LIB, FRACTAL_LIB};
+
+fn options(options: &[(&str, &str)]) -> RHashMap<RString, RString> {
+    options
+        .iter()
+        .map(|&(name, value)| (RString::from(name), RString::from(value)))
+        .collect()
+}
+
+#[test]
+fn failed_option_is_an_error_state() {
+    let mut worker = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    worker.set_fractal_options(options(&[("max_iter", "many")]));
+    assert!(matches!(worker.get_state(), WorkerState::Error(msg) if msg.contains("max_iter")));
+
+    worker.set_color_options(options(&[("no_such_option", "1")]));
+    assert!(
+        matches!(worker.get_state(), WorkerState::Error(msg) if msg.contains("no_such_option"))
+    );
+}
+
+#[test]
+fn renders_to_completion() {
+    let mut worker = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    let screen = render(&mut worker, 64, 64);
+    assert_eq!(worker.get_state(), WorkerState::Finished);
+    assert!(screen.chunks(4).all(|pixel| pixel[3] == 0xff));
+}
+
+#[test]
+fn statistics_are_applied_to_the_render() {
+    let mut sine = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    let mut histogram = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    sine.set_refine_edges(false);
+    histogram.set_refine_edges(false);
+    histogram.set_color_options(options(&[("mode", "histogram")]));
+
+    // without statistics, the histogram mode colors like the sine mode
+    assert_ne!(render(&mut histogram, 64, 64), render(&mut sine, 64, 64));
+    assert_eq!(histogram.get_state(), WorkerState::Finished);
+}
+
+#[test]
+fn paused_renders_are_not_recolored() {
+    let mut worker = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    worker.set_refine_edges(false);
+    let mut screen = vec![0; 64 * 64 * 4];
+    draw_until_idle(&mut worker, 64, 64, &mut screen);
+    let rendered = screen.clone();
+
+    worker.set_paused(true);
+    worker.set_color_options(options(&[("emboss", "2")]));
+    for _ in 0..20 {
+        worker.draw_new_chunks(64, 64, &mut screen);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(screen, rendered);
+
+    worker.set_paused(false);
+    draw_until_idle(&mut worker, 64, 64, &mut screen);
+    assert_ne!(screen, rendered);
+}
+
+#[test]
+fn configure_applies_every_setting() {
+    let mut worker = FractalWorker::new(64, 64, FRACTAL_LIB, COLOR_LIB).unwrap();
+    worker.configure(RenderSettings {
+        remote_workers: vec![],
+        chunk_budget: Duration::from_secs(3),
+        chunk_size: 1000,
+        adaptive_chunk_size: true,
+        progressive: true,
+        chunk_order: ChunkOrder::Random,
+        chunk_order_seed: 7,
+        boundary_fill: true,
+        refine_edges: false,
+        render_threads: 2,
+        render_priority: RenderPriority::Normal,
+        background_mode: true,
+    });
+    assert_eq!(worker.get_chunk_budget(), Duration::from_secs(3));
+    assert_eq!(worker.get_chunk_size(), (128, true));
+    assert!(worker.is_progressive());
+    assert_eq!(worker.get_chunk_order(), (ChunkOrder::Random, 7));
+    assert!(worker.is_boundary_fill());
+    assert!(!worker.is_refining_edges());
+    assert_eq!(worker.get_render_threads(), (2, RenderPriority::Normal));
+    assert_eq!(worker.current_render_threads(), 2);
+    assert!(worker.is_background_mode());
+
+    // losing the focus lowers the priority, the threads stay
+    worker.set_focused(false);
+    assert_eq!(worker.current_render_threads(), 2);
+    render(&mut worker, 64, 64);
+    assert_eq!(worker.get_state(), WorkerState::Finished);
+}
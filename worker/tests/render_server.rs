@@ -0,0 +1,33 @@
+mod common;
+
+use worker::fractal_worker2::{FractalWorker, WorkerState};
+use worker::render_server::RenderServer;
+
+use common::{render, COLOR_LIB, FRACTAL_LIB};
+
+const SIZE: u32 = 128;
+
+#[test]
+fn remote_chunks_match_a_local_render() {
+    let plugin_dir = std::env::temp_dir().join(format!("render-server-{}", std::process::id()));
+    std::fs::create_dir_all(&plugin_dir).unwrap();
+    let server = RenderServer::bind("127.0.0.1:0", plugin_dir.clone()).unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+    server.spawn();
+
+    let mut local = FractalWorker::new(SIZE, SIZE, FRACTAL_LIB, COLOR_LIB).unwrap();
+    let expected = render(&mut local, SIZE, SIZE);
+
+    let mut remote = FractalWorker::new(SIZE, SIZE, FRACTAL_LIB, COLOR_LIB).unwrap();
+    // small chunks, so that the server gets some of them
+    remote.set_chunk_size(8, false);
+    remote.set_remote_workers(vec![addr]);
+    let rendered = render(&mut remote, SIZE, SIZE);
+    std::fs::remove_dir_all(&plugin_dir).ok();
+
+    assert_eq!(remote.get_state(), WorkerState::Finished);
+    let stats = remote.get_remote_stats();
+    assert_eq!(stats[0].error, None);
+    assert!(stats[0].chunks > 0);
+    assert!(rendered == expected, "the remote render differs");
+}